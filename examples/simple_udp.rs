@@ -57,11 +57,15 @@ pub fn main() {
         .unwrap(),
     ));
 
-    // Send the queued send operations
-    client.manual_poll(Instant::now());
+    // The packets are queued until the connection handshake has completed, which takes a few
+    // round trips between the client and the server.
+    for _ in 0..3 {
+        // Send the queued send operations
+        client.manual_poll(Instant::now());
 
-    // Check for any new packets
-    server.manual_poll(Instant::now());
+        // Check for any new packets
+        server.manual_poll(Instant::now());
+    }
 
     // ==== results ====
     // Coords { longitude: 10.555, latitude: 10.55454, altitude: 1.3 }
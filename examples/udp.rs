@@ -27,7 +27,8 @@ pub fn send_data() -> Result<()> {
     let packet = construct_packet();
 
     // next send or packet to the endpoint we earlier putted into the packet.
-    socket.send(packet)
+    socket.send(packet)?;
+    Ok(())
 }
 
 /// This is an example of how to receive data over udp.
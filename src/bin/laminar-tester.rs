@@ -134,7 +134,7 @@ fn run_server(server_config: ServerConfiguration) -> Result<()> {
                     println!["Got a packet"];
                     throughput.tick();
                 }
-                SocketEvent::Connect(address) => {
+                SocketEvent::Connect(address, _) => {
                     socket.send(Packet::unreliable(address, vec![0])).unwrap();
                 }
                 _ => error!("Event not handled yet."),
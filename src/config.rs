@@ -1,3 +1,5 @@
+use crate::error::{ConfigErrorKind, Result};
+use crate::infrastructure::CongestionControl;
 use crate::net::constants::{DEFAULT_MTU, FRAGMENT_SIZE_DEFAULT, MAX_FRAGMENTS_DEFAULT};
 use std::{default::Default, time::Duration};
 
@@ -11,6 +13,47 @@ pub struct Config {
     /// Value which specifies at which interval (if at all) a heartbeat should be sent, if no other packet was sent in the meantime.
     /// If None, no heartbeats will be sent (the default).
     pub heartbeat_interval: Option<Duration>,
+    /// Value which specifies at which interval handshake packets are resent while a connection is being established.
+    pub handshake_interval: Duration,
+    /// Value which specifies how many times the disconnect packet is sent when disconnecting, so the remote endpoint learns of the disconnect even if some of them are lost.
+    /// Defaults to 10.
+    pub disconnect_redundancy: usize,
+    /// Value which specifies the maximal number of connections that will be accepted.
+    /// If None, all connection requests are accepted (the default).
+    pub max_connections: Option<usize>,
+    /// Value which specifies the pre-shared key from which the encryption keys of each connection are derived during the handshake.
+    /// Both endpoints have to use the same key. If None, packets are not encrypted (the default).
+    pub encryption_key: Option<[u8; 32]>,
+    /// Value which specifies the private key with which the backend generates connect tokens.
+    /// If Some, only clients presenting a valid connect token for this server are accepted and their connection is encrypted with the keys from the token.
+    /// If None, all clients are accepted (the default).
+    pub connect_token_key: Option<[u8; 32]>,
+    /// Value which specifies how many times a reliable packet may be resent before laminar gives up on it.
+    /// If None, packets are resent until they are acknowledged or the connection times out (the default).
+    pub max_packet_resends: Option<u16>,
+    /// Value which specifies how many of the previously received packets every acknowledgment covers.
+    /// Larger windows avoid needless resends when many packets are in flight, at the cost of a larger acknowledgment header.
+    /// Supported sizes are 32, 64 and 128; other values are rounded up to the next supported size, or down to 128.
+    /// Both endpoints propose their window during the handshake and the connection uses the smaller one. Defaults to 32.
+    pub ack_window_size: u16,
+    /// Value which specifies whether the `Acked` and `Lost` events are emitted for the packets that are sent.
+    /// Unreliable packets are considered lost once the remote endpoint acknowledged packets sent more than an acknowledgment window after them, but not them.
+    /// Defaults to false.
+    pub acknowledgment_events: bool,
+    /// Value which specifies how many bytes of reliable payload a connection may have unacknowledged at once, including the reliable packets still waiting to be sent.
+    /// Sending a reliable packet over this limit fails with `BackpressureErrorKind::InFlightLimitReached` until enough packets are acknowledged, expired or lost.
+    /// Packets sent through a `PacketSender` are rejected by the polling loop instead, which emits a `Lost` event for them.
+    /// If None, the amount of reliable data in flight is not limited (the default).
+    pub max_in_flight_bytes: Option<usize>,
+    /// Value which specifies how many packets the application can hand to the socket before they are picked up by the polling loop.
+    /// If None, the queue is unbounded (the default).
+    pub packet_queue_size: Option<usize>,
+    /// Value which specifies how many events the socket can queue up before the application receives them.
+    /// When the queue is full, new events are discarded. If None, the queue is unbounded (the default).
+    pub event_queue_size: Option<usize>,
+    /// Value which specifies whether sending through a `PacketSender` waits for room in a full packet queue instead of failing with `BackpressureErrorKind::PacketQueueFull`.
+    /// `Socket::send` always fails instead, because only the polling of the socket makes room. `AsyncSocket::send` waits without blocking its thread. Defaults to false.
+    pub block_on_full_packet_queue: bool,
     /// Value which can specify the maximum size a packet can be in bytes. This value is inclusive of fragmenting; if a packet is fragmented, the total size of the fragments cannot exceed this value.
     ///
     /// Recommended value: 16384
@@ -19,7 +62,7 @@ pub struct Config {
     ///
     /// Why can't I have more than 255 (u8)?
     /// This is because you don't want to send more then 256 fragments over UDP, with high amounts of fragments the chance for an invalid packet is very high.
-    /// Use TCP instead (later we will probably support larger ranges but every fragment packet then needs to be resent if it doesn't get an acknowledgment).
+    /// Send larger amounts of data with `Packet::large_message` instead, which acknowledges and resends every chunk on its own.
     ///
     /// default: 16 but keep in mind that lower is better.
     pub max_fragments: u8,
@@ -27,44 +70,125 @@ pub struct Config {
     ///
     /// This is the maximum size of each fragment. It defaults to `1450` bytes, due to the default MTU on most network devices being `1500`.
     pub fragment_size: u16,
+    /// Value which can specify the maximum size of a large message in bytes, both for sending and for receiving.
+    ///
+    /// Large messages are streamed in chunks of `fragment_size` bytes. A partially received message only holds the chunks which arrived, so each connection holds at most this many bytes of it. Defaults to 16 MiB.
+    pub max_large_message_size: usize,
+    /// Value which can specify how many chunks of a large message may be in flight at once, counted from the first chunk that was not acknowledged. Defaults to 64.
+    pub large_message_window: u32,
+    /// Value which can specify how long a partially received large message is kept when none of its chunks arrive.
+    ///
+    /// A message of which no chunk arrived within this time is discarded. Defaults to 10 seconds.
+    pub large_message_reassembly_timeout: Duration,
     /// Value which can specify the size of the buffer that queues up fragments ready to be reassembled once all fragments have arrived.```
     pub fragment_reassembly_buffer_size: u16,
+    /// Value which can specify how long the fragments of a packet are kept while waiting for its remaining fragments.
+    ///
+    /// A packet of which not all fragments arrived within this time is discarded. Defaults to 1 second.
+    pub fragment_reassembly_timeout: Duration,
+    /// Value which can specify how many bytes of fragments each connection holds at most while waiting for the remaining fragments of their packets.
+    ///
+    /// When a fragment would exceed this limit, the oldest partially received packets are discarded. Defaults to 1 MiB.
+    pub max_fragment_reassembly_bytes: usize,
     /// Value that specifies the size of the buffer the UDP data will be read into. Defaults to `1450` bytes.
     pub receive_buffer_max_size: usize,
     /// Value which can specify the factor which will smooth out network jitter.
     ///
-    /// use-case: If one packet hast not arrived we don't directly want to transform to a bad network state.
-    /// Value that specifies the factor used to smooth out network jitter. It defaults to 10% of the round-trip time. It is expressed as a ratio, with 0 equal to 0% and 1 equal to 100%. This helps prevent flapping of `VirtualConnections`
+    /// It is the weight with which each measured round-trip time is blended into the smoothed round-trip time, so a single slow packet does not make the connection look congested.
+    /// It is expressed as a ratio, with 0 equal to 0% and 1 equal to 100%. Defaults to 10%.
     pub rtt_smoothing_factor: f32,
-    /// Value which can specify the maximal round trip time (rtt) for packet.
+    /// Value which can specify the congestion controller of each connection, which paces the packets it sends.
+    ///
+    /// Defaults to `CongestionControl::None`, which sends packets at the rate the application sends them.
+    pub congestion_control: CongestionControl,
+    /// Value which can specify how many bytes per second each connection sends at most.
+    ///
+    /// Packets which would exceed it are held back and sent by later polls. A socket cannot be built if it is zero. If None, the send rate is not limited (the default).
+    pub max_send_rate: Option<u32>,
+    /// Value which can specify how many bytes a connection may send at once when it did not send for a while, if `max_send_rate` is set. It must not be zero then. Defaults to 16 KiB.
+    pub send_burst_size: usize,
+    /// Value which can specify how many packets each connection holds back at most because they exceed its send rate.
+    ///
+    /// Sending a packet to a connection which holds back this many packets fails with `BackpressureErrorKind::PacingQueueFull`.
+    /// Packets sent through a `PacketSender` are rejected by the polling loop instead, which emits a `Lost` event for them. Defaults to 1024.
+    pub max_paced_packets: usize,
+    /// Value which can specify the maximal round trip time (rtt) before we consider it a problem. This is expressed in milliseconds.
     ///
-    /// Value which specifies the maximum round trip time before we consider it a problem. This is expressed in milliseconds.
+    /// It is used in three places:
+    /// - The retransmission timeout is never shorter than it, so unacknowledged packets are not considered lost before this time.
+    /// - `CongestionControl::Binary` switches to its bad send rate when the smoothed round trip time exceeds it.
+    /// - The quality of a connection whose smoothed round trip time exceeds it is `Bad`.
+    ///
+    /// Defaults to 250 milliseconds.
     pub rtt_max_value: u16,
-    /// Value which can specify the event buffer we read socket events into.
+    /// Value which can specify the share of lost packets above which the quality of a connection is `Bad`.
+    ///
+    /// The share is smoothed over the recently acknowledged and lost packets. It is expressed as a ratio, with 0 equal to 0% and 1 equal to 100%. Defaults to 10%.
+    pub packet_loss_threshold: f32,
+    /// Value which can specify how far the round trip time and packet loss must fall below their thresholds before a `Bad` connection is `Good` again.
     ///
-    /// Value that specifies the size of the event buffer into which we receive socket events, in bytes. Defaults to 1024.
+    /// This prevents a connection at the edge of a threshold from flapping between the qualities. It is expressed as a ratio of the thresholds, with 0 equal to 0% and 1 equal to 100%. Defaults to 20%.
+    pub quality_hysteresis: f32,
+    /// Value which can specify the number of readiness events which are received at once while the polling loop waits for the socket. Defaults to 1024.
     pub socket_event_buffer_size: usize,
-    /// Value which can specify how long we should block polling for socket events.
+    /// Value which can specify how long the polling loop blocks at most while waiting for socket events.
     ///
-    /// Value that specifies how long we should block polling for socket events, in milliseconds. Defaults to `1ms`.
+    /// The loop always wakes up when a packet arrives, a packet is sent or a protocol timer expires.
+    /// On other targets than unix, a UDP socket is checked once per millisecond instead of waking the loop when a packet arrives.
+    /// If None, it only wakes up for those (the default).
     pub socket_polling_timeout: Option<Duration>,
 }
 
+impl Config {
+    // Returns an error for settings with which connections could not work, so a socket is never
+    // built with them.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match self.max_send_rate {
+            Some(0) => Err(ConfigErrorKind::ZeroSendRate.into()),
+            Some(_) if self.send_burst_size == 0 => Err(ConfigErrorKind::ZeroSendBurstSize.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             blocking_mode: false,
             idle_connection_timeout: Duration::from_secs(5),
             heartbeat_interval: None,
+            handshake_interval: Duration::from_millis(100),
+            disconnect_redundancy: 10,
+            max_connections: None,
+            encryption_key: None,
+            connect_token_key: None,
+            max_packet_resends: None,
+            ack_window_size: 32,
+            acknowledgment_events: false,
+            max_in_flight_bytes: None,
+            packet_queue_size: None,
+            event_queue_size: None,
+            block_on_full_packet_queue: false,
             max_packet_size: (MAX_FRAGMENTS_DEFAULT * FRAGMENT_SIZE_DEFAULT) as usize,
             max_fragments: MAX_FRAGMENTS_DEFAULT as u8,
             fragment_size: FRAGMENT_SIZE_DEFAULT,
+            max_large_message_size: 16 * 1024 * 1024,
+            large_message_window: 64,
+            large_message_reassembly_timeout: Duration::from_secs(10),
             fragment_reassembly_buffer_size: 64,
+            fragment_reassembly_timeout: Duration::from_secs(1),
+            max_fragment_reassembly_bytes: 1024 * 1024,
             receive_buffer_max_size: DEFAULT_MTU as usize,
             rtt_smoothing_factor: 0.10,
+            congestion_control: CongestionControl::None,
+            max_send_rate: None,
+            send_burst_size: 16 * 1024,
+            max_paced_packets: 1024,
             rtt_max_value: 250,
+            packet_loss_threshold: 0.10,
+            quality_hysteresis: 0.20,
             socket_event_buffer_size: 1024,
-            socket_polling_timeout: Some(Duration::from_millis(1)),
+            socket_polling_timeout: None,
         }
     }
 }
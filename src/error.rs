@@ -20,6 +20,16 @@ pub enum ErrorKind {
     FragmentError(FragmentErrorKind),
     /// Error relating to receiving or parsing a packet
     PacketError(PacketErrorKind),
+    /// Error relating to encrypting or decrypting a packet
+    EncryptionError(EncryptionErrorKind),
+    /// Error relating to creating or using a connect token
+    ConnectTokenError(ConnectTokenErrorKind),
+    /// Error relating to parsing a link conditioner script
+    LinkScriptError(LinkScriptErrorKind),
+    /// A send-side limit was reached and the packet was not sent
+    BackpressureError(BackpressureErrorKind),
+    /// The configuration of the socket is invalid
+    ConfigError(ConfigErrorKind),
     /// Wrapper around a std io::Error
     IOError(io::Error),
     /// Did not receive enough data
@@ -50,6 +60,31 @@ impl Display for ErrorKind {
                 "Something went wrong with receiving/parsing packets. Reason: {:?}.",
                 e
             ),
+            ErrorKind::EncryptionError(e) => write!(
+                fmt,
+                "Something went wrong with encrypting/decrypting packets. Reason: {:?}.",
+                e
+            ),
+            ErrorKind::ConnectTokenError(e) => write!(
+                fmt,
+                "Something went wrong with creating/using a connect token. Reason: {:?}.",
+                e
+            ),
+            ErrorKind::LinkScriptError(e) => write!(
+                fmt,
+                "Something went wrong with parsing a link conditioner script. Reason: {:?}.",
+                e
+            ),
+            ErrorKind::BackpressureError(e) => write!(
+                fmt,
+                "The packet could not be sent because a limit was reached. Reason: {:?}.",
+                e
+            ),
+            ErrorKind::ConfigError(e) => write!(
+                fmt,
+                "The configuration of the socket is invalid. Reason: {:?}.",
+                e
+            ),
             ErrorKind::IOError(e) => write!(fmt, "An IO Error occurred. Reason: {:?}.", e),
             ErrorKind::ReceivedDataToShort => {
                 write!(fmt, "The received data did not have any length.")
@@ -115,6 +150,157 @@ impl Display for PacketErrorKind {
     }
 }
 
+/// Errors that could occur while encrypting or decrypting packets
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EncryptionErrorKind {
+    /// The packet could not be encrypted
+    EncryptionFailed,
+    /// The packet could not be decrypted, it was not encrypted with the expected key or tampered with
+    DecryptionFailed,
+    /// The packet was received before
+    ReplayedPacket,
+    /// An encrypted packet was received from a connection without encryption keys
+    MissingKeys,
+}
+
+impl Display for EncryptionErrorKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            EncryptionErrorKind::EncryptionFailed => {
+                write!(fmt, "The packet could not be encrypted.")
+            }
+            EncryptionErrorKind::DecryptionFailed => {
+                write!(fmt, "The packet could not be decrypted.")
+            }
+            EncryptionErrorKind::ReplayedPacket => {
+                write!(fmt, "The packet was already received before.")
+            }
+            EncryptionErrorKind::MissingKeys => write!(
+                fmt,
+                "An encrypted packet was received but there are no keys to decrypt it."
+            ),
+        }
+    }
+}
+
+/// Errors that could occur while creating or using connect tokens
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConnectTokenErrorKind {
+    /// The token contains more server addresses than allowed
+    TooManyServerAddresses,
+    /// The user data of the token is larger than allowed
+    UserDataTooLarge,
+    /// The token could not be encrypted
+    EncryptionFailed,
+    /// The token could not be read
+    InvalidToken,
+    /// The token does not allow connecting to the given server address
+    UnknownServerAddress,
+}
+
+impl Display for ConnectTokenErrorKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConnectTokenErrorKind::TooManyServerAddresses => {
+                write!(fmt, "The token contains too many server addresses.")
+            }
+            ConnectTokenErrorKind::UserDataTooLarge => {
+                write!(fmt, "The user data of the token is too large.")
+            }
+            ConnectTokenErrorKind::EncryptionFailed => {
+                write!(fmt, "The token could not be encrypted.")
+            }
+            ConnectTokenErrorKind::InvalidToken => write!(fmt, "The token could not be read."),
+            ConnectTokenErrorKind::UnknownServerAddress => write!(
+                fmt,
+                "The token does not allow connecting to the server address."
+            ),
+        }
+    }
+}
+
+/// Errors that could occur while parsing link conditioner scripts, with the number of the line
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LinkScriptErrorKind {
+    /// The line does not start with a valid time
+    InvalidTime(usize),
+    /// The command of the line is missing or unknown
+    UnknownCommand(usize),
+    /// The arguments of the command are missing or invalid
+    InvalidArguments(usize),
+}
+
+impl Display for LinkScriptErrorKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            LinkScriptErrorKind::InvalidTime(line) => {
+                write!(fmt, "Line {} does not start with a valid time.", line)
+            }
+            LinkScriptErrorKind::UnknownCommand(line) => {
+                write!(fmt, "Line {} does not contain a known command.", line)
+            }
+            LinkScriptErrorKind::InvalidArguments(line) => {
+                write!(fmt, "The arguments on line {} are invalid.", line)
+            }
+        }
+    }
+}
+
+/// Send-side limits that could prevent a packet from being sent
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BackpressureErrorKind {
+    /// The queue of packets waiting to be sent by the socket is full
+    PacketQueueFull,
+    /// The connection has too many unacknowledged reliable bytes in flight
+    InFlightLimitReached,
+    /// The connection holds back too many packets which exceed its send rate
+    PacingQueueFull,
+}
+
+impl Display for BackpressureErrorKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            BackpressureErrorKind::PacketQueueFull => {
+                write!(fmt, "The packet queue of the socket is full.")
+            }
+            BackpressureErrorKind::InFlightLimitReached => write!(
+                fmt,
+                "The connection has too many unacknowledged reliable bytes in flight."
+            ),
+            BackpressureErrorKind::PacingQueueFull => write!(
+                fmt,
+                "The connection holds back too many packets which exceed its send rate."
+            ),
+        }
+    }
+}
+
+/// Settings of the `Config` a socket cannot be built with
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConfigErrorKind {
+    /// The `max_send_rate` is zero, so no packet could ever be sent
+    ZeroSendRate,
+    /// The `send_burst_size` is zero while the send rate is limited
+    ZeroSendBurstSize,
+}
+
+impl Display for ConfigErrorKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConfigErrorKind::ZeroSendRate => write!(
+                fmt,
+                "The max send rate is zero, use None to not limit the send rate."
+            ),
+            ConfigErrorKind::ZeroSendBurstSize => {
+                write!(
+                    fmt,
+                    "The send burst size is zero while the send rate is limited."
+                )
+            }
+        }
+    }
+}
+
 /// Errors that could occur with constructing/parsing fragment contents
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum FragmentErrorKind {
@@ -128,6 +314,10 @@ pub enum FragmentErrorKind {
     FragmentWithUnevenNumberOfFragemts,
     /// Fragment we expected to be able to find we couldn't
     CouldNotFindFragmentById,
+    /// The id of the fragment is not smaller than the number of fragments
+    InvalidFragmentId,
+    /// The size of the fragment does not match its position in the packet
+    InvalidFragmentSize,
 }
 
 impl Display for FragmentErrorKind {
@@ -151,6 +341,13 @@ impl Display for FragmentErrorKind {
                 fmt,
                 "The fragment supposed to be in a the cache but it was not found."
             ),
+            FragmentErrorKind::InvalidFragmentId => {
+                write!(fmt, "The fragment id is outside of the fragment count.")
+            }
+            FragmentErrorKind::InvalidFragmentSize => write!(
+                fmt,
+                "The fragment size does not match its position in the packet."
+            ),
         }
     }
 }
@@ -167,6 +364,36 @@ impl From<PacketErrorKind> for ErrorKind {
     }
 }
 
+impl From<EncryptionErrorKind> for ErrorKind {
+    fn from(inner: EncryptionErrorKind) -> Self {
+        ErrorKind::EncryptionError(inner)
+    }
+}
+
+impl From<LinkScriptErrorKind> for ErrorKind {
+    fn from(inner: LinkScriptErrorKind) -> Self {
+        ErrorKind::LinkScriptError(inner)
+    }
+}
+
+impl From<ConnectTokenErrorKind> for ErrorKind {
+    fn from(inner: ConnectTokenErrorKind) -> Self {
+        ErrorKind::ConnectTokenError(inner)
+    }
+}
+
+impl From<BackpressureErrorKind> for ErrorKind {
+    fn from(inner: BackpressureErrorKind) -> Self {
+        ErrorKind::BackpressureError(inner)
+    }
+}
+
+impl From<ConfigErrorKind> for ErrorKind {
+    fn from(inner: ConfigErrorKind) -> Self {
+        ErrorKind::ConfigError(inner)
+    }
+}
+
 impl From<FragmentErrorKind> for ErrorKind {
     fn from(inner: FragmentErrorKind) -> Self {
         ErrorKind::FragmentError(inner)
@@ -1,13 +1,23 @@
 //! This module provides the logic around the processing of the packet.
-//! Like ordering, sequencing, controlling congestion, fragmentation, and packet acknowledgment.
+//! Like ordering, sequencing, controlling congestion, pacing, fragmentation, large messages, encryption, and packet acknowledgment.
 
 mod acknowledgment;
 mod congestion;
+mod congestion_control;
+mod encryption;
 mod fragmenter;
+mod large_message;
+mod pacer;
 
 pub mod arranging;
 
 pub use self::acknowledgment::AcknowledgmentHandler;
 pub use self::acknowledgment::SentPacket;
 pub use self::congestion::CongestionHandler;
-pub use self::fragmenter::Fragmentation;
+pub use self::congestion_control::{
+    BinaryCongestionController, CongestionControl, CongestionController,
+};
+pub use self::encryption::{ConnectionKeys, EncryptionHandler, EncryptionKey, KEY_SIZE};
+pub use self::fragmenter::{Fragmentation, ReassemblyStats};
+pub use self::large_message::{LargeMessageReceiver, LargeMessageSender, ReceivedChunk};
+pub use self::pacer::Pacer;
@@ -1,9 +1,11 @@
-use crate::packet::OrderingGuarantee;
-use crate::packet::SequenceNumber;
+use crate::packet::header::AckWindow;
+use crate::packet::{DeliveryGuarantee, OrderingGuarantee, PacketId, SequenceNumber};
 use crate::sequence_buffer::{sequence_less_than, SequenceBuffer};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-const REDUNDANT_PACKET_ACKS_SIZE: u16 = 32;
+// The number of packets covered by the largest acknowledgment window.
+const MAX_REDUNDANT_PACKET_ACKS_SIZE: u16 = 128;
 const DEFAULT_SEND_PACKETS_SIZE: usize = 256;
 
 /// Responsible for handling the acknowledgment of packets.
@@ -15,9 +17,13 @@ pub struct AcknowledgmentHandler {
     // Using a Hashmap to track every packet we send out so we can ensure that we can resend when
     // dropped.
     sent_packets: HashMap<u16, SentPacket>,
-    // However, we can only reasonably ack up to REDUNDANT_PACKET_ACKS_SIZE + 1 packets on each
-    // message we send so this should be that large
+    // However, we can only reasonably ack up to the window size + 1 packets on each message we
+    // send so this should be large enough for the largest window
     received_packets: SequenceBuffer<ReceivedPacket>,
+    // The number of packets before the last acknowledged one that each acknowledgment covers.
+    window: AckWindow,
+    // The total payload size of the packets in `sent_packets`.
+    in_flight_bytes: usize,
 }
 
 impl AcknowledgmentHandler {
@@ -27,10 +33,34 @@ impl AcknowledgmentHandler {
             sequence_number: 0,
             remote_ack_sequence_num: u16::max_value(),
             sent_packets: HashMap::with_capacity(DEFAULT_SEND_PACKETS_SIZE),
-            received_packets: SequenceBuffer::with_capacity(REDUNDANT_PACKET_ACKS_SIZE + 1),
+            received_packets: SequenceBuffer::with_capacity(MAX_REDUNDANT_PACKET_ACKS_SIZE + 1),
+            window: AckWindow::default(),
+            in_flight_bytes: 0,
         }
     }
 
+    /// Returns the acknowledgment window used for the acknowledgments we send and receive.
+    pub fn window(&self) -> AckWindow {
+        self.window
+    }
+
+    /// Sets the acknowledgment window that was negotiated with the remote host.
+    pub fn set_window(&mut self, window: AckWindow) {
+        self.window = window;
+    }
+
+    /// Returns the number of payload bytes which were sent but are not acknowledged yet.
+    ///
+    /// Only reliable packets are counted, the payload of unreliable packets is not kept.
+    pub fn in_flight_bytes(&self) -> usize {
+        self.in_flight_bytes
+    }
+
+    /// Returns the number of packets which were sent but are not acknowledged or dropped yet.
+    pub fn packets_in_flight(&self) -> usize {
+        self.sent_packets.len()
+    }
+
     /// Returns the next sequence number to send.
     pub fn local_sequence_num(&self) -> SequenceNumber {
         self.sequence_number
@@ -41,16 +71,16 @@ impl AcknowledgmentHandler {
         self.received_packets.sequence_num().wrapping_sub(1)
     }
 
-    /// Returns the ack_bitfield corresponding to which of the past 32 packets we've
-    /// successfully received.
-    pub fn ack_bitfield(&self) -> u32 {
+    /// Returns the ack_bitfield corresponding to which of the past packets within the window
+    /// we've successfully received.
+    pub fn ack_bitfield(&self) -> u128 {
         let most_recent_remote_seq_num: u16 = self.remote_sequence_num();
-        let mut ack_bitfield: u32 = 0;
-        let mut mask: u32 = 1;
+        let mut ack_bitfield: u128 = 0;
+        let mut mask: u128 = 1;
 
-        // Iterate the past REDUNDANT_PACKET_ACKS_SIZE received packets and set the corresponding
-        // bit for each packet which exists in the buffer.
-        for i in 1..=REDUNDANT_PACKET_ACKS_SIZE {
+        // Iterate the past received packets within the window and set the corresponding bit for
+        // each packet which exists in the buffer.
+        for i in 1..=self.window.packets() {
             let sequence = most_recent_remote_seq_num.wrapping_sub(i);
             if self.received_packets.exists(sequence) {
                 ack_bitfield |= mask;
@@ -65,75 +95,175 @@ impl AcknowledgmentHandler {
     ///
     /// - Acknowledge the incoming sequence number
     /// - Update dropped packets
+    ///
+    /// Returns the ids of the packets which are acknowledged for the first time.
     pub fn process_incoming(
         &mut self,
         remote_seq_num: u16,
         remote_ack_seq: u16,
-        mut remote_ack_field: u32,
-    ) {
-        self.remote_ack_sequence_num = remote_ack_seq;
+        remote_ack_field: u128,
+    ) -> Vec<PacketId> {
         self.received_packets
             .insert(remote_seq_num, ReceivedPacket {});
+        self.process_acks(remote_ack_seq, remote_ack_field)
+    }
+
+    /// Process the acknowledgments of a packet which is not acknowledged itself, like a heartbeat.
+    ///
+    /// Returns the ids of the packets which are acknowledged for the first time.
+    pub fn process_acks(
+        &mut self,
+        remote_ack_seq: u16,
+        mut remote_ack_field: u128,
+    ) -> Vec<PacketId> {
+        self.remote_ack_sequence_num = remote_ack_seq;
+
+        let mut acked = Vec::new();
 
         // The current remote_ack_seq was (clearly) received so we should remove it.
-        self.sent_packets.remove(&remote_ack_seq);
+        acked.extend(self.remove_sent_packet(remote_ack_seq).map(|p| p.id));
 
-        // The remote_ack_field is going to include whether or not the past 32 packets have been
-        // received successfully. If so, we have no need to resend old packets.
-        for i in 1..=REDUNDANT_PACKET_ACKS_SIZE {
+        // The remote_ack_field is going to include whether or not the past packets within the
+        // window have been received successfully. If so, we have no need to resend old packets.
+        for i in 1..=self.window.packets() {
             let ack_sequence = remote_ack_seq.wrapping_sub(i);
             if remote_ack_field & 1 == 1 {
-                self.sent_packets.remove(&ack_sequence);
+                acked.extend(self.remove_sent_packet(ack_sequence).map(|p| p.id));
             }
             remote_ack_field >>= 1;
         }
+
+        acked
     }
 
     /// Enqueue the outgoing packet for acknowledgment.
     pub fn process_outgoing(
         &mut self,
+        id: PacketId,
         payload: &[u8],
+        delivery_guarantee: DeliveryGuarantee,
         ordering_guarantee: OrderingGuarantee,
         item_identifier: Option<SequenceNumber>,
     ) {
-        self.sent_packets.insert(
+        self.in_flight_bytes += payload.len();
+        let overwritten = self.sent_packets.insert(
             self.sequence_number,
             SentPacket {
+                id,
                 payload: Box::from(payload),
+                delivery_guarantee,
                 ordering_guarantee,
                 item_identifier,
+                sent_time: None,
+                resend_count: 0,
+                deadline: None,
             },
         );
+        if let Some(overwritten) = overwritten {
+            self.in_flight_bytes -= overwritten.payload.len();
+        }
 
         // Bump the local sequence number for the next outgoing packet.
         self.sequence_number = self.sequence_number.wrapping_add(1);
     }
 
+    /// Marks the most recently enqueued packet as being the `resend_count`th resend of an earlier packet.
+    pub fn record_resend(&mut self, resend_count: u16) {
+        let last_sequence = self.sequence_number.wrapping_sub(1);
+        if let Some(sent_packet) = self.sent_packets.get_mut(&last_sequence) {
+            sent_packet.resend_count = resend_count;
+        }
+    }
+
+    /// Marks the most recently enqueued packet as no longer being resent after `deadline`.
+    pub fn record_deadline(&mut self, deadline: Instant) {
+        let last_sequence = self.sequence_number.wrapping_sub(1);
+        if let Some(sent_packet) = self.sent_packets.get_mut(&last_sequence) {
+            sent_packet.deadline = Some(deadline);
+        }
+    }
+
     /// Returns a `Vec` of packets we believe have been dropped.
     pub fn dropped_packets(&mut self) -> Vec<SentPacket> {
         let mut sent_sequences: Vec<SequenceNumber> = self.sent_packets.keys().cloned().collect();
         sent_sequences.sort();
 
         let remote_ack_sequence = self.remote_ack_sequence_num;
+        let window = self.window.packets();
         sent_sequences
             .into_iter()
             .filter(|s| {
                 if sequence_less_than(*s, remote_ack_sequence) {
-                    remote_ack_sequence.wrapping_sub(*s) > REDUNDANT_PACKET_ACKS_SIZE
+                    remote_ack_sequence.wrapping_sub(*s) > window
                 } else {
                     false
                 }
             })
-            .flat_map(|s| self.sent_packets.remove(&s))
+            .flat_map(|s| self.remove_sent_packet(s))
             .collect()
     }
+
+    /// Marks the packet with the given sequence number as sent at the given time, from which on its
+    /// retransmission timeout runs.
+    pub fn record_sent(&mut self, sequence: SequenceNumber, time: Instant) {
+        if let Some(sent_packet) = self.sent_packets.get_mut(&sequence) {
+            sent_packet.sent_time = Some(time);
+        }
+    }
+
+    /// Returns the time at which the oldest unacknowledged reliable packet was sent, if any.
+    pub fn oldest_sent_time(&self) -> Option<Instant> {
+        self.sent_packets
+            .values()
+            .filter(|sent_packet| sent_packet.delivery_guarantee == DeliveryGuarantee::Reliable)
+            .filter_map(|sent_packet| sent_packet.sent_time)
+            .min()
+    }
+
+    /// Returns a `Vec` of reliable packets which have not been acknowledged within the given `timeout`.
+    ///
+    /// This makes sure packets are resent even when no newer packets arrive to acknowledge them,
+    /// e.g. when the last packets of a burst are lost. Unreliable packets are only considered lost
+    /// once the acknowledgments skipped them, see `dropped_packets`.
+    pub fn expired_packets(&mut self, time: Instant, timeout: Duration) -> Vec<SentPacket> {
+        let mut expired_sequences: Vec<SequenceNumber> = self
+            .sent_packets
+            .iter()
+            .filter(|(_, sent_packet)| {
+                sent_packet.delivery_guarantee == DeliveryGuarantee::Reliable
+                    && matches!(sent_packet.sent_time, Some(sent_time) if sent_time + timeout <= time)
+            })
+            .map(|(sequence, _)| *sequence)
+            .collect();
+        expired_sequences.sort();
+
+        expired_sequences
+            .into_iter()
+            .flat_map(|s| self.remove_sent_packet(s))
+            .collect()
+    }
+
+    fn remove_sent_packet(&mut self, sequence: SequenceNumber) -> Option<SentPacket> {
+        let sent_packet = self.sent_packets.remove(&sequence)?;
+        self.in_flight_bytes -= sent_packet.payload.len();
+        Some(sent_packet)
+    }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SentPacket {
+    // The id the packet was handed to the socket with, which is kept when it is resent.
+    pub id: PacketId,
     pub payload: Box<[u8]>,
+    pub delivery_guarantee: DeliveryGuarantee,
     pub ordering_guarantee: OrderingGuarantee,
     pub item_identifier: Option<SequenceNumber>,
+    // The time this packet was last sent, `None` while it is still held back by the pacer.
+    pub sent_time: Option<Instant>,
+    // The number of times the payload of this packet has been resent.
+    pub resend_count: u16,
+    // The time after which this packet is no longer resent, if any.
+    pub deadline: Option<Instant>,
 }
 
 // TODO: At some point we should put something useful here. Possibly timing information or total
@@ -145,15 +275,23 @@ pub struct ReceivedPacket;
 mod test {
     use crate::infrastructure::acknowledgment::ReceivedPacket;
     use crate::infrastructure::{AcknowledgmentHandler, SentPacket};
-    use crate::packet::OrderingGuarantee;
+    use crate::packet::header::AckWindow;
+    use crate::packet::{DeliveryGuarantee, OrderingGuarantee, PacketId};
     use log::debug;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn increment_local_seq_num_on_process_outgoing() {
         let mut handler = AcknowledgmentHandler::new();
         assert_eq!(handler.local_sequence_num(), 0);
         for i in 0..10 {
-            handler.process_outgoing(vec![].as_slice(), OrderingGuarantee::None, None);
+            handler.process_outgoing(
+                PacketId(0),
+                vec![].as_slice(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+            );
             assert_eq!(handler.local_sequence_num(), i + 1);
         }
     }
@@ -162,7 +300,13 @@ mod test {
     fn local_seq_num_wraps_on_overflow() {
         let mut handler = AcknowledgmentHandler::new();
         handler.sequence_number = u16::max_value();
-        handler.process_outgoing(vec![].as_slice(), OrderingGuarantee::None, None);
+        handler.process_outgoing(
+            PacketId(0),
+            vec![].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
         assert_eq!(handler.local_sequence_num(), 0);
     }
 
@@ -193,9 +337,21 @@ mod test {
         let mut handler = AcknowledgmentHandler::new();
 
         handler.sequence_number = 0;
-        handler.process_outgoing(vec![1, 2, 3].as_slice(), OrderingGuarantee::None, None);
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1, 2, 3].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
         handler.sequence_number = 40;
-        handler.process_outgoing(vec![1, 2, 4].as_slice(), OrderingGuarantee::None, None);
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1, 2, 4].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
 
         static ARBITRARY: u16 = 23;
         handler.process_incoming(ARBITRARY, 40, 0);
@@ -203,13 +359,107 @@ mod test {
         assert_eq!(
             handler.dropped_packets(),
             vec![SentPacket {
+                id: PacketId(0),
                 payload: vec![1, 2, 3].into_boxed_slice(),
+                delivery_guarantee: DeliveryGuarantee::Reliable,
                 ordering_guarantee: OrderingGuarantee::None,
                 item_identifier: None,
+                sent_time: None,
+                resend_count: 0,
+                deadline: None,
             }]
         );
     }
 
+    #[test]
+    fn packet_expires_after_timeout() {
+        let mut handler = AcknowledgmentHandler::new();
+        let time = Instant::now();
+        let timeout = Duration::from_millis(100);
+
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1, 2, 3].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1, 2, 4].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1, 2, 5].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
+        handler.record_sent(0, time);
+        handler.record_sent(1, time + Duration::from_millis(50));
+
+        assert!(handler
+            .expired_packets(time + Duration::from_millis(99), timeout)
+            .is_empty());
+
+        let expired = handler.expired_packets(time + timeout, timeout);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].payload, vec![1, 2, 3].into_boxed_slice());
+
+        // Acknowledged packets never expire, nor do packets which were not sent yet.
+        handler.process_incoming(0, 1, 0);
+        assert!(handler
+            .expired_packets(time + Duration::from_secs(1), timeout)
+            .is_empty());
+    }
+
+    #[test]
+    fn unreliable_packets_never_expire() {
+        let mut handler = AcknowledgmentHandler::new();
+        let time = Instant::now();
+
+        handler.process_outgoing(
+            PacketId(0),
+            &[],
+            DeliveryGuarantee::Unreliable,
+            OrderingGuarantee::None,
+            None,
+        );
+        handler.record_sent(0, time);
+
+        assert_eq!(handler.oldest_sent_time(), None);
+        assert!(handler
+            .expired_packets(time + Duration::from_secs(1), Duration::from_millis(100))
+            .is_empty());
+        assert_eq!(handler.packets_in_flight(), 1);
+    }
+
+    #[test]
+    fn resend_count_is_recorded_on_last_packet() {
+        let mut handler = AcknowledgmentHandler::new();
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
+        handler.process_outgoing(
+            PacketId(0),
+            vec![2].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
+        handler.record_resend(3);
+
+        assert_eq!(handler.sent_packets[&0].resend_count, 0);
+        assert_eq!(handler.sent_packets[&1].resend_count, 3);
+    }
+
     #[test]
     fn acking_500_packets_without_packet_drop() {
         let mut handler = AcknowledgmentHandler::new();
@@ -217,7 +467,13 @@ mod test {
 
         for i in 0..500 {
             handler.sequence_number = i;
-            handler.process_outgoing(vec![1, 2, 3].as_slice(), OrderingGuarantee::None, None);
+            handler.process_outgoing(
+                PacketId(0),
+                vec![1, 2, 3].as_slice(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+            );
 
             other.process_incoming(i, handler.remote_sequence_num(), handler.ack_bitfield());
             handler.process_incoming(i, other.remote_sequence_num(), other.ack_bitfield());
@@ -234,7 +490,13 @@ mod test {
         let mut drop_count = 0;
 
         for i in 0..100 {
-            handler.process_outgoing(vec![1, 2, 3].as_slice(), OrderingGuarantee::None, None);
+            handler.process_outgoing(
+                PacketId(0),
+                vec![1, 2, 3].as_slice(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+            );
             handler.sequence_number = i;
 
             // dropping every 4th with modulo's
@@ -268,6 +530,26 @@ mod test {
         assert_eq!(handler.remote_sequence_num(), 1);
     }
 
+    #[test]
+    fn acknowledged_packet_ids_are_returned_once() {
+        let mut handler = AcknowledgmentHandler::new();
+        for id in 0..3 {
+            handler.process_outgoing(
+                PacketId(id),
+                vec![1, 2, 3].as_slice(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+            );
+        }
+
+        let mut acked = handler.process_incoming(0, 2, 0b10);
+        acked.sort();
+        assert_eq!(acked, vec![PacketId(0), PacketId(2)]);
+        assert!(handler.process_incoming(1, 2, 0b10).is_empty());
+        assert_eq!(handler.process_incoming(2, 2, 0b11), vec![PacketId(1)]);
+    }
+
     #[test]
     fn processing_a_full_set_of_packets() {
         let mut handler = AcknowledgmentHandler::new();
@@ -275,13 +557,73 @@ mod test {
             handler.process_incoming(i, 0, 0);
         }
         assert_eq!(handler.remote_sequence_num(), 32);
-        assert_eq!(handler.ack_bitfield(), !0);
+        assert_eq!(handler.ack_bitfield(), u128::from(u32::MAX));
+    }
+
+    #[test]
+    fn larger_window_acknowledges_older_packets() {
+        let mut handler = AcknowledgmentHandler::new();
+        let mut other = AcknowledgmentHandler::new();
+        handler.set_window(AckWindow::Bits128);
+        other.set_window(AckWindow::Bits128);
+
+        for i in 0..101 {
+            handler.process_outgoing(
+                PacketId(0),
+                vec![1, 2, 3].as_slice(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+            );
+
+            // only the first and the last packet arrive
+            if i == 0 || i == 100 {
+                other.process_incoming(i, 0, 0);
+            }
+        }
+        assert_eq!(other.ack_bitfield(), 1 << 99);
+
+        handler.process_incoming(0, other.remote_sequence_num(), other.ack_bitfield());
+
+        // The first packet is acknowledged even though it is 100 packets old, and the missing
+        // packets are still within the window so they are not considered dropped yet.
+        assert!(!handler.sent_packets.contains_key(&0));
+        assert!(!handler.sent_packets.contains_key(&100));
+        assert!(handler.dropped_packets().is_empty());
+        assert_eq!(handler.sent_packets.len(), 99);
+    }
+
+    #[test]
+    fn in_flight_bytes_are_released_when_acknowledged_or_dropped() {
+        let mut handler = AcknowledgmentHandler::new();
+        for _ in 0..40 {
+            handler.process_outgoing(
+                PacketId(0),
+                vec![1, 2, 3].as_slice(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+            );
+        }
+        assert_eq!(handler.in_flight_bytes(), 120);
+
+        // Only the last packet arrives, the packets outside of the window are dropped.
+        handler.process_incoming(0, 39, 0);
+        assert_eq!(handler.in_flight_bytes(), 117);
+        assert_eq!(handler.dropped_packets().len(), 7);
+        assert_eq!(handler.in_flight_bytes(), 96);
     }
 
     #[test]
     fn test_process_outgoing() {
         let mut handler = AcknowledgmentHandler::new();
-        handler.process_outgoing(vec![1, 2, 3].as_slice(), OrderingGuarantee::None, None);
+        handler.process_outgoing(
+            PacketId(0),
+            vec![1, 2, 3].as_slice(),
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            None,
+        );
         assert_eq!(handler.sent_packets.len(), 1);
         assert_eq!(handler.local_sequence_num(), 1);
     }
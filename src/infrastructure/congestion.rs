@@ -1,16 +1,21 @@
 use crate::{
+    infrastructure::CongestionController,
     net::{NetworkQuality, RttMeasurer},
     sequence_buffer::{CongestionData, SequenceBuffer},
     Config,
 };
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+// The number of sent packets whose sending time is kept to measure their round trip time. A buffer
+// of the full sequence range would consider every other sequence number too old to insert.
+const CONGESTION_DATA_SIZE: u16 = 1024;
 
 /// Type that is responsible for keeping track of congestion information.
 pub struct CongestionHandler {
     rtt_measurer: RttMeasurer,
     congestion_data: SequenceBuffer<CongestionData>,
-    _quality: NetworkQuality,
+    controller: Option<Box<dyn CongestionController>>,
 }
 
 impl CongestionHandler {
@@ -18,34 +23,80 @@ impl CongestionHandler {
     pub fn new(config: &Config) -> CongestionHandler {
         CongestionHandler {
             rtt_measurer: RttMeasurer::new(config),
-            congestion_data: SequenceBuffer::with_capacity(<u16>::max_value()),
-            _quality: NetworkQuality::Good,
+            congestion_data: SequenceBuffer::with_capacity(CONGESTION_DATA_SIZE),
+            controller: config.congestion_control.create(config),
         }
     }
 
-    /// Process incoming sequence number.
-    ///
-    /// This will calculate the RTT-time and smooth down the RTT-value to prevent uge RTT-spikes.
-    pub fn process_incoming(&mut self, incoming_seq: u16) {
-        let congestion_data = self.congestion_data.get_mut(incoming_seq);
-        self.rtt_measurer.calculate_rrt(congestion_data);
-    }
-
     /// Process outgoing sequence number.
     ///
     /// This will insert an entry which is used for keeping track of the sending time.
-    /// Once we process incoming sequence numbers we can calculate the `RTT` time.
+    /// Once the packet is acknowledged we can calculate its `RTT` time.
     pub fn process_outgoing(&mut self, seq: u16, time: Instant) {
         self.congestion_data
             .insert(seq, CongestionData::new(seq, time));
     }
+
+    /// Process the acknowledgment of the given outgoing sequence number.
+    ///
+    /// The round trip time of the packet is measured with the given time, which updates the RTT
+    /// estimation. The smoothed round trip time is handed to the congestion controller, so a single
+    /// delayed acknowledgment does not count as congestion. Every packet is measured once, later acknowledgments of it are ignored.
+    pub fn process_acknowledgment(&mut self, acked_seq: u16, time: Instant) {
+        let sending_time = match self.congestion_data.get_mut(acked_seq) {
+            Some(congestion_data) => congestion_data.sending_time,
+            None => return,
+        };
+        self.congestion_data.remove(acked_seq);
+
+        let rtt = time.saturating_duration_since(sending_time);
+        self.rtt_measurer.update(rtt);
+        if let (Some(controller), Some(smoothed_rtt)) =
+            (&mut self.controller, self.rtt_measurer.smoothed_rtt())
+        {
+            controller.on_ack(smoothed_rtt, time);
+        }
+    }
+
+    /// Process a packet which is considered lost.
+    pub fn process_loss(&mut self, time: Instant) {
+        if let Some(controller) = &mut self.controller {
+            controller.on_loss(time);
+        }
+    }
+
+    /// Returns the number of packets per second the congestion controller allows, or `None` if the
+    /// send rate is not limited.
+    pub fn send_rate(&self) -> Option<f64> {
+        self.controller
+            .as_ref()
+            .and_then(|controller| controller.send_rate())
+    }
+
+    /// Returns the mode of the congestion controller, which is always `Good` without one.
+    pub fn quality(&self) -> NetworkQuality {
+        self.controller
+            .as_ref()
+            .map_or(NetworkQuality::Good, |controller| controller.quality())
+    }
+
+    /// Returns the round trip time estimation of the connection.
+    pub fn rtt_measurer(&self) -> &RttMeasurer {
+        &self.rtt_measurer
+    }
+
+    /// Returns the duration after which an unacknowledged packet should be resent.
+    pub fn retransmission_timeout(&self) -> Duration {
+        self.rtt_measurer.retransmission_timeout()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::infrastructure::CongestionHandler;
+    use crate::infrastructure::{CongestionControl, CongestionHandler};
+    use crate::net::NetworkQuality;
     use crate::Config;
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn congestion_entry_created() {
@@ -60,9 +111,79 @@ mod test {
     fn rtt_value_is_updated() {
         let mut congestion_handler = CongestionHandler::new(&Config::default());
 
-        assert_eq!(congestion_handler.rtt_measurer.get_rtt(), 0.);
-        congestion_handler.process_outgoing(1, Instant::now());
-        congestion_handler.process_incoming(1);
-        assert_eq!(congestion_handler.rtt_measurer.get_rtt() != 0., true);
+        let time = Instant::now();
+
+        assert_eq!(congestion_handler.rtt_measurer.smoothed_rtt(), None);
+        congestion_handler.process_outgoing(1, time);
+        congestion_handler.process_acknowledgment(1, time + Duration::from_millis(80));
+        assert_eq!(
+            congestion_handler.rtt_measurer.smoothed_rtt(),
+            Some(Duration::from_millis(80))
+        );
+
+        // Every packet is measured once.
+        congestion_handler.process_acknowledgment(1, time + Duration::from_millis(500));
+        assert_eq!(
+            congestion_handler.rtt_measurer.smoothed_rtt(),
+            Some(Duration::from_millis(80))
+        );
+    }
+
+    #[test]
+    fn acknowledgments_drive_the_congestion_controller() {
+        let config = Config {
+            congestion_control: CongestionControl::Binary {
+                good_send_rate: 30,
+                bad_send_rate: 10,
+            },
+            rtt_max_value: 250,
+            ..Config::default()
+        };
+        let mut congestion_handler = CongestionHandler::new(&config);
+        let time = Instant::now();
+        assert_eq!(congestion_handler.send_rate(), Some(30.));
+
+        congestion_handler.process_outgoing(1, time);
+        congestion_handler.process_acknowledgment(1, time + Duration::from_millis(100));
+        assert_eq!(congestion_handler.quality(), NetworkQuality::Good);
+
+        // The smoothed round trip time exceeds the threshold once the round trip time stays high.
+        for seq in 2..20 {
+            congestion_handler.process_outgoing(seq, time);
+            congestion_handler.process_acknowledgment(seq, time + Duration::from_millis(300));
+        }
+        assert_eq!(congestion_handler.quality(), NetworkQuality::Bad);
+        assert_eq!(congestion_handler.send_rate(), Some(10.));
+    }
+
+    #[test]
+    fn single_delayed_acknowledgment_does_not_switch_the_mode() {
+        let config = Config {
+            congestion_control: CongestionControl::Binary {
+                good_send_rate: 30,
+                bad_send_rate: 10,
+            },
+            rtt_max_value: 250,
+            ..Config::default()
+        };
+        let mut congestion_handler = CongestionHandler::new(&config);
+        let time = Instant::now();
+
+        for seq in 0..10 {
+            congestion_handler.process_outgoing(seq, time);
+            congestion_handler.process_acknowledgment(seq, time + Duration::from_millis(100));
+        }
+        congestion_handler.process_outgoing(10, time);
+        congestion_handler.process_acknowledgment(10, time + Duration::from_millis(1000));
+
+        assert_eq!(congestion_handler.quality(), NetworkQuality::Good);
+        assert_eq!(congestion_handler.send_rate(), Some(30.));
+    }
+
+    #[test]
+    fn send_rate_is_not_limited_without_congestion_control() {
+        let congestion_handler = CongestionHandler::new(&Config::default());
+        assert_eq!(congestion_handler.send_rate(), None);
+        assert_eq!(congestion_handler.quality(), NetworkQuality::Good);
     }
 }
@@ -0,0 +1,252 @@
+use crate::{config::Config, net::NetworkQuality};
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The time the binary controller stays in bad mode at least, before the penalty is adjusted.
+const INITIAL_PENALTY_TIME: Duration = Duration::from_secs(4);
+const MIN_PENALTY_TIME: Duration = Duration::from_secs(1);
+const MAX_PENALTY_TIME: Duration = Duration::from_secs(60);
+/// The time after which good conditions are considered stable.
+const STABLE_CONDITIONS_TIME: Duration = Duration::from_secs(10);
+
+/// Decides the rate at which a connection sends its packets, based on the round trip times and
+/// losses it observes.
+///
+/// Implement this trait to plug in another algorithm, like AIMD or a delay-based one, with
+/// `CongestionControl::Custom`.
+pub trait CongestionController: Send {
+    /// Called for every acknowledgment which measured the round trip time of a packet, with the
+    /// smoothed round trip time of the connection.
+    fn on_ack(&mut self, smoothed_rtt: Duration, time: Instant);
+
+    /// Called for every packet which is considered lost.
+    fn on_loss(&mut self, _time: Instant) {}
+
+    /// Returns the number of packets per second the connection may send, or `None` if it is not
+    /// limited.
+    fn send_rate(&self) -> Option<f64>;
+
+    /// Returns the mode the controller is in: `Bad` while it reduces the send rate because the
+    /// network is congested.
+    fn quality(&self) -> NetworkQuality;
+}
+
+/// Creates the congestion controller of a connection.
+pub type CongestionControllerFactory =
+    dyn Fn(&Config) -> Box<dyn CongestionController> + Send + Sync;
+
+/// Selects the congestion controller of each connection.
+#[derive(Clone, Default)]
+pub enum CongestionControl {
+    /// Packets are sent at the rate the application sends them (the default).
+    #[default]
+    None,
+    /// Sends at one of two rates, in packets per second. It switches to the bad rate as soon as the
+    /// smoothed round trip time exceeds `Config::rtt_max_value`, and back once it stayed below it for a while.
+    Binary {
+        /// The send rate while the network is not congested.
+        good_send_rate: u32,
+        /// The send rate while the network is congested.
+        bad_send_rate: u32,
+    },
+    /// Creates a custom controller for every connection.
+    Custom(Arc<CongestionControllerFactory>),
+}
+
+impl CongestionControl {
+    /// Creates the controller for a new connection, if congestion control is enabled.
+    pub fn create(&self, config: &Config) -> Option<Box<dyn CongestionController>> {
+        match self {
+            CongestionControl::None => None,
+            CongestionControl::Binary {
+                good_send_rate,
+                bad_send_rate,
+            } => Some(Box::new(BinaryCongestionController::new(
+                Duration::from_millis(u64::from(config.rtt_max_value)),
+                f64::from(*good_send_rate),
+                f64::from(*bad_send_rate),
+            ))),
+            CongestionControl::Custom(create) => Some(create(config)),
+        }
+    }
+}
+
+impl fmt::Debug for CongestionControl {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CongestionControl::None => write!(fmt, "None"),
+            CongestionControl::Binary {
+                good_send_rate,
+                bad_send_rate,
+            } => fmt
+                .debug_struct("Binary")
+                .field("good_send_rate", good_send_rate)
+                .field("bad_send_rate", bad_send_rate)
+                .finish(),
+            CongestionControl::Custom(_) => write!(fmt, "Custom"),
+        }
+    }
+}
+
+/// Toggles between a good and a bad send rate, as described by
+/// [Gaffer on Games](https://gafferongames.com/post/reliability_ordering_and_congestion_avoidance_over_udp/).
+///
+/// The controller switches to bad mode as soon as the round trip time exceeds the threshold, and
+/// back to good mode once it stayed below the threshold for the penalty time. The penalty time
+/// doubles when good mode does not last for 10 seconds, and halves for every 10 seconds good mode
+/// lasts, so the controller does not flap between the modes.
+pub struct BinaryCongestionController {
+    rtt_threshold: Duration,
+    good_send_rate: f64,
+    bad_send_rate: f64,
+    quality: NetworkQuality,
+    penalty_time: Duration,
+    // How long the round trip time stayed below the threshold.
+    good_conditions_time: Duration,
+    // How long good mode lasted since the penalty time was last reduced.
+    penalty_reduction_time: Duration,
+    last_update: Option<Instant>,
+}
+
+impl BinaryCongestionController {
+    /// Constructs a new `BinaryCongestionController`, which starts in good mode.
+    pub fn new(rtt_threshold: Duration, good_send_rate: f64, bad_send_rate: f64) -> Self {
+        BinaryCongestionController {
+            rtt_threshold,
+            good_send_rate,
+            bad_send_rate,
+            quality: NetworkQuality::Good,
+            penalty_time: INITIAL_PENALTY_TIME,
+            good_conditions_time: Duration::default(),
+            penalty_reduction_time: Duration::default(),
+            last_update: None,
+        }
+    }
+}
+
+impl CongestionController for BinaryCongestionController {
+    fn on_ack(&mut self, smoothed_rtt: Duration, time: Instant) {
+        let elapsed = self.last_update.map_or(Duration::default(), |last_update| {
+            time.saturating_duration_since(last_update)
+        });
+        self.last_update = Some(time);
+        let is_congested = smoothed_rtt > self.rtt_threshold;
+
+        match self.quality {
+            NetworkQuality::Good if is_congested => {
+                // Good mode did not last, so wait longer before trying it again.
+                if self.good_conditions_time < STABLE_CONDITIONS_TIME {
+                    self.penalty_time = (self.penalty_time * 2).min(MAX_PENALTY_TIME);
+                }
+                self.quality = NetworkQuality::Bad;
+                self.good_conditions_time = Duration::default();
+                self.penalty_reduction_time = Duration::default();
+            }
+            NetworkQuality::Good => {
+                self.good_conditions_time += elapsed;
+                self.penalty_reduction_time += elapsed;
+                if self.penalty_reduction_time >= STABLE_CONDITIONS_TIME {
+                    self.penalty_time = (self.penalty_time / 2).max(MIN_PENALTY_TIME);
+                    self.penalty_reduction_time = Duration::default();
+                }
+            }
+            NetworkQuality::Bad if is_congested => {
+                self.good_conditions_time = Duration::default();
+            }
+            NetworkQuality::Bad => {
+                self.good_conditions_time += elapsed;
+                if self.good_conditions_time >= self.penalty_time {
+                    self.quality = NetworkQuality::Good;
+                    self.good_conditions_time = Duration::default();
+                    self.penalty_reduction_time = Duration::default();
+                }
+            }
+        }
+    }
+
+    fn send_rate(&self) -> Option<f64> {
+        match self.quality {
+            NetworkQuality::Good => Some(self.good_send_rate),
+            NetworkQuality::Bad => Some(self.bad_send_rate),
+        }
+    }
+
+    fn quality(&self) -> NetworkQuality {
+        self.quality
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryCongestionController, CongestionController};
+    use crate::net::NetworkQuality;
+    use std::time::{Duration, Instant};
+
+    const THRESHOLD: Duration = Duration::from_millis(250);
+    const GOOD_RTT: Duration = Duration::from_millis(50);
+    const BAD_RTT: Duration = Duration::from_millis(300);
+
+    // Acknowledges a packet every 100 milliseconds for the given duration.
+    fn ack_for(
+        controller: &mut BinaryCongestionController,
+        rtt: Duration,
+        start: Instant,
+        duration: Duration,
+    ) -> Instant {
+        let mut time = start;
+        while time < start + duration {
+            time += Duration::from_millis(100);
+            controller.on_ack(rtt, time);
+        }
+        time
+    }
+
+    #[test]
+    fn switches_to_bad_mode_when_rtt_exceeds_threshold() {
+        let mut controller = BinaryCongestionController::new(THRESHOLD, 30., 10.);
+        let time = Instant::now();
+
+        controller.on_ack(GOOD_RTT, time);
+        assert_eq!(controller.quality(), NetworkQuality::Good);
+        assert_eq!(controller.send_rate(), Some(30.));
+
+        controller.on_ack(BAD_RTT, time);
+        assert_eq!(controller.quality(), NetworkQuality::Bad);
+        assert_eq!(controller.send_rate(), Some(10.));
+    }
+
+    #[test]
+    fn switches_back_to_good_mode_after_the_penalty_time() {
+        let mut controller = BinaryCongestionController::new(THRESHOLD, 30., 10.);
+        let time = Instant::now();
+        controller.on_ack(BAD_RTT, time);
+        // Good mode lasted shortly, so the penalty doubled to 8 seconds.
+        assert_eq!(controller.penalty_time, Duration::from_secs(8));
+
+        let time = ack_for(&mut controller, GOOD_RTT, time, Duration::from_secs(7));
+        assert_eq!(controller.quality(), NetworkQuality::Bad);
+
+        ack_for(&mut controller, GOOD_RTT, time, Duration::from_secs(1));
+        assert_eq!(controller.quality(), NetworkQuality::Good);
+    }
+
+    #[test]
+    fn penalty_time_halves_while_good_mode_lasts() {
+        let mut controller = BinaryCongestionController::new(THRESHOLD, 30., 10.);
+        let time = ack_for(
+            &mut controller,
+            GOOD_RTT,
+            Instant::now(),
+            Duration::from_secs(30),
+        );
+        assert_eq!(controller.penalty_time, Duration::from_secs(1));
+
+        // Good mode lasted, so the penalty does not grow when the network gets congested.
+        controller.on_ack(BAD_RTT, time);
+        assert_eq!(controller.penalty_time, Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    error::{EncryptionErrorKind, ErrorKind, Result},
+    net::constants::STANDARD_HEADER_SIZE,
+    packet::{
+        header::{HeaderWriter, StandardHeader},
+        DeliveryGuarantee, OrderingGuarantee, PacketType,
+    },
+};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+/// The size of an encryption key.
+pub const KEY_SIZE: usize = 32;
+/// The size of the sequence number in front of each encrypted packet.
+const SEQUENCE_SIZE: usize = 8;
+/// The size of the authentication tag behind each encrypted packet.
+const TAG_SIZE: usize = 16;
+/// The size of the unencrypted, but authenticated, header of an encrypted packet.
+const ENCRYPTED_HEADER_SIZE: usize = STANDARD_HEADER_SIZE as usize + SEQUENCE_SIZE;
+/// The number of sequence numbers before the highest received one that are tracked for replays.
+const REPLAY_WINDOW_SIZE: u64 = 64;
+
+/// A key with which packets are encrypted.
+pub type EncryptionKey = [u8; KEY_SIZE];
+
+/// The keys with which the packets of a connection are encrypted and decrypted.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConnectionKeys {
+    send_key: EncryptionKey,
+    receive_key: EncryptionKey,
+}
+
+impl ConnectionKeys {
+    /// Constructs new `ConnectionKeys`; the `send_key` of one endpoint is the `receive_key` of the other.
+    pub fn new(send_key: EncryptionKey, receive_key: EncryptionKey) -> ConnectionKeys {
+        ConnectionKeys {
+            send_key,
+            receive_key,
+        }
+    }
+}
+
+/// Responsible for encrypting and decrypting the packets of a connection.
+///
+/// An encrypted packet consists of a standard header of the `Encrypted` packet type and the sequence
+/// number of the encrypted packet, followed by the encrypted contents of the original packet, headers
+/// included. The sequence number is used as the nonce and is authenticated along with the standard
+/// header. Packets with a sequence number that was received before are rejected.
+pub struct EncryptionHandler {
+    send_cipher: ChaCha20Poly1305,
+    receive_cipher: ChaCha20Poly1305,
+    // The sequence number of the next packet we encrypt.
+    send_sequence: u64,
+    // The highest sequence number received so far, if any.
+    remote_sequence: Option<u64>,
+    // Bit `n` is set when the sequence number `remote_sequence - n - 1` was received.
+    received_field: u64,
+}
+
+impl EncryptionHandler {
+    /// Constructs a new `EncryptionHandler` which encrypts with the given keys.
+    pub fn new(keys: &ConnectionKeys) -> EncryptionHandler {
+        EncryptionHandler {
+            send_cipher: ChaCha20Poly1305::new(Key::from_slice(&keys.send_key)),
+            receive_cipher: ChaCha20Poly1305::new(Key::from_slice(&keys.receive_key)),
+            send_sequence: 0,
+            remote_sequence: None,
+            received_field: 0,
+        }
+    }
+
+    /// Encrypts the given packet, returning the packet which should be sent instead.
+    pub fn encrypt(&mut self, packet: &[u8]) -> Result<Box<[u8]>> {
+        let mut buffer = Vec::with_capacity(ENCRYPTED_HEADER_SIZE + packet.len() + TAG_SIZE);
+        StandardHeader::new(
+            DeliveryGuarantee::Unreliable,
+            OrderingGuarantee::None,
+            PacketType::Encrypted,
+        )
+        .parse(&mut buffer)?;
+        buffer.write_u64::<BigEndian>(self.send_sequence)?;
+
+        let encrypted = self
+            .send_cipher
+            .encrypt(
+                &nonce(self.send_sequence),
+                Payload {
+                    msg: packet,
+                    aad: &buffer,
+                },
+            )
+            .map_err(|_| ErrorKind::EncryptionError(EncryptionErrorKind::EncryptionFailed))?;
+
+        self.send_sequence += 1;
+        buffer.extend_from_slice(&encrypted);
+
+        Ok(buffer.into_boxed_slice())
+    }
+
+    /// Decrypts the given encrypted packet, returning the original packet.
+    pub fn decrypt(&mut self, packet: &[u8]) -> Result<Box<[u8]>> {
+        if packet.len() < ENCRYPTED_HEADER_SIZE + TAG_SIZE {
+            return Err(ErrorKind::ReceivedDataToShort);
+        }
+
+        let sequence =
+            BigEndian::read_u64(&packet[STANDARD_HEADER_SIZE as usize..ENCRYPTED_HEADER_SIZE]);
+
+        if self.is_replayed(sequence) {
+            return Err(ErrorKind::EncryptionError(
+                EncryptionErrorKind::ReplayedPacket,
+            ));
+        }
+
+        let decrypted = self
+            .receive_cipher
+            .decrypt(
+                &nonce(sequence),
+                Payload {
+                    msg: &packet[ENCRYPTED_HEADER_SIZE..],
+                    aad: &packet[..ENCRYPTED_HEADER_SIZE],
+                },
+            )
+            .map_err(|_| ErrorKind::EncryptionError(EncryptionErrorKind::DecryptionFailed))?;
+
+        // Only authentic packets may move the replay window.
+        self.mark_received(sequence);
+
+        Ok(decrypted.into_boxed_slice())
+    }
+
+    // Returns true if the sequence number was received before, or is too old to tell.
+    fn is_replayed(&self, sequence: u64) -> bool {
+        match self.remote_sequence {
+            Some(remote_sequence) if sequence <= remote_sequence => {
+                let distance = remote_sequence - sequence;
+                distance == 0
+                    || distance > REPLAY_WINDOW_SIZE
+                    || self.received_field & (1 << (distance - 1)) != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn mark_received(&mut self, sequence: u64) {
+        match self.remote_sequence {
+            Some(remote_sequence) if sequence < remote_sequence => {
+                self.received_field |= 1 << (remote_sequence - sequence - 1);
+            }
+            Some(remote_sequence) => {
+                let shift = sequence - remote_sequence;
+                self.received_field = if shift > REPLAY_WINDOW_SIZE {
+                    0
+                } else {
+                    // The previous highest sequence number becomes part of the field.
+                    (self.received_field << (shift - 1) << 1) | (1 << (shift - 1))
+                };
+                self.remote_sequence = Some(sequence);
+            }
+            None => self.remote_sequence = Some(sequence),
+        }
+    }
+}
+
+// Constructs the nonce for the packet with the given sequence number.
+fn nonce(sequence: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    BigEndian::write_u64(&mut nonce[4..], sequence);
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionKeys, EncryptionHandler};
+    use crate::error::{EncryptionErrorKind, ErrorKind};
+    use crate::packet::{PacketReader, PacketType};
+
+    fn handlers() -> (EncryptionHandler, EncryptionHandler) {
+        let client_keys = ConnectionKeys::new([1; 32], [2; 32]);
+        let server_keys = ConnectionKeys::new([2; 32], [1; 32]);
+        (
+            EncryptionHandler::new(&client_keys),
+            EncryptionHandler::new(&server_keys),
+        )
+    }
+
+    #[test]
+    fn encrypted_packet_can_be_decrypted() {
+        let (mut client, mut server) = handlers();
+
+        let encrypted = client.encrypt(b"Hello world!").unwrap();
+
+        let header = PacketReader::new(&encrypted)
+            .read_standard_header()
+            .unwrap();
+        assert_eq!(header.packet_type(), PacketType::Encrypted);
+        assert!(!encrypted.windows(5).any(|window| window == b"Hello"));
+
+        assert_eq!(&*server.decrypt(&encrypted).unwrap(), b"Hello world!");
+    }
+
+    #[test]
+    fn packet_encrypted_with_other_key_is_rejected() {
+        let (mut client, _) = handlers();
+        let (mut other_client, _) = handlers();
+
+        let encrypted = client.encrypt(b"Hello world!").unwrap();
+
+        match other_client.decrypt(&encrypted) {
+            Err(ErrorKind::EncryptionError(EncryptionErrorKind::DecryptionFailed)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn tampered_packet_is_rejected() {
+        let (mut client, mut server) = handlers();
+
+        let mut encrypted = client.encrypt(b"Hello world!").unwrap();
+        // Flip a bit of the sequence number, which is authenticated but not encrypted.
+        encrypted[12] ^= 1;
+
+        assert!(server.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn replayed_packet_is_rejected() {
+        let (mut client, mut server) = handlers();
+
+        let first = client.encrypt(&[1]).unwrap();
+        let second = client.encrypt(&[2]).unwrap();
+
+        assert!(server.decrypt(&second).is_ok());
+        assert!(server.decrypt(&first).is_ok());
+
+        for packet in &[first, second] {
+            match server.decrypt(packet) {
+                Err(ErrorKind::EncryptionError(EncryptionErrorKind::ReplayedPacket)) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn packets_older_than_replay_window_are_rejected() {
+        let (mut client, mut server) = handlers();
+
+        let packets: Vec<_> = (0..100).map(|_| client.encrypt(&[0]).unwrap()).collect();
+
+        assert!(server.decrypt(&packets[99]).is_ok());
+        assert!(server.decrypt(&packets[40]).is_ok());
+        assert!(server.decrypt(&packets[34]).is_err());
+    }
+}
@@ -1,16 +1,29 @@
 use crate::{
     config::Config,
     error::{FragmentErrorKind, Result},
-    net::constants::FRAGMENT_HEADER_SIZE,
-    packet::header::FragmentHeader,
+    packet::{
+        header::{AckedPacketHeader, ArrangingHeader, FragmentHeader},
+        SequenceNumber,
+    },
     sequence_buffer::{ReassemblyData, SequenceBuffer},
 };
+use std::time::Instant;
 
-use std::io::Write;
+/// Counts the partially received packets which were discarded before all of their fragments arrived.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReassemblyStats {
+    /// Packets whose fragments did not all arrive within `fragment_reassembly_timeout`.
+    pub timed_out: u64,
+    /// Packets which were discarded to stay within `max_fragment_reassembly_bytes`.
+    pub over_memory_limit: u64,
+    /// Packets which were pushed out of the reassembly buffer by newer packets.
+    pub overwritten: u64,
+}
 
 /// Type that will manage fragmentation of packets.
 pub struct Fragmentation {
     fragments: SequenceBuffer<ReassemblyData>,
+    stats: ReassemblyStats,
     config: Config,
 }
 
@@ -19,6 +32,7 @@ impl Fragmentation {
     pub fn new(config: &Config) -> Fragmentation {
         Fragmentation {
             fragments: SequenceBuffer::with_capacity(config.fragment_reassembly_buffer_size),
+            stats: ReassemblyStats::default(),
             config: config.clone(),
         }
     }
@@ -94,78 +108,394 @@ impl Fragmentation {
     }
 
     /// This will read fragment data and return the complete packet when all fragments are received.
+    ///
+    /// Fragments may arrive in any order, each fragment is placed by its id. The headers which come
+    /// with the first fragment are kept along with the fragments until the packet is complete.
+    /// Partially received packets are discarded when they time out or exceed the memory limit.
     pub fn handle_fragment(
         &mut self,
         fragment_header: FragmentHeader,
+        acked_header: Option<AckedPacketHeader>,
+        arranging_header: Option<ArrangingHeader>,
         fragment_payload: &[u8],
-    ) -> Result<Option<Vec<u8>>> {
-        // read fragment packet
+        time: Instant,
+    ) -> Result<Option<ReassemblyData>> {
+        if fragment_header.fragment_count() > self.config.max_fragments {
+            Err(FragmentErrorKind::ExceededMaxFragments)?
+        }
+        if fragment_header.id() >= fragment_header.fragment_count() {
+            Err(FragmentErrorKind::InvalidFragmentId)?
+        }
 
-        self.create_fragment_if_not_exists(fragment_header);
+        self.discard_expired(time);
+        self.create_fragment_if_not_exists(fragment_header, time);
 
-        let num_fragments_received;
-        let num_fragments_total;
-        let sequence;
-        let total_buffer;
+        // get entry of previous received fragments
+        let reassembly_data = match self.fragments.get_mut(fragment_header.sequence()) {
+            Some(val) => val,
+            None => Err(FragmentErrorKind::CouldNotFindFragmentById)?,
+        };
 
-        {
-            // get entry of previous received fragments
-            let reassembly_data = match self.fragments.get_mut(fragment_header.sequence()) {
-                Some(val) => val,
-                None => Err(FragmentErrorKind::CouldNotFindFragmentById)?,
-            };
-
-            // Got the data
-            if reassembly_data.num_fragments_total != fragment_header.fragment_count() {
-                Err(FragmentErrorKind::FragmentWithUnevenNumberOfFragemts)?
-            }
+        if reassembly_data.num_fragments_total != fragment_header.fragment_count() {
+            Err(FragmentErrorKind::FragmentWithUnevenNumberOfFragemts)?
+        }
 
-            if reassembly_data.fragments_received[usize::from(fragment_header.id())] {
-                Err(FragmentErrorKind::AlreadyProcessedFragment)?
-            }
+        if reassembly_data.fragments[usize::from(fragment_header.id())].is_some() {
+            Err(FragmentErrorKind::AlreadyProcessedFragment)?
+        }
 
-            // increase number of received fragments and set the specific fragment to received.
-            reassembly_data.num_fragments_received += 1;
-            reassembly_data.fragments_received[usize::from(fragment_header.id())] = true;
+        if !self.make_room(fragment_header.sequence(), fragment_payload.len()) {
+            self.fragments.remove(fragment_header.sequence());
+            self.stats.over_memory_limit += 1;
+            return Ok(None);
+        }
 
-            // add the payload from the fragment to the buffer whe have in cache
-            reassembly_data.buffer.write_all(&*fragment_payload)?;
+        let reassembly_data = match self.fragments.get_mut(fragment_header.sequence()) {
+            Some(val) => val,
+            None => Err(FragmentErrorKind::CouldNotFindFragmentById)?,
+        };
 
-            num_fragments_received = reassembly_data.num_fragments_received;
-            num_fragments_total = reassembly_data.num_fragments_total;
-            sequence = reassembly_data.sequence as u16;
-            total_buffer = reassembly_data.buffer.clone();
+        // place the payload in the slot of the fragment and count it as received.
+        reassembly_data.fragments[usize::from(fragment_header.id())] =
+            Some(Box::from(fragment_payload));
+        reassembly_data.num_fragments_received += 1;
+        reassembly_data.received_bytes += fragment_payload.len();
+        if acked_header.is_some() {
+            reassembly_data.acked_header = acked_header;
+        }
+        if arranging_header.is_some() {
+            reassembly_data.arranging_header = arranging_header;
         }
 
-        // if whe received all fragments then remove entry and return the total received bytes.
-        if num_fragments_received == num_fragments_total {
-            let sequence = sequence as u16;
-            self.fragments.remove(sequence);
+        // if we received all fragments then remove entry and return it.
+        if reassembly_data.is_complete() {
+            let reassembly_data = std::mem::take(reassembly_data);
+            self.fragments.remove(fragment_header.sequence());
 
-            return Ok(Some(total_buffer));
+            return Ok(Some(reassembly_data));
         }
 
         Ok(None)
     }
 
+    /// Discards the partially received packets whose fragments did not all arrive within
+    /// `fragment_reassembly_timeout`.
+    pub fn discard_expired(&mut self, time: Instant) {
+        let timeout = self.config.fragment_reassembly_timeout;
+        let expired: Vec<SequenceNumber> = self
+            .fragments
+            .iter()
+            .filter(|(_, data)| matches!(data.started, Some(started) if started + timeout <= time))
+            .map(|(sequence, _)| sequence)
+            .collect();
+
+        for sequence in expired {
+            self.fragments.remove(sequence);
+            self.stats.timed_out += 1;
+        }
+    }
+
+    /// Returns the time at which the oldest partially received packet times out, if any.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.fragments
+            .iter()
+            .filter_map(|(_, data)| data.started)
+            .min()
+            .map(|started| started + self.config.fragment_reassembly_timeout)
+    }
+
+    /// Returns the number of fragment payload bytes held for packets which are not complete yet.
+    pub fn held_bytes(&self) -> usize {
+        self.fragments
+            .iter()
+            .map(|(_, data)| data.received_bytes)
+            .sum()
+    }
+
+    /// Returns the counters of the partially received packets which were discarded.
+    pub fn stats(&self) -> ReassemblyStats {
+        self.stats
+    }
+
+    // Discards the oldest partially received packets, other than the one with the given sequence,
+    // until `bytes` more fit within `max_fragment_reassembly_bytes`. Returns false if they do not.
+    fn make_room(&mut self, sequence: SequenceNumber, bytes: usize) -> bool {
+        let own_bytes: usize = self
+            .fragments
+            .iter()
+            .filter(|(other, _)| *other == sequence)
+            .map(|(_, data)| data.received_bytes)
+            .sum();
+        if own_bytes + bytes > self.config.max_fragment_reassembly_bytes {
+            return false;
+        }
+
+        while self.held_bytes() + bytes > self.config.max_fragment_reassembly_bytes {
+            let oldest = self
+                .fragments
+                .iter()
+                .filter(|(other, _)| *other != sequence)
+                .min_by_key(|(_, data)| data.started)
+                .map(|(other, _)| other);
+
+            match oldest {
+                Some(oldest) => {
+                    self.fragments.remove(oldest);
+                    self.stats.over_memory_limit += 1;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
     /// If fragment does not exist we need to insert a new entry.
-    fn create_fragment_if_not_exists(&mut self, fragment_header: FragmentHeader) {
+    fn create_fragment_if_not_exists(&mut self, fragment_header: FragmentHeader, time: Instant) {
         if !self.fragments.exists(fragment_header.sequence()) {
-            let reassembly_data = ReassemblyData::new(
-                fragment_header.sequence(),
-                fragment_header.fragment_count(),
-                (u16::from(FRAGMENT_HEADER_SIZE) + self.config.fragment_size) as usize,
-            );
+            let reassembly_data = ReassemblyData::new(fragment_header.fragment_count(), time);
 
-            self.fragments
-                .insert(fragment_header.sequence(), reassembly_data);
+            // A newer packet may push partially received older packets out of the buffer.
+            let partial_count = self.fragments.iter().count();
+            if self
+                .fragments
+                .insert(fragment_header.sequence(), reassembly_data)
+                .is_some()
+            {
+                self.stats.overwritten +=
+                    (partial_count + 1 - self.fragments.iter().count()) as u64;
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Fragmentation;
+    use super::{Fragmentation, ReassemblyStats};
+    use crate::{
+        config::Config,
+        error::{ErrorKind, FragmentErrorKind},
+        packet::header::{AckedPacketHeader, FragmentHeader},
+    };
+    use rand::{seq::SliceRandom, SeedableRng};
+    use rand_pcg::Pcg64Mcg as Random;
+    use std::time::{Duration, Instant};
+
+    // Splits the payload into fragments of the given sequence, with the acknowledgment header
+    // along with the first fragment like they are sent.
+    fn fragments_of(
+        sequence: u16,
+        payload: &[u8],
+        config: &Config,
+    ) -> Vec<(FragmentHeader, Option<AckedPacketHeader>, Vec<u8>)> {
+        let fragments = Fragmentation::spit_into_fragments(payload, config).unwrap();
+        let count = fragments.len() as u8;
+        fragments
+            .into_iter()
+            .enumerate()
+            .map(|(id, fragment)| {
+                let acked_header = if id == 0 {
+                    Some(AckedPacketHeader::new(sequence, 0, 0))
+                } else {
+                    None
+                };
+                (
+                    FragmentHeader::new(sequence, id as u8, count),
+                    acked_header,
+                    fragment.to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order() {
+        let config = Config {
+            fragment_size: 100,
+            ..Config::default()
+        };
+        let payload: Vec<u8> = (0..1050).map(|i| i as u8).collect();
+        let mut random = Random::seed_from_u64(0);
+        let time = Instant::now();
+
+        for _ in 0..20 {
+            let mut fragmentation = Fragmentation::new(&config);
+            let mut fragments = fragments_of(5, &payload, &config);
+            fragments.shuffle(&mut random);
+
+            let last = fragments.pop().unwrap();
+            for (header, acked_header, fragment) in fragments {
+                assert!(fragmentation
+                    .handle_fragment(header, acked_header, None, &fragment, time)
+                    .unwrap()
+                    .is_none());
+            }
+
+            let reassembled = fragmentation
+                .handle_fragment(last.0, last.1, None, &last.2, time)
+                .unwrap()
+                .unwrap();
+            assert_eq!(reassembled.assemble(), payload);
+            assert_eq!(reassembled.acked_header.unwrap().sequence(), 5);
+        }
+    }
+
+    #[test]
+    fn interleaved_packets_are_reassembled() {
+        let config = Config {
+            fragment_size: 100,
+            ..Config::default()
+        };
+        let first: Vec<u8> = vec![1; 250];
+        let second: Vec<u8> = vec![2; 250];
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        let mut first_fragments = fragments_of(0, &first, &config);
+        let second_fragments = fragments_of(1, &second, &config);
+        first_fragments.reverse();
+
+        let mut reassembled = Vec::new();
+        for ((header, acked_header, fragment), (other_header, other_acked_header, other)) in
+            first_fragments.into_iter().zip(second_fragments)
+        {
+            reassembled.extend(
+                fragmentation
+                    .handle_fragment(header, acked_header, None, &fragment, time)
+                    .unwrap(),
+            );
+            reassembled.extend(
+                fragmentation
+                    .handle_fragment(other_header, other_acked_header, None, &other, time)
+                    .unwrap(),
+            );
+        }
+
+        let payloads: Vec<Vec<u8>> = reassembled.iter().map(|r| r.assemble()).collect();
+        assert_eq!(payloads, vec![first, second]);
+    }
+
+    #[test]
+    fn duplicate_and_invalid_fragments_are_rejected() {
+        let config = Config::default();
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        fragmentation
+            .handle_fragment(FragmentHeader::new(0, 1, 3), None, None, &[1], time)
+            .unwrap();
+        match fragmentation.handle_fragment(FragmentHeader::new(0, 1, 3), None, None, &[1], time) {
+            Err(ErrorKind::FragmentError(FragmentErrorKind::AlreadyProcessedFragment)) => {}
+            _ => panic!("Expected a duplicate fragment error"),
+        }
+        match fragmentation.handle_fragment(FragmentHeader::new(0, 3, 3), None, None, &[1], time) {
+            Err(ErrorKind::FragmentError(FragmentErrorKind::InvalidFragmentId)) => {}
+            _ => panic!("Expected an invalid fragment id error"),
+        }
+    }
+
+    #[test]
+    fn partial_packets_are_discarded_after_timeout() {
+        let config = Config {
+            fragment_reassembly_timeout: Duration::from_millis(100),
+            ..Config::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        fragmentation
+            .handle_fragment(FragmentHeader::new(0, 0, 2), None, None, &[1; 10], time)
+            .unwrap();
+        assert_eq!(fragmentation.held_bytes(), 10);
+        assert_eq!(
+            fragmentation.next_expiry(),
+            Some(time + Duration::from_millis(100))
+        );
+
+        fragmentation.discard_expired(time + Duration::from_millis(100));
+        assert_eq!(fragmentation.held_bytes(), 0);
+        assert_eq!(fragmentation.next_expiry(), None);
+
+        // The remaining fragment starts a new packet rather than completing the discarded one.
+        let reassembled = fragmentation
+            .handle_fragment(
+                FragmentHeader::new(0, 1, 2),
+                None,
+                None,
+                &[2; 10],
+                time + Duration::from_millis(100),
+            )
+            .unwrap();
+        assert!(reassembled.is_none());
+        assert_eq!(fragmentation.stats().timed_out, 1);
+    }
+
+    #[test]
+    fn oldest_partial_packets_are_discarded_over_memory_limit() {
+        let config = Config {
+            max_fragment_reassembly_bytes: 25,
+            ..Config::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        // A peer which only sends the first fragment of many packets cannot hold more memory.
+        for sequence in 0..10 {
+            fragmentation
+                .handle_fragment(
+                    FragmentHeader::new(sequence, 0, 2),
+                    None,
+                    None,
+                    &[0; 10],
+                    time + Duration::from_millis(u64::from(sequence)),
+                )
+                .unwrap();
+            assert!(fragmentation.held_bytes() <= 25);
+        }
+
+        // The two newest packets are still held and can be completed.
+        let reassembled = fragmentation
+            .handle_fragment(FragmentHeader::new(9, 1, 2), None, None, &[0; 5], time)
+            .unwrap();
+        assert!(reassembled.is_some());
+        assert_eq!(
+            fragmentation.stats(),
+            ReassemblyStats {
+                over_memory_limit: 8,
+                ..ReassemblyStats::default()
+            }
+        );
+
+        // A fragment which does not fit at all discards its own packet.
+        match fragmentation.handle_fragment(
+            FragmentHeader::new(10, 0, 2),
+            None,
+            None,
+            &[0; 30],
+            time,
+        ) {
+            Ok(None) => {}
+            _ => panic!("Expected the fragment to be discarded"),
+        }
+        assert_eq!(fragmentation.held_bytes(), 10);
+        assert_eq!(fragmentation.stats().over_memory_limit, 9);
+    }
+
+    #[test]
+    fn overwritten_partial_packets_are_counted() {
+        let config = Config {
+            fragment_reassembly_buffer_size: 4,
+            ..Config::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        for sequence in 0..6 {
+            fragmentation
+                .handle_fragment(FragmentHeader::new(sequence, 0, 2), None, None, &[0], time)
+                .unwrap();
+        }
+        assert_eq!(fragmentation.stats().overwritten, 2);
+    }
 
     #[test]
     pub fn expect_right_number_of_fragments() {
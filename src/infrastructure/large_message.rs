@@ -0,0 +1,506 @@
+use crate::error::{FragmentErrorKind, PacketErrorKind, Result};
+use crate::packet::header::{ChunkAckHeader, ChunkHeader, CHUNK_ACK_FIELD_SIZE};
+use crate::packet::{
+    DeliveryGuarantee, OrderingGuarantee, OutgoingPacketBuilder, PacketId, PacketType,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Streams large messages in chunks, one message at a time. At most `window` chunks after the
+/// first unacknowledged chunk are in flight, and only the chunks which are not acknowledged in
+/// time are resent.
+pub struct LargeMessageSender {
+    messages: VecDeque<OutgoingMessage>,
+    next_message_id: u16,
+    chunk_size: u16,
+    window: u32,
+}
+
+// A large message which is being sent or waits for the messages before it.
+struct OutgoingMessage {
+    id: PacketId,
+    message_id: u16,
+    payload: Box<[u8]>,
+    // The number of chunks at the start of the message which are all acknowledged.
+    acked_up_to: u32,
+    acked: Vec<bool>,
+    // The time each chunk was last sent, if it was sent already.
+    sent_times: Vec<Option<Instant>>,
+}
+
+impl LargeMessageSender {
+    /// Constructs a new `LargeMessageSender` which splits messages into chunks of `chunk_size`
+    /// bytes, and keeps at most `window` chunks in flight.
+    pub fn new(chunk_size: u16, window: u32) -> Self {
+        LargeMessageSender {
+            messages: VecDeque::new(),
+            next_message_id: 0,
+            chunk_size: chunk_size.max(1),
+            window: window.max(1),
+        }
+    }
+
+    /// Queues a message to be sent once the messages queued before it are acknowledged.
+    pub fn push(&mut self, id: PacketId, payload: &[u8]) {
+        let chunk_count =
+            ChunkHeader::new(0, 0, payload.len() as u32, self.chunk_size).chunk_count() as usize;
+
+        self.messages.push_back(OutgoingMessage {
+            id,
+            message_id: self.next_message_id,
+            payload: Box::from(payload),
+            acked_up_to: 0,
+            acked: vec![false; chunk_count],
+            sent_times: vec![None; chunk_count],
+        });
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+    }
+
+    /// Returns the chunks of the current message which are due: the chunks within the window which
+    /// were not sent yet, or which were not acknowledged within `resend_timeout`.
+    pub fn due_chunks(&mut self, time: Instant, resend_timeout: Duration) -> Vec<Box<[u8]>> {
+        let chunk_size = self.chunk_size;
+        let window = self.window;
+        let message = match self.messages.front_mut() {
+            Some(message) => message,
+            None => return Vec::new(),
+        };
+
+        let message_size = message.payload.len();
+        let end = message
+            .acked_up_to
+            .saturating_add(window)
+            .min(message.acked.len() as u32);
+        let mut chunks = Vec::new();
+
+        for index in message.acked_up_to..end {
+            let slot = index as usize;
+            let is_due = match message.sent_times[slot] {
+                Some(sent_time) => sent_time + resend_timeout <= time,
+                None => true,
+            };
+            if message.acked[slot] || !is_due {
+                continue;
+            }
+
+            message.sent_times[slot] = Some(time);
+            let start = slot * usize::from(chunk_size);
+            let stop = (start + usize::from(chunk_size)).min(message_size);
+            let header =
+                ChunkHeader::new(message.message_id, index, message_size as u32, chunk_size);
+
+            chunks.push(
+                OutgoingPacketBuilder::new(&message.payload[start..stop])
+                    .with_default_header(
+                        PacketType::Chunk,
+                        DeliveryGuarantee::Reliable,
+                        OrderingGuarantee::None,
+                    )
+                    .with_chunk_header(header)
+                    .build()
+                    .contents(),
+            );
+        }
+
+        chunks
+    }
+
+    /// Returns the time at which the next chunk of the current message has to be resent, if any.
+    pub fn next_resend_time(&self, resend_timeout: Duration) -> Option<Instant> {
+        let message = self.messages.front()?;
+        message
+            .sent_times
+            .iter()
+            .zip(message.acked.iter())
+            .skip(message.acked_up_to as usize)
+            .take(self.window as usize)
+            .filter(|(_, acked)| !**acked)
+            .filter_map(|(sent_time, _)| *sent_time)
+            .min()
+            .map(|sent_time| sent_time + resend_timeout)
+    }
+
+    /// Marks the acknowledged chunks of the current message. Returns the id of the message once all
+    /// of its chunks are acknowledged, after which the next message is sent.
+    pub fn process_ack(&mut self, header: &ChunkAckHeader) -> Option<PacketId> {
+        let message = self.messages.front_mut()?;
+        if message.message_id != header.message_id() {
+            return None;
+        }
+
+        let chunk_count = message.acked.len() as u32;
+        let end = header
+            .received_up_to()
+            .saturating_add(CHUNK_ACK_FIELD_SIZE + 1)
+            .min(chunk_count);
+        for index in message.acked_up_to..end {
+            if header.is_acked(index) {
+                message.acked[index as usize] = true;
+            }
+        }
+        while message.acked_up_to < chunk_count && message.acked[message.acked_up_to as usize] {
+            message.acked_up_to += 1;
+        }
+
+        if message.acked_up_to == chunk_count {
+            return self.messages.pop_front().map(|message| message.id);
+        }
+        None
+    }
+}
+
+/// Reassembles large messages from their chunks, which may arrive in any order. Only the chunks
+/// which arrived are held, and a message of which no chunk arrived within `timeout` is discarded.
+pub struct LargeMessageReceiver {
+    max_message_size: usize,
+    timeout: Duration,
+    message: Option<IncomingMessage>,
+    // The id and chunk count of the last completed message, so its chunks can be acknowledged
+    // again when they are resent because an acknowledgment was lost.
+    completed: Option<(u16, u32)>,
+    // Whether chunks were received since the last acknowledgment.
+    ack_pending: bool,
+}
+
+// A large message of which some chunks were received.
+struct IncomingMessage {
+    header: ChunkHeader,
+    // The received chunks by their index.
+    chunks: BTreeMap<u32, Box<[u8]>>,
+    received_bytes: usize,
+    // The number of chunks at the start of the message which were all received.
+    received_up_to: u32,
+    last_received: Instant,
+}
+
+/// What a received chunk changed about the message it is part of.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReceivedChunk {
+    /// Another percent of the message was received.
+    Progress {
+        /// The id of the message.
+        message_id: u16,
+        /// The number of bytes of the message which were received.
+        received_bytes: usize,
+        /// The size of the message.
+        total_bytes: usize,
+    },
+    /// The last chunk of the message was received.
+    Complete(Box<[u8]>),
+}
+
+impl LargeMessageReceiver {
+    /// Constructs a new `LargeMessageReceiver` which accepts messages of at most `max_message_size`
+    /// bytes, and discards a message of which no chunk arrived within `timeout`.
+    pub fn new(max_message_size: usize, timeout: Duration) -> Self {
+        LargeMessageReceiver {
+            max_message_size,
+            timeout,
+            message: None,
+            completed: None,
+            ack_pending: false,
+        }
+    }
+
+    /// Places the chunk in the message it is part of.
+    pub fn process_chunk(
+        &mut self,
+        header: ChunkHeader,
+        payload: &[u8],
+        time: Instant,
+    ) -> Result<Option<ReceivedChunk>> {
+        let message_size = header.message_size() as usize;
+        if message_size > self.max_message_size {
+            Err(PacketErrorKind::ExceededMaxPacketSize)?
+        }
+
+        let chunk_count = header.chunk_count();
+        if header.index() >= chunk_count || header.chunk_size() == 0 {
+            Err(FragmentErrorKind::InvalidFragmentId)?
+        }
+
+        let start = header.index() as usize * usize::from(header.chunk_size());
+        let end = (start + usize::from(header.chunk_size())).min(message_size);
+        if payload.len() != end - start {
+            Err(FragmentErrorKind::InvalidFragmentSize)?
+        }
+
+        self.ack_pending = true;
+        if matches!(self.completed, Some((message_id, _)) if message_id == header.message_id()) {
+            return Ok(None);
+        }
+
+        let message = match &mut self.message {
+            Some(message) if message.header.message_id() == header.message_id() => {
+                if message.header.message_size() != header.message_size()
+                    || message.header.chunk_size() != header.chunk_size()
+                {
+                    Err(FragmentErrorKind::FragmentWithUnevenNumberOfFragemts)?
+                }
+                message
+            }
+            slot => slot.insert(IncomingMessage {
+                header,
+                chunks: BTreeMap::new(),
+                received_bytes: 0,
+                received_up_to: 0,
+                last_received: time,
+            }),
+        };
+
+        message.last_received = time;
+        if message.chunks.contains_key(&header.index()) {
+            return Ok(None);
+        }
+
+        message.chunks.insert(header.index(), Box::from(payload));
+        message.received_bytes += payload.len();
+        while message.chunks.contains_key(&message.received_up_to) {
+            message.received_up_to += 1;
+        }
+
+        let received_count = message.chunks.len() as u32;
+        if received_count == chunk_count {
+            let mut buffer = Vec::with_capacity(message_size);
+            for chunk in message.chunks.values() {
+                buffer.extend_from_slice(chunk);
+            }
+            self.completed = Some((header.message_id(), chunk_count));
+            self.message = None;
+            return Ok(Some(ReceivedChunk::Complete(buffer.into_boxed_slice())));
+        }
+
+        let percent = |chunks: u32| u64::from(chunks) * 100 / u64::from(chunk_count);
+        if percent(received_count) > percent(received_count - 1) {
+            return Ok(Some(ReceivedChunk::Progress {
+                message_id: header.message_id(),
+                received_bytes: message.received_bytes,
+                total_bytes: message_size,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Discards the partially received message if none of its chunks arrived within the timeout.
+    pub fn discard_expired(&mut self, time: Instant) {
+        if matches!(&self.message, Some(message) if message.last_received + self.timeout <= time) {
+            self.message = None;
+        }
+    }
+
+    /// Returns the time at which the partially received message times out, if any.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.message
+            .as_ref()
+            .map(|message| message.last_received + self.timeout)
+    }
+
+    /// Returns the acknowledgment of the received chunks, if chunks were received since the last
+    /// acknowledgment was taken.
+    pub fn take_ack(&mut self) -> Option<ChunkAckHeader> {
+        if !std::mem::replace(&mut self.ack_pending, false) {
+            return None;
+        }
+
+        match (&self.message, self.completed) {
+            (Some(message), _) => {
+                let mut ack_field = 0;
+                for offset in 0..CHUNK_ACK_FIELD_SIZE {
+                    let index = message.received_up_to + 1 + offset;
+                    if message.chunks.contains_key(&index) {
+                        ack_field |= 1 << offset;
+                    }
+                }
+                Some(ChunkAckHeader::new(
+                    message.header.message_id(),
+                    message.received_up_to,
+                    ack_field,
+                ))
+            }
+            (None, Some((message_id, chunk_count))) => {
+                Some(ChunkAckHeader::new(message_id, chunk_count, 0))
+            }
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LargeMessageReceiver, LargeMessageSender, ReceivedChunk};
+    use crate::packet::header::{ChunkAckHeader, ChunkHeader};
+    use crate::packet::{PacketId, PacketReader};
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn read_chunk(chunk: &[u8]) -> (ChunkHeader, Box<[u8]>) {
+        let mut reader = PacketReader::new(chunk);
+        reader.read_standard_header().unwrap();
+        let header = reader.read_chunk_header().unwrap();
+        (header, reader.read_payload())
+    }
+
+    #[test]
+    fn only_the_window_is_in_flight() {
+        let mut sender = LargeMessageSender::new(10, 4);
+        sender.push(PacketId(0), &[1; 95]);
+        let time = Instant::now();
+
+        let chunks = sender.due_chunks(time, TIMEOUT);
+        let indices: Vec<_> = chunks.iter().map(|c| read_chunk(c).0.index()).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+        assert!(sender.due_chunks(time, TIMEOUT).is_empty());
+
+        // The window moves once the first chunks are acknowledged.
+        assert_eq!(sender.process_ack(&ChunkAckHeader::new(0, 2, 0)), None);
+        let indices: Vec<_> = sender
+            .due_chunks(time, TIMEOUT)
+            .iter()
+            .map(|c| read_chunk(c).0.index())
+            .collect();
+        assert_eq!(indices, vec![4, 5]);
+    }
+
+    #[test]
+    fn only_missing_chunks_are_resent() {
+        let mut sender = LargeMessageSender::new(10, 8);
+        sender.push(PacketId(0), &[1; 50]);
+        let time = Instant::now();
+        assert_eq!(sender.due_chunks(time, TIMEOUT).len(), 5);
+
+        // Chunks 0, 2 and 4 arrived.
+        sender.process_ack(&ChunkAckHeader::new(0, 1, 0b101));
+        assert_eq!(sender.next_resend_time(TIMEOUT), Some(time + TIMEOUT));
+        assert!(sender.due_chunks(time, TIMEOUT).is_empty());
+
+        let indices: Vec<_> = sender
+            .due_chunks(time + TIMEOUT, TIMEOUT)
+            .iter()
+            .map(|c| read_chunk(c).0.index())
+            .collect();
+        assert_eq!(indices, vec![1, 3]);
+
+        assert_eq!(
+            sender.process_ack(&ChunkAckHeader::new(0, 5, 0)),
+            Some(PacketId(0))
+        );
+        assert!(sender.messages.is_empty());
+    }
+
+    #[test]
+    fn message_is_reassembled_from_chunks_in_any_order() {
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut sender = LargeMessageSender::new(64, 100);
+        sender.push(PacketId(0), &payload);
+        let time = Instant::now();
+        let mut chunks = sender.due_chunks(time, TIMEOUT);
+        chunks.reverse();
+
+        let mut receiver = LargeMessageReceiver::new(payload.len(), TIMEOUT);
+        let mut results = Vec::new();
+        for chunk in chunks.iter() {
+            let (header, chunk_payload) = read_chunk(chunk);
+            results.extend(
+                receiver
+                    .process_chunk(header, &chunk_payload, time)
+                    .unwrap(),
+            );
+        }
+
+        match results.pop() {
+            Some(ReceivedChunk::Complete(message)) => assert_eq!(&*message, payload.as_slice()),
+            _ => panic!("Expected the complete message"),
+        }
+        assert_eq!(results.len(), 15);
+        assert_eq!(
+            results[0],
+            ReceivedChunk::Progress {
+                message_id: 0,
+                received_bytes: 40,
+                total_bytes: 1000
+            }
+        );
+
+        // Resent chunks of the completed message are acknowledged again.
+        assert_eq!(receiver.take_ack(), Some(ChunkAckHeader::new(0, 16, 0)));
+        let (header, chunk_payload) = read_chunk(&chunks[0]);
+        assert_eq!(
+            receiver
+                .process_chunk(header, &chunk_payload, time)
+                .unwrap(),
+            None
+        );
+        assert_eq!(receiver.take_ack(), Some(ChunkAckHeader::new(0, 16, 0)));
+        assert_eq!(receiver.take_ack(), None);
+    }
+
+    #[test]
+    fn acknowledgment_reports_chunks_after_the_first_missing_chunk() {
+        let mut receiver = LargeMessageReceiver::new(1000, TIMEOUT);
+        let time = Instant::now();
+        for index in &[0, 2, 3] {
+            receiver
+                .process_chunk(ChunkHeader::new(7, *index, 100, 10), &[0; 10], time)
+                .unwrap();
+        }
+        assert_eq!(receiver.take_ack(), Some(ChunkAckHeader::new(7, 1, 0b11)));
+    }
+
+    #[test]
+    fn invalid_chunks_are_rejected() {
+        let mut receiver = LargeMessageReceiver::new(100, TIMEOUT);
+        let time = Instant::now();
+        assert!(receiver
+            .process_chunk(ChunkHeader::new(0, 0, 101, 10), &[0; 10], time)
+            .is_err());
+        assert!(receiver
+            .process_chunk(ChunkHeader::new(0, 10, 100, 10), &[0; 10], time)
+            .is_err());
+        assert!(receiver
+            .process_chunk(ChunkHeader::new(0, 9, 95, 10), &[0; 10], time)
+            .is_err());
+        assert_eq!(receiver.take_ack(), None);
+    }
+
+    #[test]
+    fn stalled_message_is_discarded() {
+        let mut receiver = LargeMessageReceiver::new(1000, TIMEOUT);
+        let time = Instant::now();
+        receiver
+            .process_chunk(ChunkHeader::new(3, 0, 100, 10), &[0; 10], time)
+            .unwrap();
+        receiver
+            .process_chunk(
+                ChunkHeader::new(3, 1, 100, 10),
+                &[0; 10],
+                time + TIMEOUT / 2,
+            )
+            .unwrap();
+        assert_eq!(receiver.next_expiry(), Some(time + TIMEOUT / 2 + TIMEOUT));
+
+        receiver.discard_expired(time + TIMEOUT);
+        assert_eq!(receiver.message.as_ref().unwrap().received_bytes, 20);
+
+        receiver.discard_expired(time + TIMEOUT / 2 + TIMEOUT);
+        assert!(receiver.message.is_none());
+        assert_eq!(receiver.next_expiry(), None);
+    }
+
+    #[test]
+    fn only_received_chunks_are_held() {
+        let mut receiver = LargeMessageReceiver::new(1_000_000, TIMEOUT);
+        receiver
+            .process_chunk(
+                ChunkHeader::new(0, 99_999, 1_000_000, 10),
+                &[0; 10],
+                Instant::now(),
+            )
+            .unwrap();
+
+        let message = receiver.message.as_ref().unwrap();
+        assert_eq!(message.chunks.len(), 1);
+        assert_eq!(message.received_bytes, 10);
+    }
+}
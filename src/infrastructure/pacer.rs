@@ -0,0 +1,266 @@
+use crate::config::Config;
+use crate::packet::SequenceNumber;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Holds back the outgoing packets of a connection which exceed its send rate, and releases them
+/// across later polls.
+///
+/// Two limits apply: the packets per second the congestion controller allows, and the bytes per
+/// second of `Config::max_send_rate`, which is enforced by a token bucket of
+/// `Config::send_burst_size` bytes.
+///
+/// The packets are held back along with the sequence number they are acknowledged with, so they
+/// are only considered sent once they are released.
+pub struct Pacer {
+    packets: VecDeque<(Box<[u8]>, Option<SequenceNumber>)>,
+    max_packets: usize,
+    // The time at which the congestion controller allows the next packet to be sent.
+    next_send: Instant,
+    token_bucket: Option<TokenBucket>,
+}
+
+impl Pacer {
+    /// Constructs a new `Pacer` which has nothing held back.
+    pub fn new(config: &Config, time: Instant) -> Self {
+        Pacer {
+            packets: VecDeque::new(),
+            max_packets: config.max_paced_packets,
+            next_send: time,
+            token_bucket: config
+                .max_send_rate
+                .map(|rate| TokenBucket::new(rate, config.send_burst_size, time)),
+        }
+    }
+
+    /// Holds the packet back behind the packets which are waiting already.
+    pub fn push(&mut self, packet: Box<[u8]>, sequence: Option<SequenceNumber>, time: Instant) {
+        // Sending time which was not used does not add up, so packets are not sent in bursts.
+        if self.packets.is_empty() && self.next_send < time {
+            self.next_send = time;
+        }
+        self.packets.push_back((packet, sequence));
+    }
+
+    /// Returns true if `Config::max_paced_packets` packets are held back.
+    pub fn is_full(&self) -> bool {
+        self.packets.len() >= self.max_packets
+    }
+
+    /// Returns the held back packets which may be sent by now, given the packets per second the
+    /// congestion controller allows, along with their sequence numbers.
+    pub fn take_due(
+        &mut self,
+        time: Instant,
+        send_rate: Option<f64>,
+    ) -> Vec<(Box<[u8]>, Option<SequenceNumber>)> {
+        let interval = Self::interval(send_rate);
+        let mut packets = Vec::new();
+
+        while let Some((packet, _)) = self.packets.front() {
+            if self.next_send > time {
+                break;
+            }
+            if let Some(token_bucket) = &mut self.token_bucket {
+                if !token_bucket.try_consume(packet.len(), time) {
+                    break;
+                }
+            }
+
+            packets.extend(self.packets.pop_front());
+            if let Some(interval) = interval {
+                self.next_send += interval;
+            }
+        }
+
+        if interval.is_none() && self.next_send < time {
+            self.next_send = time;
+        }
+        packets
+    }
+
+    /// Returns the time at which the next held back packet may be sent, if any are held back.
+    pub fn next_send_time(&self) -> Option<Instant> {
+        let (packet, _) = self.packets.front()?;
+        Some(match &self.token_bucket {
+            Some(token_bucket) => self.next_send.max(token_bucket.available_at(packet.len())),
+            None => self.next_send,
+        })
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    // Returns the time between two packets at the given packets per second, if it is limited.
+    fn interval(send_rate: Option<f64>) -> Option<Duration> {
+        match send_rate {
+            Some(send_rate) if send_rate > 0. => Some(Duration::from_secs_f64(1. / send_rate)),
+            _ => None,
+        }
+    }
+}
+
+// Allows `rate` bytes per second, of which up to `burst_size` bytes may be sent at once.
+struct TokenBucket {
+    rate: f64,
+    burst_size: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, burst_size: usize, time: Instant) -> Self {
+        TokenBucket {
+            rate: f64::from(rate),
+            burst_size: burst_size as f64,
+            tokens: burst_size as f64,
+            last_refill: time,
+        }
+    }
+
+    // Takes the tokens for a packet of the given size, if there are enough. A packet larger than
+    // the burst size may be sent once the bucket is full, the bucket is in debt afterwards.
+    fn try_consume(&mut self, bytes: usize, time: Instant) -> bool {
+        let elapsed = time.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst_size);
+        self.last_refill = self.last_refill.max(time);
+
+        let required = (bytes as f64).min(self.burst_size);
+        if self.tokens < required {
+            return false;
+        }
+        self.tokens -= bytes as f64;
+        true
+    }
+
+    // Returns the time at which there are enough tokens for a packet of the given size.
+    fn available_at(&self, bytes: usize) -> Instant {
+        let missing = (bytes as f64).min(self.burst_size) - self.tokens;
+        if missing <= 0. || self.rate <= 0. {
+            return self.last_refill;
+        }
+        self.last_refill + Duration::from_secs_f64(missing / self.rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pacer;
+    use crate::config::Config;
+    use std::time::{Duration, Instant};
+
+    fn packets(count: usize, size: usize) -> Vec<Box<[u8]>> {
+        (0..count)
+            .map(|_| vec![0; size].into_boxed_slice())
+            .collect()
+    }
+
+    #[test]
+    fn packets_are_not_held_back_without_limits() {
+        let time = Instant::now();
+        let mut pacer = Pacer::new(&Config::default(), time);
+        for packet in packets(10, 100) {
+            pacer.push(packet, None, time);
+        }
+
+        assert_eq!(pacer.take_due(time, None).len(), 10);
+        assert_eq!(pacer.next_send_time(), None);
+    }
+
+    #[test]
+    fn send_rate_spreads_packets_over_time() {
+        let time = Instant::now();
+        let mut pacer = Pacer::new(&Config::default(), time);
+        for packet in packets(10, 100) {
+            pacer.push(packet, None, time);
+        }
+
+        assert_eq!(pacer.take_due(time, Some(100.)).len(), 1);
+        assert_eq!(
+            pacer.next_send_time(),
+            Some(time + Duration::from_millis(10))
+        );
+        assert_eq!(
+            pacer
+                .take_due(time + Duration::from_millis(45), Some(100.))
+                .len(),
+            4
+        );
+        assert_eq!(pacer.len(), 5);
+    }
+
+    #[test]
+    fn pacer_is_full_at_max_paced_packets() {
+        let config = Config {
+            max_paced_packets: 2,
+            ..Config::default()
+        };
+        let time = Instant::now();
+        let mut pacer = Pacer::new(&config, time);
+        for packet in packets(2, 100) {
+            pacer.push(packet, None, time);
+        }
+        assert!(pacer.is_full());
+
+        assert_eq!(pacer.take_due(time, Some(1.)).len(), 1);
+        assert!(!pacer.is_full());
+    }
+
+    #[test]
+    fn token_bucket_limits_bytes_per_second() {
+        let config = Config {
+            max_send_rate: Some(10_000),
+            send_burst_size: 3000,
+            ..Config::default()
+        };
+        let time = Instant::now();
+        let mut pacer = Pacer::new(&config, time);
+        for packet in packets(10, 1000) {
+            pacer.push(packet, None, time);
+        }
+
+        // The burst is sent at once, after which a packet of 1000 bytes is allowed every 100 milliseconds.
+        assert_eq!(pacer.take_due(time, None).len(), 3);
+        assert_eq!(
+            pacer.next_send_time(),
+            Some(time + Duration::from_millis(100))
+        );
+        assert!(pacer
+            .take_due(time + Duration::from_millis(99), None)
+            .is_empty());
+        assert_eq!(
+            pacer
+                .take_due(time + Duration::from_millis(250), None)
+                .len(),
+            2
+        );
+
+        // Unused tokens add up to the burst size at most.
+        assert_eq!(
+            pacer.take_due(time + Duration::from_secs(10), None).len(),
+            3
+        );
+        assert_eq!(pacer.len(), 2);
+    }
+
+    #[test]
+    fn packets_larger_than_the_burst_are_sent_once_the_bucket_is_full() {
+        let config = Config {
+            max_send_rate: Some(1000),
+            send_burst_size: 500,
+            ..Config::default()
+        };
+        let time = Instant::now();
+        let mut pacer = Pacer::new(&config, time);
+        for packet in packets(2, 1000) {
+            pacer.push(packet, None, time);
+        }
+
+        assert_eq!(pacer.take_due(time, None).len(), 1);
+        // The bucket repays the excess 500 bytes before it fills up again.
+        assert_eq!(pacer.next_send_time(), Some(time + Duration::from_secs(1)));
+    }
+}
@@ -23,7 +23,6 @@
 #![allow(clippy::trivially_copy_pass_by_ref)]
 
 mod config;
-mod either;
 mod error;
 mod infrastructure;
 mod net;
@@ -38,6 +37,16 @@ mod throughput;
 pub use self::throughput::ThroughputMonitoring;
 
 pub use self::config::Config;
-pub use self::error::{ErrorKind, Result};
-pub use self::net::{LinkConditioner, Socket, SocketEvent};
-pub use self::packet::{DeliveryGuarantee, OrderingGuarantee, Packet};
+pub use self::error::{BackpressureErrorKind, ConfigErrorKind, ErrorKind, Result};
+pub use self::infrastructure::{
+    BinaryCongestionController, CongestionControl, CongestionController, ReassemblyStats,
+};
+#[cfg(feature = "async")]
+pub use self::net::AsyncSocket;
+pub use self::net::{
+    BurstLoss, ChannelNetwork, ChannelTransport, ClientData, ConnectToken, ConnectionStats,
+    DatagramTransport, DisconnectReason, Jitter, LinkConditioner, LinkScript, MessageProgress,
+    NetworkConditions, NetworkQuality, NetworkSimulator, PacketSender, Preset, ScriptCommand,
+    SimulatedTransport, Socket, SocketEvent, TransportReadiness, TransportWaker,
+};
+pub use self::packet::{DeliveryGuarantee, OrderingGuarantee, Packet, PacketId};
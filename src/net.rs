@@ -1,17 +1,34 @@
 //! This module provides the logic between the low-level abstract types and the types that the user will be interacting with.
 //! You can think of the socket, connection management, congestion control.
 
+#[cfg(feature = "async")]
+mod async_socket;
+mod connect_token;
 mod connection;
 mod events;
+mod handshake;
 mod link_conditioner;
+mod poller;
 mod quality;
+mod simulator;
 mod socket;
+mod stats;
+mod transport;
 mod virtual_connection;
 
 pub mod constants;
 
-pub use self::events::SocketEvent;
-pub use self::link_conditioner::LinkConditioner;
+#[cfg(feature = "async")]
+pub use self::async_socket::AsyncSocket;
+pub use self::connect_token::{ClientData, ConnectToken};
+pub use self::events::{DisconnectReason, MessageProgress, SocketEvent};
+pub use self::link_conditioner::{
+    BurstLoss, Jitter, LinkConditioner, LinkScript, Preset, ScriptCommand,
+};
+pub use self::poller::{PacketSender, TransportReadiness, TransportWaker};
 pub use self::quality::{NetworkQuality, RttMeasurer};
+pub use self::simulator::{NetworkConditions, NetworkSimulator, SimulatedTransport};
 pub use self::socket::Socket;
+pub use self::stats::ConnectionStats;
+pub use self::transport::{ChannelNetwork, ChannelTransport, DatagramTransport};
 pub use self::virtual_connection::VirtualConnection;
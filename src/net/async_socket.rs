@@ -0,0 +1,378 @@
+//! This module provides an asynchronous front-end for [Socket], which can be used from any `Future`
+//! executor.
+//!
+//! The socket is driven by a dedicated background thread, which runs the polling loop of the socket
+//! until the `AsyncSocket` is dropped. Tasks waiting for an event are woken as soon as the driver
+//! thread has emitted one, and tasks waiting for room in a full packet queue as soon as it emptied
+//! the queue.
+
+use crate::{
+    config::Config,
+    error::{BackpressureErrorKind, ErrorKind, Result},
+    net::{socket::Socket, DatagramTransport, PacketSender, SocketEvent},
+    packet::{Packet, PacketId},
+};
+use crossbeam_channel::{Receiver, SendError, TryRecvError, TrySendError};
+use futures_core::Stream;
+use std::{
+    future::Future,
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll as TaskPoll, Waker},
+    thread,
+    time::Instant,
+};
+
+/// An asynchronous wrapper around [Socket].
+///
+/// Packets are sent with [AsyncSocket::send] and events are received with [AsyncSocket::recv], with
+/// [AsyncSocket::poll_recv], or by using the socket as a `Stream<Item = SocketEvent>`.
+///
+/// Every `AsyncSocket` runs the polling loop of its socket on a dedicated OS thread, named
+/// `laminar-async-socket`, rather than on the reactor of the executor. Dropping the `AsyncSocket`
+/// signals the thread to stop without waiting for it, so the transport is released shortly after.
+#[derive(Debug)]
+pub struct AsyncSocket {
+    local_addr: SocketAddr,
+    packet_sender: PacketSender,
+    event_receiver: Receiver<SocketEvent>,
+    shared: Arc<Shared>,
+}
+
+// The state shared between an `AsyncSocket` and its driver thread.
+#[derive(Debug)]
+struct Shared {
+    // The task waiting for an event, if any.
+    waker: Mutex<Option<Waker>>,
+    // The tasks waiting for room in the packet queue.
+    send_wakers: Mutex<Vec<Waker>>,
+    closed: AtomicBool,
+}
+
+impl AsyncSocket {
+    /// Binds to the given addresses and starts driving the socket.
+    pub fn bind<A: ToSocketAddrs>(addresses: A) -> Result<Self> {
+        AsyncSocket::bind_with_config(addresses, Config::default())
+    }
+
+    /// Binds to the given addresses with the given config and starts driving the socket.
+    pub fn bind_with_config<A: ToSocketAddrs>(addresses: A, config: Config) -> Result<Self> {
+        AsyncSocket::from_socket(Socket::bind_with_config(addresses, config)?)
+    }
+
+    /// Starts driving an existing socket, which may run on any transport. The socket is switched to
+    /// non-blocking mode, since the driver thread only reads from it when it is readable.
+    pub fn from_socket<T>(mut socket: Socket<T>) -> Result<Self>
+    where
+        T: DatagramTransport + Send + 'static,
+    {
+        socket.set_nonblocking()?;
+
+        let shared = Arc::new(Shared {
+            waker: Mutex::new(None),
+            send_wakers: Mutex::new(Vec::new()),
+            closed: AtomicBool::new(false),
+        });
+
+        let local_addr = socket.local_addr()?;
+        let packet_sender = socket.get_packet_sender();
+        let event_receiver = socket.get_event_receiver();
+
+        // The driver thread is detached, it stops by itself once the `AsyncSocket` is dropped.
+        {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("laminar-async-socket".to_owned())
+                .spawn(move || drive(socket, &shared))?;
+        }
+
+        Ok(AsyncSocket {
+            local_addr,
+            packet_sender,
+            event_receiver,
+            shared,
+        })
+    }
+
+    /// Returns the local socket address.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Sends a packet. The packet is handed to the driver thread, which sends it right away.
+    ///
+    /// Returns the id with which the `Acked` and `Lost` events refer to the packet. When the packet
+    /// queue is full and `block_on_full_packet_queue` is enabled, the task waits until the driver
+    /// thread made room, without blocking the thread it runs on.
+    pub async fn send(&self, packet: Packet) -> Result<PacketId> {
+        SendPacket {
+            socket: self,
+            id: self.packet_sender.next_id(),
+            packet: Some(packet),
+        }
+        .await
+    }
+
+    /// Receives the next event. Returns `None` if the driver thread stopped.
+    pub async fn recv(&mut self) -> Option<SocketEvent> {
+        Recv { socket: self }.await
+    }
+
+    /// Polls for the next event, registering the task to be woken when one arrives if there is none
+    /// yet. Returns `Ready(None)` if the driver thread stopped.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> TaskPoll<Option<SocketEvent>> {
+        if let Some(event) = self.try_recv() {
+            return event;
+        }
+
+        *self.shared.waker.lock().expect("Waker lock poisoned") = Some(cx.waker().clone());
+
+        // An event may have been emitted before the waker was stored.
+        self.try_recv().unwrap_or(TaskPoll::Pending)
+    }
+
+    fn try_recv(&self) -> Option<TaskPoll<Option<SocketEvent>>> {
+        match self.event_receiver.try_recv() {
+            Ok(event) => Some(TaskPoll::Ready(Some(event))),
+            Err(TryRecvError::Disconnected) => Some(TaskPoll::Ready(None)),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+}
+
+impl Stream for AsyncSocket {
+    type Item = SocketEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Option<SocketEvent>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl Drop for AsyncSocket {
+    // Signals the driver thread to stop, without blocking the executor until it did.
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.packet_sender.wake();
+    }
+}
+
+// The future returned by `AsyncSocket::recv`.
+struct Recv<'a> {
+    socket: &'a mut AsyncSocket,
+}
+
+impl<'a> Future for Recv<'a> {
+    type Output = Option<SocketEvent>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Self::Output> {
+        self.socket.poll_recv(cx)
+    }
+}
+
+// The future returned by `AsyncSocket::send`.
+struct SendPacket<'a> {
+    socket: &'a AsyncSocket,
+    id: PacketId,
+    packet: Option<Packet>,
+}
+
+impl<'a> SendPacket<'a> {
+    fn try_send(&mut self, packet: Packet) -> TaskPoll<Result<PacketId>> {
+        match self.socket.packet_sender.try_send_with_id(self.id, packet) {
+            Ok(id) => TaskPoll::Ready(Ok(id)),
+            Err(TrySendError::Full(packet)) if self.socket.packet_sender.blocks_when_full() => {
+                self.packet = Some(packet);
+                TaskPoll::Pending
+            }
+            Err(TrySendError::Full(_)) => {
+                TaskPoll::Ready(Err(BackpressureErrorKind::PacketQueueFull.into()))
+            }
+            Err(TrySendError::Disconnected(packet)) => TaskPoll::Ready(Err(ErrorKind::SendError(
+                SendError(SocketEvent::Packet(packet)),
+            ))),
+        }
+    }
+}
+
+impl<'a> Future for SendPacket<'a> {
+    type Output = Result<PacketId>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Self::Output> {
+        let packet = self
+            .packet
+            .take()
+            .expect("SendPacket polled after completion");
+        if let TaskPoll::Ready(result) = self.try_send(packet) {
+            return TaskPoll::Ready(result);
+        }
+
+        self.socket
+            .shared
+            .send_wakers
+            .lock()
+            .expect("Waker lock poisoned")
+            .push(cx.waker().clone());
+
+        // The driver thread may have emptied the queue before the waker was stored.
+        let packet = self.packet.take().expect("A pending send keeps its packet");
+        self.try_send(packet)
+    }
+}
+
+// Runs the socket until the `AsyncSocket` is dropped.
+fn drive<T: DatagramTransport>(mut socket: Socket<T>, shared: &Shared) {
+    let event_receiver = socket.get_event_receiver();
+    let polling_timeout = socket.config().socket_polling_timeout;
+
+    while !shared.closed.load(Ordering::SeqCst) {
+        socket.manual_poll(Instant::now());
+
+        // Polling emptied the packet queue.
+        for waker in shared
+            .send_wakers
+            .lock()
+            .expect("Waker lock poisoned")
+            .drain(..)
+        {
+            waker.wake();
+        }
+
+        if !event_receiver.is_empty() {
+            if let Some(waker) = shared.waker.lock().expect("Waker lock poisoned").take() {
+                waker.wake();
+            }
+        }
+
+        socket.wait_for_readiness(polling_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncSocket;
+    use crate::{Config, Packet, SocketEvent};
+    use futures_core::Stream;
+    use std::{
+        future::{self, Future},
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll, Wake},
+        thread::{self, Thread},
+    };
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Runs the future on the current thread, parking it until the future is woken.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn packets_are_exchanged_asynchronously() {
+        let mut server = AsyncSocket::bind("127.0.0.1:12398").unwrap();
+        let mut client = AsyncSocket::bind("127.0.0.1:12399").unwrap();
+
+        let server_addr = server.local_addr();
+        let client_addr = client.local_addr();
+
+        block_on(async {
+            client
+                .send(Packet::reliable_unordered(server_addr, vec![1, 2, 3]))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                server.recv().await,
+                Some(SocketEvent::Connect(client_addr, None))
+            );
+            assert_eq!(
+                server.recv().await,
+                Some(SocketEvent::Packet(Packet::reliable_unordered(
+                    client_addr,
+                    vec![1, 2, 3]
+                )))
+            );
+            assert_eq!(
+                client.recv().await,
+                Some(SocketEvent::Connect(server_addr, None))
+            );
+        });
+    }
+
+    #[test]
+    fn events_can_be_streamed() {
+        let mut server = AsyncSocket::bind("127.0.0.1:12404").unwrap();
+        let client = AsyncSocket::bind("127.0.0.1:12405").unwrap();
+
+        let server_addr = server.local_addr();
+        let client_addr = client.local_addr();
+
+        block_on(async {
+            client
+                .send(Packet::reliable_unordered(server_addr, vec![1, 2, 3]))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                future::poll_fn(|cx| Pin::new(&mut server).poll_next(cx)).await,
+                Some(SocketEvent::Connect(client_addr, None))
+            );
+            assert_eq!(
+                future::poll_fn(|cx| Pin::new(&mut server).poll_next(cx)).await,
+                Some(SocketEvent::Packet(Packet::reliable_unordered(
+                    client_addr,
+                    vec![1, 2, 3]
+                )))
+            );
+        });
+    }
+
+    #[test]
+    fn send_waits_for_room_in_a_full_packet_queue() {
+        let config = Config {
+            packet_queue_size: Some(1),
+            block_on_full_packet_queue: true,
+            ..Config::default()
+        };
+        let mut server = AsyncSocket::bind("127.0.0.1:12402").unwrap();
+        let client = AsyncSocket::bind_with_config("127.0.0.1:12403", config).unwrap();
+
+        let server_addr = server.local_addr();
+
+        block_on(async {
+            for number in 0..20 {
+                client
+                    .send(Packet::reliable_ordered(server_addr, vec![number], None))
+                    .await
+                    .unwrap();
+            }
+
+            let mut payloads = Vec::new();
+            while payloads.len() < 20 {
+                if let Some(SocketEvent::Packet(packet)) = server.recv().await {
+                    payloads.push(packet.payload()[0]);
+                }
+            }
+            assert_eq!(payloads, (0..20).collect::<Vec<_>>());
+        });
+    }
+}
@@ -0,0 +1,456 @@
+//! This module provides connect tokens, with which a backend (e.g. a matchmaker) authorizes clients
+//! to connect to its dedicated servers.
+//!
+//! The backend and the servers share a private key, which clients never get to see. The backend
+//! generates a token for a client, containing the id of the client, the servers it may connect to,
+//! the moment the token expires, the encryption keys of the connection and application specific user
+//! data. The client id, the user data, the addresses and the keys are encrypted with the private key,
+//! so the client can only pass this private part on to the server along with its connection request.
+//! The servers and the keys are also readable by the client, so it knows where to connect to and how
+//! to encrypt its packets. The keys of each connection are derived from these keys and the cookie of
+//! its handshake, so reconnecting with the same token does not reuse the keys of a connection.
+
+use crate::error::{ConnectTokenErrorKind, Result};
+use crate::infrastructure::{ConnectionKeys, EncryptionKey, KEY_SIZE};
+use crate::net::handshake::{derive_token_keys, ChallengeCookie};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use std::{
+    io::{self, Cursor, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The maximal number of server addresses in a connect token.
+pub const MAX_SERVER_ADDRESSES: usize = 8;
+/// The maximal size of the user data in a connect token.
+pub const MAX_USER_DATA_SIZE: usize = 256;
+
+/// The size of the nonce the private part of a token is encrypted with.
+const NONCE_SIZE: usize = 24;
+/// The size of the expire timestamp of a token.
+const TIMESTAMP_SIZE: usize = 8;
+
+/// Gives a client permission to connect to the servers that share the private key it was generated
+/// with, until it expires.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConnectToken {
+    expire_timestamp: u64,
+    server_addresses: Vec<SocketAddr>,
+    client_to_server_key: EncryptionKey,
+    server_to_client_key: EncryptionKey,
+    // The encrypted part of the token, which is only readable by the servers.
+    private_data: Box<[u8]>,
+}
+
+impl ConnectToken {
+    /// Generates a token with which the client with the given id can connect to the given servers
+    /// during `valid_for`. Each server has to use the given private key as the `connect_token_key`
+    /// of its config. The user data is passed on to the server when the client connects.
+    pub fn generate(
+        private_key: &EncryptionKey,
+        client_id: u64,
+        server_addresses: &[SocketAddr],
+        valid_for: Duration,
+        user_data: &[u8],
+    ) -> Result<ConnectToken> {
+        if server_addresses.len() > MAX_SERVER_ADDRESSES {
+            return Err(ConnectTokenErrorKind::TooManyServerAddresses.into());
+        }
+        if user_data.len() > MAX_USER_DATA_SIZE {
+            return Err(ConnectTokenErrorKind::UserDataTooLarge.into());
+        }
+
+        let expire_timestamp = timestamp(SystemTime::now() + valid_for);
+        let client_to_server_key = random_key();
+        let server_to_client_key = random_key();
+
+        let mut private_part = Vec::new();
+        private_part.write_u64::<BigEndian>(client_id)?;
+        private_part.extend_from_slice(&client_to_server_key);
+        private_part.extend_from_slice(&server_to_client_key);
+        write_addresses(&mut private_part, server_addresses)?;
+        private_part.write_u16::<BigEndian>(user_data.len() as u16)?;
+        private_part.extend_from_slice(user_data);
+
+        let mut nonce = [0; NONCE_SIZE];
+        for byte in nonce.iter_mut() {
+            *byte = rand::random();
+        }
+
+        let mut private_data = Vec::with_capacity(NONCE_SIZE + TIMESTAMP_SIZE);
+        private_data.extend_from_slice(&nonce);
+        private_data.write_u64::<BigEndian>(expire_timestamp)?;
+
+        let encrypted = XChaCha20Poly1305::new(Key::from_slice(private_key))
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &private_part,
+                    aad: &private_data[NONCE_SIZE..],
+                },
+            )
+            .map_err(|_| ConnectTokenErrorKind::EncryptionFailed)?;
+        private_data.extend_from_slice(&encrypted);
+
+        Ok(ConnectToken {
+            expire_timestamp,
+            server_addresses: server_addresses.to_vec(),
+            client_to_server_key,
+            server_to_client_key,
+            private_data: private_data.into_boxed_slice(),
+        })
+    }
+
+    /// Returns the addresses of the servers the token gives permission to connect to.
+    pub fn server_addresses(&self) -> &[SocketAddr] {
+        &self.server_addresses
+    }
+
+    /// Returns the moment after which the token is no longer accepted.
+    pub fn expires(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.expire_timestamp)
+    }
+
+    /// Serializes the token, so it can be handed to the client.
+    pub fn to_bytes(&self) -> Box<[u8]> {
+        let mut bytes = Vec::new();
+        bytes
+            .write_u64::<BigEndian>(self.expire_timestamp)
+            .and_then(|_| write_addresses(&mut bytes, &self.server_addresses))
+            .and_then(|_| bytes.write_u16::<BigEndian>(self.private_data.len() as u16))
+            .expect("Writing to a vector does not fail");
+        bytes.extend_from_slice(&self.client_to_server_key);
+        bytes.extend_from_slice(&self.server_to_client_key);
+        bytes.extend_from_slice(&self.private_data);
+        bytes.into_boxed_slice()
+    }
+
+    /// Deserializes a token that was serialized with [ConnectToken::to_bytes].
+    pub fn from_bytes(bytes: &[u8]) -> Result<ConnectToken> {
+        let read_token = || -> io::Result<ConnectToken> {
+            let mut cursor = Cursor::new(bytes);
+            let expire_timestamp = cursor.read_u64::<BigEndian>()?;
+            let server_addresses = read_addresses(&mut cursor)?;
+            let mut private_data = vec![0; cursor.read_u16::<BigEndian>()? as usize];
+            let client_to_server_key = read_key(&mut cursor)?;
+            let server_to_client_key = read_key(&mut cursor)?;
+            cursor.read_exact(&mut private_data)?;
+
+            Ok(ConnectToken {
+                expire_timestamp,
+                server_addresses,
+                client_to_server_key,
+                server_to_client_key,
+                private_data: private_data.into_boxed_slice(),
+            })
+        };
+
+        read_token().map_err(|_| ConnectTokenErrorKind::InvalidToken.into())
+    }
+
+    /// Returns the encrypted part of the token, which the client sends to the server.
+    pub(crate) fn private_data(&self) -> &[u8] {
+        &self.private_data
+    }
+
+    /// Returns the keys with which the client encrypts the connection it made with the given
+    /// handshake cookie.
+    pub(crate) fn client_keys(&self, cookie: &ChallengeCookie) -> ConnectionKeys {
+        derive_token_keys(
+            &self.client_to_server_key,
+            &self.server_to_client_key,
+            cookie,
+            false,
+        )
+    }
+}
+
+impl std::fmt::Debug for ConnectToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Never write the keys to the logs.
+        f.debug_struct("ConnectToken")
+            .field("expires", &self.expires())
+            .field("server_addresses", &self.server_addresses)
+            .finish()
+    }
+}
+
+/// The data of the connect token a client connected with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientData {
+    client_id: u64,
+    user_data: Vec<u8>,
+}
+
+impl ClientData {
+    /// Returns the id of the client, as assigned by the backend that generated its token.
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    /// Returns the user data the backend put in the token of the client.
+    pub fn user_data(&self) -> &[u8] {
+        &self.user_data
+    }
+}
+
+/// The private part of a connect token, as read by a server.
+#[derive(Debug)]
+pub struct PrivateConnectToken {
+    client_data: ClientData,
+    server_addresses: Vec<SocketAddr>,
+    client_to_server_key: EncryptionKey,
+    server_to_client_key: EncryptionKey,
+}
+
+impl PrivateConnectToken {
+    /// Decrypts the private part of a connect token. Returns `None` if the token was not generated
+    /// with the given private key, or if it expired before `now`.
+    pub fn open(private_key: &EncryptionKey, private_data: &[u8], now: SystemTime) -> Option<Self> {
+        if private_data.len() < NONCE_SIZE + TIMESTAMP_SIZE {
+            return None;
+        }
+
+        let (nonce, rest) = private_data.split_at(NONCE_SIZE);
+        let (expire_timestamp, encrypted) = rest.split_at(TIMESTAMP_SIZE);
+
+        let private_part = XChaCha20Poly1305::new(Key::from_slice(private_key))
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: encrypted,
+                    aad: expire_timestamp,
+                },
+            )
+            .ok()?;
+
+        if Cursor::new(expire_timestamp).read_u64::<BigEndian>().ok()? <= timestamp(now) {
+            return None;
+        }
+
+        let read_token = || -> io::Result<PrivateConnectToken> {
+            let mut cursor = Cursor::new(&private_part[..]);
+            let client_id = cursor.read_u64::<BigEndian>()?;
+            let client_to_server_key = read_key(&mut cursor)?;
+            let server_to_client_key = read_key(&mut cursor)?;
+            let server_addresses = read_addresses(&mut cursor)?;
+            let mut user_data = vec![0; cursor.read_u16::<BigEndian>()? as usize];
+            cursor.read_exact(&mut user_data)?;
+
+            Ok(PrivateConnectToken {
+                client_data: ClientData {
+                    client_id,
+                    user_data,
+                },
+                server_addresses,
+                client_to_server_key,
+                server_to_client_key,
+            })
+        };
+
+        read_token().ok()
+    }
+
+    /// Returns the client id and user data of the token.
+    pub fn client_data(&self) -> &ClientData {
+        &self.client_data
+    }
+
+    /// Returns true if the token gives permission to connect to the given server address.
+    ///
+    /// A server bound to all interfaces, with an unspecified IP address, does not know under which
+    /// addresses it is reached, so only the port has to match for it.
+    pub fn allows_server(&self, address: SocketAddr) -> bool {
+        if address.ip().is_unspecified() {
+            return self
+                .server_addresses
+                .iter()
+                .any(|server_address| server_address.port() == address.port());
+        }
+        self.server_addresses.contains(&address)
+    }
+
+    /// Returns the keys with which the server encrypts the connection made with the given handshake
+    /// cookie.
+    pub fn server_keys(&self, cookie: &ChallengeCookie) -> ConnectionKeys {
+        derive_token_keys(
+            &self.client_to_server_key,
+            &self.server_to_client_key,
+            cookie,
+            true,
+        )
+    }
+}
+
+// The number of seconds between the unix epoch and the given time.
+fn timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn random_key() -> EncryptionKey {
+    let mut key = [0; KEY_SIZE];
+    for byte in key.iter_mut() {
+        *byte = rand::random();
+    }
+    key
+}
+
+fn read_key(cursor: &mut Cursor<&[u8]>) -> io::Result<EncryptionKey> {
+    let mut key = [0; KEY_SIZE];
+    cursor.read_exact(&mut key)?;
+    Ok(key)
+}
+
+fn write_addresses(buffer: &mut Vec<u8>, addresses: &[SocketAddr]) -> io::Result<()> {
+    buffer.write_u8(addresses.len() as u8)?;
+    for address in addresses {
+        match address.ip() {
+            IpAddr::V4(ip) => {
+                buffer.write_u8(4)?;
+                buffer.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                buffer.write_u8(6)?;
+                buffer.extend_from_slice(&ip.octets());
+            }
+        }
+        buffer.write_u16::<BigEndian>(address.port())?;
+    }
+    Ok(())
+}
+
+fn read_addresses(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<SocketAddr>> {
+    let count = cursor.read_u8()? as usize;
+    if count > MAX_SERVER_ADDRESSES {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let mut addresses = Vec::with_capacity(count);
+    for _ in 0..count {
+        let ip = match cursor.read_u8()? {
+            4 => {
+                let mut octets = [0; 4];
+                cursor.read_exact(&mut octets)?;
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let mut octets = [0; 16];
+                cursor.read_exact(&mut octets)?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+        addresses.push(SocketAddr::new(ip, cursor.read_u16::<BigEndian>()?));
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectToken, PrivateConnectToken, MAX_USER_DATA_SIZE};
+    use crate::error::{ConnectTokenErrorKind, ErrorKind};
+    use crate::infrastructure::EncryptionHandler;
+    use crate::net::handshake::CHALLENGE_COOKIE_SIZE;
+    use std::net::SocketAddr;
+    use std::time::{Duration, SystemTime};
+
+    const PRIVATE_KEY: [u8; 32] = [9; 32];
+    const VALID_FOR: Duration = Duration::from_secs(30);
+
+    fn server_addresses() -> Vec<SocketAddr> {
+        vec![
+            "127.0.0.1:12345".parse().unwrap(),
+            "[::1]:12345".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn token_can_be_opened_by_server() {
+        let token =
+            ConnectToken::generate(&PRIVATE_KEY, 42, &server_addresses(), VALID_FOR, b"red")
+                .unwrap();
+
+        let private_token =
+            PrivateConnectToken::open(&PRIVATE_KEY, token.private_data(), SystemTime::now())
+                .unwrap();
+
+        assert_eq!(private_token.client_data().client_id(), 42);
+        assert_eq!(private_token.client_data().user_data(), b"red");
+        assert!(private_token.allows_server(server_addresses()[1]));
+        assert!(!private_token.allows_server("127.0.0.1:12346".parse().unwrap()));
+        assert!(private_token.allows_server("0.0.0.0:12345".parse().unwrap()));
+        assert!(!private_token.allows_server("[::]:12346".parse().unwrap()));
+        let cookie = [7; CHALLENGE_COOKIE_SIZE];
+        assert!(private_token.server_keys(&cookie) != token.client_keys(&cookie));
+    }
+
+    #[test]
+    fn each_connection_with_a_token_has_other_keys() {
+        let token =
+            ConnectToken::generate(&PRIVATE_KEY, 42, &server_addresses(), VALID_FOR, b"red")
+                .unwrap();
+        let private_token =
+            PrivateConnectToken::open(&PRIVATE_KEY, token.private_data(), SystemTime::now())
+                .unwrap();
+        let cookie = [7; CHALLENGE_COOKIE_SIZE];
+        let other_cookie = [8; CHALLENGE_COOKIE_SIZE];
+
+        let mut client = EncryptionHandler::new(&token.client_keys(&cookie));
+        let mut server = EncryptionHandler::new(&private_token.server_keys(&cookie));
+        let encrypted = client.encrypt(b"Hello world!").unwrap();
+        assert_eq!(&*server.decrypt(&encrypted).unwrap(), b"Hello world!");
+
+        // A reconnect with the same token starts over with the same nonces, but other keys.
+        let mut reconnected_server =
+            EncryptionHandler::new(&private_token.server_keys(&other_cookie));
+        assert!(reconnected_server.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn token_survives_serialization() {
+        let token =
+            ConnectToken::generate(&PRIVATE_KEY, 42, &server_addresses(), VALID_FOR, b"red")
+                .unwrap();
+
+        let bytes = token.to_bytes();
+
+        assert_eq!(ConnectToken::from_bytes(&bytes).unwrap(), token);
+        assert!(ConnectToken::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn token_is_rejected_with_other_key() {
+        let token =
+            ConnectToken::generate(&PRIVATE_KEY, 42, &server_addresses(), VALID_FOR, &[]).unwrap();
+
+        assert!(
+            PrivateConnectToken::open(&[8; 32], token.private_data(), SystemTime::now()).is_none()
+        );
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let token =
+            ConnectToken::generate(&PRIVATE_KEY, 42, &server_addresses(), VALID_FOR, &[]).unwrap();
+
+        let later = SystemTime::now() + VALID_FOR * 2;
+
+        assert!(PrivateConnectToken::open(&PRIVATE_KEY, token.private_data(), later).is_none());
+    }
+
+    #[test]
+    fn too_much_user_data_is_rejected() {
+        let user_data = [0; MAX_USER_DATA_SIZE + 1];
+
+        match ConnectToken::generate(&PRIVATE_KEY, 42, &server_addresses(), VALID_FOR, &user_data) {
+            Err(ErrorKind::ConnectTokenError(ConnectTokenErrorKind::UserDataTooLarge)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 pub use crate::net::{NetworkQuality, RttMeasurer, VirtualConnection};
 
+use crate::net::{virtual_connection::ConnectionState, ConnectionStats};
+
 use crate::config::Config;
-use crate::either::Either::{self, Left, Right};
+use crate::infrastructure::ReassemblyStats;
 use std::{
     collections::HashMap,
     net::SocketAddr,
@@ -35,19 +37,54 @@ impl ActiveConnections {
             .or_insert_with(|| VirtualConnection::new(address, config, time))
     }
 
-    /// Try to get or create a [VirtualConnection] by address. If the connection does not exist, it will be
-    /// created and returned, but not inserted into the table of active connections.
-    pub(crate) fn get_or_create_connection(
-        &mut self,
-        address: SocketAddr,
-        config: &Config,
-        time: Instant,
-    ) -> Either<&mut VirtualConnection, VirtualConnection> {
-        if let Some(connection) = self.connections.get_mut(&address) {
-            Left(connection)
-        } else {
-            Right(VirtualConnection::new(address, config, time))
-        }
+    /// Try to get a `VirtualConnection` by address.
+    pub fn get_mut(&mut self, address: &SocketAddr) -> Option<&mut VirtualConnection> {
+        self.connections.get_mut(address)
+    }
+
+    /// Returns the handshake state of the connection with the given address, if there is one.
+    pub fn connection_state(&self, address: &SocketAddr) -> Option<ConnectionState> {
+        self.connections
+            .get(address)
+            .map(|connection| connection.state())
+    }
+
+    /// Returns the number of unacknowledged reliable bytes of the connection with the given address.
+    pub fn in_flight_bytes(&self, address: &SocketAddr) -> usize {
+        self.connections
+            .get(address)
+            .map_or(0, |connection| connection.in_flight_bytes())
+    }
+
+    /// Returns true if the connection with the given address holds back `max_paced_packets` packets.
+    pub fn is_pacer_full(&self, address: &SocketAddr) -> bool {
+        self.connections
+            .get(address)
+            .is_some_and(|connection| connection.is_pacer_full())
+    }
+
+    /// Returns the counters of the discarded partially received packets of the connection with the
+    /// given address, if there is one.
+    pub fn reassembly_stats(&self, address: &SocketAddr) -> Option<ReassemblyStats> {
+        self.connections
+            .get(address)
+            .map(|connection| connection.reassembly_stats())
+    }
+
+    /// Returns a snapshot of the network health of the connection with the given address, if there
+    /// is one.
+    pub fn connection_stats(&self, address: &SocketAddr) -> Option<ConnectionStats> {
+        self.connections
+            .get(address)
+            .map(|connection| connection.stats())
+    }
+
+    /// Returns a snapshot of the network health of every connection.
+    pub fn stats(&self) -> Vec<(SocketAddr, ConnectionStats)> {
+        self.connections
+            .iter()
+            .map(|(address, connection)| (*address, connection.stats()))
+            .collect()
     }
 
     /// Removes the connection from `ActiveConnections` by socket address.
@@ -67,21 +104,49 @@ impl ActiveConnections {
             .collect()
     }
 
-    /// Check for and return `VirtualConnection`s which have not sent anything for a duration of at least `heartbeat_interval`.
+    /// Check for and return `VirtualConnection`s which have received packets they did not acknowledge yet,
+    /// or which have not sent anything for a duration of at least `heartbeat_interval`.
     pub fn heartbeat_required_connections(
         &mut self,
-        heartbeat_interval: Duration,
+        heartbeat_interval: Option<Duration>,
         time: Instant,
     ) -> impl Iterator<Item = &mut VirtualConnection> {
         self.connections
             .iter_mut()
-            .filter(move |(_, connection)| connection.last_sent(time) >= heartbeat_interval)
+            .filter(move |(_, connection)| connection.requires_heartbeat(heartbeat_interval, time))
             .map(|(_, connection)| connection)
     }
 
-    /// Returns true if the given connection exists.
-    pub fn exists(&self, address: &SocketAddr) -> bool {
-        self.connections.contains_key(&address)
+    /// Returns an iterator over all active connections.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut VirtualConnection> {
+        self.connections.values_mut()
+    }
+
+    /// Returns the number of connections which have completed the connection handshake.
+    pub fn connected_count(&self) -> usize {
+        self.connections
+            .values()
+            .filter(|connection| connection.is_connected())
+            .count()
+    }
+
+    /// Returns the first moment at which a timer of any connection expires, if there are any
+    /// connections.
+    pub fn next_timer(&self, time: Instant) -> Option<Instant> {
+        self.connections
+            .values()
+            .map(|connection| connection.next_timer(time))
+            .min()
+    }
+
+    /// Returns true if a connection was made with a connect token of the client with the given id.
+    pub fn has_client(&self, client_id: u64) -> bool {
+        self.connections.values().any(|connection| {
+            connection
+                .client_data()
+                .map(|client_data| client_data.client_id() == client_id)
+                .unwrap_or(false)
+        })
     }
 
     /// Returns the number of connected clients.
@@ -1,15 +1,21 @@
 /// The size of the fragment header.
 pub const FRAGMENT_HEADER_SIZE: u8 = 4;
-/// The size of the acknowledgment header.
+/// The size of the acknowledgment header with the default acknowledgment window of 32 packets.
 pub const ACKED_PACKET_HEADER: u8 = 8;
 /// The size of the arranging header.
 pub const ARRANGING_PACKET_HEADER: u8 = 3;
+/// The size of the header of a chunk of a large message.
+pub const CHUNK_HEADER_SIZE: u8 = 12;
+/// The size of the header which acknowledges the chunks of a large message.
+pub const CHUNK_ACK_HEADER_SIZE: u8 = 14;
 /// The size of the standard header.
 pub const STANDARD_HEADER_SIZE: u8 = 5;
 /// The ordering stream that will be used to order on if there is not ordering stream specified.
 pub const DEFAULT_ORDERING_STREAM: u8 = 255;
 /// The sequencing stream that will be used to sequence packets on if there is not sequencing stream specified.
 pub const DEFAULT_SEQUENCING_STREAM: u8 = 255;
+/// The stream written into the arranging header of reliable unordered packets, which are not arranged on a stream.
+pub const DEFAULT_DELIVERY_STREAM: u8 = 255;
 /// Default maximal number of fragments to size.
 pub const MAX_FRAGMENTS_DEFAULT: u16 = 16;
 /// Default maximal size of each fragment.
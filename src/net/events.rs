@@ -1,4 +1,8 @@
-use crate::packet::Packet;
+use crate::error::{ErrorKind, Result};
+use crate::net::{ClientData, NetworkQuality};
+use crate::packet::{Packet, PacketId};
+use crossbeam_channel::{SendError, Sender, TrySendError};
+use log::warn;
 use std::net::SocketAddr;
 
 /// Events that can occur in `laminar` and that will be pushed through the `event_receiver` returned by `Socket::bind`.
@@ -8,8 +12,67 @@ pub enum SocketEvent {
     Packet(Packet),
     /// A new client connected.
     /// Clients are uniquely identified by the ip:port combination at this layer.
-    Connect(SocketAddr),
+    /// This event is emitted once the connection handshake with the client has completed.
+    /// When the client connected with a connect token, its client id and user data are included.
+    Connect(SocketAddr, Option<ClientData>),
+    /// The connection with a client was closed, see [DisconnectReason] for why.
+    Disconnect(SocketAddr, DisconnectReason),
     /// The client has been idling for a configurable amount of time.
     /// You can control the timeout in the config.
     Timeout(SocketAddr),
+    /// The client acknowledged that it received the packet with the given id.
+    /// Only emitted when `acknowledgment_events` is enabled in the config.
+    Acked(SocketAddr, PacketId),
+    /// The packet with the given id is considered lost: it is an unreliable packet which the
+    /// acknowledgments of later packets skipped, or a reliable packet that was resent the maximal
+    /// number of times.
+    /// Only emitted when `acknowledgment_events` is enabled in the config, except for packets which
+    /// the polling loop rejected because the connection had no room for them (see
+    /// `max_in_flight_bytes` and `max_paced_packets`); those are always reported.
+    Lost(SocketAddr, PacketId),
+    /// The reliable packet with the given id was not acknowledged within its time-to-live, so it is
+    /// no longer resent. See `Packet::with_ttl`.
+    Expired(SocketAddr, PacketId),
+    /// Part of a large message from a client was received, see `Packet::large_message`.
+    /// Emitted whenever another percent of the message arrived; the complete message is emitted as a `Packet`.
+    Progress(SocketAddr, MessageProgress),
+    /// The congestion controller of the connection with a client changed its mode: it reduces the
+    /// send rate while the quality is `Bad`. Only emitted when `congestion_control` is enabled in the config.
+    CongestionChanged(SocketAddr, NetworkQuality),
+    /// The quality of the connection with a client changed: it is `Bad` while the smoothed round trip
+    /// time exceeds `rtt_max_value` or the recent packet loss exceeds `packet_loss_threshold`.
+    QualityChanged(SocketAddr, NetworkQuality),
+}
+
+/// How much of a large message was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageProgress {
+    /// Identifies the message among the large messages from the same client.
+    pub message_id: u16,
+    /// The number of bytes of the message which were received.
+    pub received_bytes: usize,
+    /// The size of the message in bytes.
+    pub total_bytes: usize,
+}
+
+/// The reason a connection was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The remote endpoint closed the connection.
+    Closed,
+    /// The remote endpoint denied our connection request.
+    Denied,
+}
+
+/// Pushes an event to the application. If the event queue is bounded and full, the event is
+/// discarded instead of blocking the socket.
+pub(crate) fn send_event(sender: &Sender<SocketEvent>, event: SocketEvent) -> Result<()> {
+    match sender.try_send(event) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => {
+            warn!("The event queue is full, an event was discarded.");
+            Ok(())
+        }
+        Err(TrySendError::Disconnected(event)) => Err(ErrorKind::SendError(SendError(event))),
+    }
 }
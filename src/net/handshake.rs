@@ -0,0 +1,462 @@
+//! This module provides the connection handshake. A connection is only established after the remote
+//! endpoint has proven that it can receive packets on the address it is sending from.
+//!
+//! 1. The client sends a `ConnectionRequest` containing a random nonce, padded to the size of a
+//!    challenge.
+//! 2. The server answers with a `ConnectionChallenge` containing a cookie.
+//! 3. The client echoes the cookie back in a `ChallengeResponse`, along with the acknowledgment
+//!    window it proposes.
+//! 4. The server verifies the cookie and answers with `ConnectionAccepted`, which contains the smaller
+//!    of both acknowledgment windows, or with `ConnectionDenied` when it does not accept new
+//!    connections.
+//!
+//! Either side can close an established connection by sending a `Disconnect` packet.
+//!
+//! The cookie is a timestamp and a random salt followed by a MAC of those and the address of the
+//! client, so the server does not have to keep any state for the client until it has answered the
+//! challenge. Because a connection request is at least as large as the challenge, the server never
+//! sends more data to an unverified address than it received from it.
+//!
+//! When both endpoints request a connection with each other at the same time, the endpoint with the
+//! highest request nonce stops answering requests and acts as the client.
+//!
+//! When the server requires a connect token, the client sends the private part of its token along
+//! with both the request and the response, and the server only challenges and accepts clients with a
+//! valid token. The token is sent twice so the server still does not need to keep state for clients
+//! that did not answer their challenge yet.
+//!
+//! When encryption is enabled, the keys of the connection are derived from the pre-shared key, or the
+//! keys of the connect token, and the cookie. A cookie can therefore only be redeemed once, so that a
+//! replayed handshake does not result in the keys of an earlier connection.
+
+use crate::infrastructure::{ConnectionKeys, EncryptionKey, KEY_SIZE};
+use crate::packet::{
+    header::AckWindow, DeliveryGuarantee, OrderingGuarantee, OutgoingPacketBuilder, PacketType,
+};
+use byteorder::{BigEndian, ByteOrder};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The size of the timestamp in a challenge cookie.
+const TIMESTAMP_SIZE: usize = 8;
+/// The size of the random salt in a challenge cookie.
+const SALT_SIZE: usize = 8;
+/// The size of the (truncated) MAC in a challenge cookie.
+const MAC_SIZE: usize = 16;
+/// The size of the nonce in a connection request.
+const REQUEST_NONCE_SIZE: usize = 8;
+
+/// The offset of the MAC in a challenge cookie.
+const MAC_OFFSET: usize = TIMESTAMP_SIZE + SALT_SIZE;
+
+/// The size of a challenge cookie.
+pub const CHALLENGE_COOKIE_SIZE: usize = TIMESTAMP_SIZE + SALT_SIZE + MAC_SIZE;
+
+/// A challenge cookie, which the remote endpoint has to echo back to prove it owns its address.
+pub type ChallengeCookie = [u8; CHALLENGE_COOKIE_SIZE];
+
+/// Issues and verifies challenge cookies without keeping any state per unverified remote endpoint.
+pub struct ChallengeIssuer {
+    // The secret key the cookies are signed with.
+    key: EncryptionKey,
+    // The moment from which cookie timestamps are counted.
+    epoch: Instant,
+    // The cookies which were redeemed and have not expired yet, with their timestamps.
+    redeemed: HashMap<ChallengeCookie, u64>,
+}
+
+impl ChallengeIssuer {
+    /// Constructs a new `ChallengeIssuer` with a random secret key.
+    pub fn new(epoch: Instant) -> Self {
+        let mut key = [0; KEY_SIZE];
+        for byte in key.iter_mut() {
+            *byte = rand::random();
+        }
+
+        ChallengeIssuer {
+            key,
+            epoch,
+            redeemed: HashMap::new(),
+        }
+    }
+
+    /// Returns a challenge cookie for the given address.
+    pub fn issue(&self, address: SocketAddr, time: Instant) -> ChallengeCookie {
+        let timestamp = self.timestamp(time);
+
+        let mut cookie = [0; CHALLENGE_COOKIE_SIZE];
+        BigEndian::write_u64(&mut cookie[..TIMESTAMP_SIZE], timestamp);
+        BigEndian::write_u64(&mut cookie[TIMESTAMP_SIZE..MAC_OFFSET], rand::random());
+        let mac = self
+            .mac(address, &cookie[..MAC_OFFSET])
+            .finalize()
+            .into_bytes();
+        cookie[MAC_OFFSET..].copy_from_slice(&mac[..MAC_SIZE]);
+
+        cookie
+    }
+
+    /// Returns true if the cookie was issued to the given address by this issuer, less than `max_age` ago.
+    pub fn verify(
+        &self,
+        address: SocketAddr,
+        cookie: &ChallengeCookie,
+        max_age: Duration,
+        time: Instant,
+    ) -> bool {
+        let timestamp = BigEndian::read_u64(&cookie[..TIMESTAMP_SIZE]);
+        let now = self.timestamp(time);
+
+        if timestamp > now || now - timestamp >= max_age.as_millis() as u64 {
+            return false;
+        }
+
+        self.mac(address, &cookie[..MAC_OFFSET])
+            .verify_truncated_left(&cookie[MAC_OFFSET..])
+            .is_ok()
+    }
+
+    /// Verifies the cookie like [ChallengeIssuer::verify], but only succeeds the first time a cookie
+    /// is redeemed.
+    pub fn redeem(
+        &mut self,
+        address: SocketAddr,
+        cookie: &ChallengeCookie,
+        max_age: Duration,
+        time: Instant,
+    ) -> bool {
+        if !self.verify(address, cookie, max_age, time) || self.redeemed.contains_key(cookie) {
+            return false;
+        }
+
+        // Cookies that are too old to be verified do not have to be remembered any longer.
+        let now = self.timestamp(time);
+        let max_age = max_age.as_millis() as u64;
+        self.redeemed
+            .retain(|_, timestamp| now.saturating_sub(*timestamp) < max_age);
+
+        self.redeemed
+            .insert(*cookie, BigEndian::read_u64(&cookie[..TIMESTAMP_SIZE]));
+        true
+    }
+
+    // The number of milliseconds between the epoch and the given time.
+    fn timestamp(&self, time: Instant) -> u64 {
+        time.saturating_duration_since(self.epoch).as_millis() as u64
+    }
+
+    // Constructs a MAC over the address and the signed part of a cookie.
+    fn mac(&self, address: SocketAddr, signed: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+
+        match address {
+            SocketAddr::V4(address) => mac.update(&address.ip().octets()),
+            SocketAddr::V6(address) => mac.update(&address.ip().octets()),
+        }
+
+        let mut port = [0; 2];
+        BigEndian::write_u16(&mut port, address.port());
+        mac.update(&port);
+        mac.update(signed);
+
+        mac
+    }
+}
+
+impl fmt::Debug for ChallengeIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never write the secret key to the logs.
+        f.debug_struct("ChallengeIssuer")
+            .field("epoch", &self.epoch)
+            .finish()
+    }
+}
+
+/// Constructs a handshake packet of the given type, with the given payload.
+pub fn handshake_packet(packet_type: PacketType, payload: &[u8]) -> Box<[u8]> {
+    OutgoingPacketBuilder::new(payload)
+        .with_default_header(
+            packet_type,
+            DeliveryGuarantee::Unreliable,
+            OrderingGuarantee::None,
+        )
+        .build()
+        .contents()
+}
+
+/// Constructs a connection request with the given nonce and the private data of the connect token
+/// of the client, if any. The request is padded so that it is as large as the challenge it is answered
+/// with.
+pub fn connection_request_packet(nonce: u64, connect_token: &[u8]) -> Box<[u8]> {
+    let mut payload = vec![0; CHALLENGE_COOKIE_SIZE];
+    BigEndian::write_u64(&mut payload[..REQUEST_NONCE_SIZE], nonce);
+    payload.extend_from_slice(connect_token);
+    handshake_packet(PacketType::ConnectionRequest, &payload)
+}
+
+/// Reads the nonce and the connect token from the payload of a connection request. Returns `None` if
+/// the request is not large enough to be answered with a challenge.
+pub fn read_connection_request(payload: &[u8]) -> Option<(u64, &[u8])> {
+    if payload.len() >= CHALLENGE_COOKIE_SIZE {
+        Some((
+            BigEndian::read_u64(&payload[..REQUEST_NONCE_SIZE]),
+            &payload[CHALLENGE_COOKIE_SIZE..],
+        ))
+    } else {
+        None
+    }
+}
+
+/// Constructs the response to a challenge, which echoes the cookie along with the acknowledgment
+/// window the client proposes and the private data of the connect token of the client, if any.
+pub fn challenge_response_packet(
+    cookie: &ChallengeCookie,
+    window: AckWindow,
+    connect_token: &[u8],
+) -> Box<[u8]> {
+    let mut payload = cookie.to_vec();
+    payload.push(window.field_size());
+    payload.extend_from_slice(connect_token);
+    handshake_packet(PacketType::ChallengeResponse, &payload)
+}
+
+/// Reads the cookie, the proposed acknowledgment window and the connect token from the payload of a
+/// challenge response.
+pub fn read_challenge_response(payload: &[u8]) -> Option<(ChallengeCookie, AckWindow, &[u8])> {
+    if payload.len() > CHALLENGE_COOKIE_SIZE {
+        let (cookie, rest) = payload.split_at(CHALLENGE_COOKIE_SIZE);
+        let window = AckWindow::from_field_size(rest[0])?;
+        read_cookie(cookie).map(|cookie| (cookie, window, &rest[1..]))
+    } else {
+        None
+    }
+}
+
+/// Constructs the acceptance of a connection, which contains the acknowledgment window of the
+/// connection.
+pub fn connection_accepted_packet(window: AckWindow) -> Box<[u8]> {
+    handshake_packet(PacketType::ConnectionAccepted, &[window.field_size()])
+}
+
+/// Reads the acknowledgment window from the payload of a connection acceptance.
+pub fn read_connection_accepted(payload: &[u8]) -> Option<AckWindow> {
+    match payload {
+        [field_size] => AckWindow::from_field_size(*field_size),
+        _ => None,
+    }
+}
+
+/// Derives the keys of a connection from the pre-shared key and the cookie of its handshake.
+pub fn derive_connection_keys(
+    secret: &EncryptionKey,
+    cookie: &ChallengeCookie,
+    is_server: bool,
+) -> ConnectionKeys {
+    derive_keys(secret, secret, cookie, is_server)
+}
+
+/// Derives the keys of a connection from the keys of a connect token and the cookie of its
+/// handshake, so each connection made with the same token is encrypted with other keys.
+pub fn derive_token_keys(
+    client_to_server_key: &EncryptionKey,
+    server_to_client_key: &EncryptionKey,
+    cookie: &ChallengeCookie,
+    is_server: bool,
+) -> ConnectionKeys {
+    derive_keys(
+        client_to_server_key,
+        server_to_client_key,
+        cookie,
+        is_server,
+    )
+}
+
+fn derive_keys(
+    client_secret: &EncryptionKey,
+    server_secret: &EncryptionKey,
+    cookie: &ChallengeCookie,
+    is_server: bool,
+) -> ConnectionKeys {
+    let derive_key = |secret: &EncryptionKey, label: &[u8]| {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+        mac.update(label);
+        mac.update(cookie);
+
+        let mut key = [0; KEY_SIZE];
+        key.copy_from_slice(&mac.finalize().into_bytes());
+        key
+    };
+
+    let client_key = derive_key(client_secret, b"laminar client key");
+    let server_key = derive_key(server_secret, b"laminar server key");
+
+    if is_server {
+        ConnectionKeys::new(server_key, client_key)
+    } else {
+        ConnectionKeys::new(client_key, server_key)
+    }
+}
+
+/// Reads the cookie from the payload of a handshake packet.
+pub fn read_cookie(payload: &[u8]) -> Option<ChallengeCookie> {
+    if payload.len() == CHALLENGE_COOKIE_SIZE {
+        let mut cookie = [0; CHALLENGE_COOKIE_SIZE];
+        cookie.copy_from_slice(payload);
+        Some(cookie)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        challenge_response_packet, connection_accepted_packet, connection_request_packet,
+        derive_connection_keys, handshake_packet, read_challenge_response,
+        read_connection_accepted, read_connection_request, read_cookie, ChallengeIssuer,
+        CHALLENGE_COOKIE_SIZE,
+    };
+    use crate::packet::{header::AckWindow, PacketReader, PacketType};
+    use std::time::{Duration, Instant};
+
+    const MAX_AGE: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn issued_cookie_can_be_verified() {
+        let time = Instant::now();
+        let issuer = ChallengeIssuer::new(time);
+        let address = "127.0.0.1:12345".parse().unwrap();
+
+        let cookie = issuer.issue(address, time);
+
+        assert!(issuer.verify(address, &cookie, MAX_AGE, time));
+        assert!(!issuer.verify("127.0.0.1:12346".parse().unwrap(), &cookie, MAX_AGE, time));
+        assert!(!ChallengeIssuer::new(time).verify(address, &cookie, MAX_AGE, time));
+    }
+
+    #[test]
+    fn tampered_cookie_is_rejected() {
+        let time = Instant::now();
+        let issuer = ChallengeIssuer::new(time);
+        let address = "127.0.0.1:12345".parse().unwrap();
+
+        let mut cookie = issuer.issue(address, time + Duration::from_secs(1));
+        // Pretend the cookie was issued at the epoch
+        for byte in cookie[..8].iter_mut() {
+            *byte = 0;
+        }
+
+        assert!(!issuer.verify(address, &cookie, MAX_AGE, time + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn expired_cookie_is_rejected() {
+        let time = Instant::now();
+        let issuer = ChallengeIssuer::new(time);
+        let address = "127.0.0.1:12345".parse().unwrap();
+
+        let cookie = issuer.issue(address, time);
+
+        assert!(issuer.verify(address, &cookie, MAX_AGE, time + MAX_AGE / 2));
+        assert!(!issuer.verify(address, &cookie, MAX_AGE, time + MAX_AGE));
+    }
+
+    #[test]
+    fn cookie_can_only_be_redeemed_once() {
+        let time = Instant::now();
+        let mut issuer = ChallengeIssuer::new(time);
+        let address = "127.0.0.1:12345".parse().unwrap();
+
+        let cookie = issuer.issue(address, time);
+        let other_cookie = issuer.issue(address, time);
+
+        assert!(issuer.redeem(address, &cookie, MAX_AGE, time));
+        assert!(!issuer.redeem(address, &cookie, MAX_AGE, time));
+        assert!(issuer.redeem(address, &other_cookie, MAX_AGE, time));
+    }
+
+    #[test]
+    fn derived_keys_of_client_and_server_match() {
+        let cookie = [7; CHALLENGE_COOKIE_SIZE];
+
+        let client_keys = derive_connection_keys(&[1; 32], &cookie, false);
+        let server_keys = derive_connection_keys(&[1; 32], &cookie, true);
+
+        assert!(client_keys != server_keys);
+        assert!(client_keys == derive_connection_keys(&[1; 32], &cookie, false));
+        assert!(client_keys != derive_connection_keys(&[2; 32], &cookie, false));
+    }
+
+    #[test]
+    fn request_nonce_and_token_survive_connection_request() {
+        let packet = connection_request_packet(42, &[1, 2, 3]);
+
+        let mut reader = PacketReader::new(&packet);
+        reader.read_standard_header().unwrap();
+
+        assert_eq!(
+            read_connection_request(&reader.read_payload()),
+            Some((42, &[1, 2, 3][..]))
+        );
+        assert_eq!(read_connection_request(&[0; 8]), None);
+    }
+
+    #[test]
+    fn cookie_and_token_survive_challenge_response() {
+        let cookie = [7; CHALLENGE_COOKIE_SIZE];
+        let packet = challenge_response_packet(&cookie, AckWindow::Bits64, &[1, 2, 3]);
+
+        let mut reader = PacketReader::new(&packet);
+        reader.read_standard_header().unwrap();
+
+        assert_eq!(
+            read_challenge_response(&reader.read_payload()),
+            Some((cookie, AckWindow::Bits64, &[1, 2, 3][..]))
+        );
+        assert_eq!(read_challenge_response(&cookie), None);
+    }
+
+    #[test]
+    fn window_survives_connection_accepted() {
+        let packet = connection_accepted_packet(AckWindow::Bits128);
+
+        let mut reader = PacketReader::new(&packet);
+        let header = reader.read_standard_header().unwrap();
+
+        assert_eq!(header.packet_type(), PacketType::ConnectionAccepted);
+        assert_eq!(
+            read_connection_accepted(&reader.read_payload()),
+            Some(AckWindow::Bits128)
+        );
+        assert_eq!(read_connection_accepted(&[3]), None);
+    }
+
+    #[test]
+    fn challenge_is_not_larger_than_request() {
+        let time = Instant::now();
+        let cookie = ChallengeIssuer::new(time).issue("127.0.0.1:12345".parse().unwrap(), time);
+        let challenge = handshake_packet(PacketType::ConnectionChallenge, &cookie);
+
+        assert!(challenge.len() <= connection_request_packet(0, &[]).len());
+    }
+
+    #[test]
+    fn cookie_survives_handshake_packet() {
+        let cookie = [7; CHALLENGE_COOKIE_SIZE];
+        let packet = handshake_packet(PacketType::ConnectionChallenge, &cookie);
+
+        let mut reader = PacketReader::new(&packet);
+        let header = reader.read_standard_header().unwrap();
+
+        assert_eq!(header.packet_type(), PacketType::ConnectionChallenge);
+        assert_eq!(read_cookie(&reader.read_payload()), Some(cookie));
+    }
+}
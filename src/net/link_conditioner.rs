@@ -1,50 +1,433 @@
 //! This module provides means to simulate various network conditions for development. The primary focus is
-//! for testing applications under adverse conditions such as high packet loss networks, or high latency
-//! networks. This is not in heavy use yet, hence the allowing dead code. These will be removed as our testing
-//! becomes more sophisticated.
+//! for testing applications under adverse conditions such as high packet loss networks, high latency
+//! networks, or networks which reorder, duplicate and corrupt packets.
+//!
+//! Besides being configured by hand, a link conditioner can start from one of the [Preset]s and follow
+//! a [LinkScript], which changes its conditions over time.
 
-use rand::Rng;
+mod script;
+
+pub use self::script::{LinkScript, ScriptCommand};
+
+use rand::{
+    distributions::{Exp, Normal},
+    Rng, SeedableRng,
+};
 use rand_pcg::Pcg64Mcg as Random;
-use std::time::Duration;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The distribution from which the jitter of each packet is drawn. The jitter is added to the latency
+/// of the [LinkConditioner], but never makes the delay of a packet negative.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Jitter {
+    /// Jitter drawn uniformly between minus and plus the given duration.
+    Uniform(Duration),
+    /// Jitter drawn from a normal distribution with a mean of zero and the given standard deviation.
+    Normal(Duration),
+    /// Jitter drawn from an exponential distribution with the given mean, which only ever delays
+    /// packets. This models the occasional long delays of congested links.
+    Exponential(Duration),
+}
+
+/// The parameters of the Gilbert–Elliott model, in which the link switches between a good and a bad
+/// state, each with its own packet loss. Because the link stays in the bad state for a while, packets
+/// are lost in bursts like on real networks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurstLoss {
+    /// Value between 0 and 1, representing the chance the link switches from the good to the bad
+    /// state before a packet.
+    pub good_to_bad: f64,
+    /// Value between 0 and 1, representing the chance the link switches from the bad to the good
+    /// state before a packet.
+    pub bad_to_good: f64,
+    /// Value between 0 and 1, representing the chance a packet is dropped in the good state.
+    pub good_loss: f64,
+    /// Value between 0 and 1, representing the chance a packet is dropped in the bad state.
+    pub bad_loss: f64,
+}
+
+/// The named network conditions a [LinkConditioner] can be created with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// A wired local network, with hardly any latency and no loss.
+    Lan,
+    /// A congested Wi-Fi network, with bursts of loss and occasional long delays.
+    BadWifi,
+    /// A 3G mobile network, with high latency, bursts of loss and limited bandwidth.
+    Mobile3G,
+}
+
+impl Preset {
+    /// Returns the preset with the given name: `lan`, `bad-wifi` or `3g`, in any case.
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name.to_lowercase().as_str() {
+            "lan" => Some(Preset::Lan),
+            "bad-wifi" => Some(Preset::BadWifi),
+            "3g" => Some(Preset::Mobile3G),
+            _ => None,
+        }
+    }
+}
 
-/// Network simulator. Used to simulate network conditions as dropped packets and packet delays.
-/// For use in [Socket::set_link_conditioner](crate::net::Socket::set_link_conditioner).
+/// Network simulator. Used to simulate network conditions as dropped, delayed, reordered, duplicated
+/// and corrupted packets, and links with limited bandwidth.
+/// For use in [Socket::set_link_conditioner](crate::net::Socket::set_link_conditioner) and
+/// [Socket::set_inbound_link_conditioner](crate::net::Socket::set_inbound_link_conditioner).
 #[derive(Clone, Debug)]
 pub struct LinkConditioner {
     // Value between 0 and 1, representing the % change a packet will be dropped on sending
     packet_loss: f64,
+    // The burst-loss model which replaces `packet_loss`, if any
+    burst_loss: Option<BurstLoss>,
+    // Whether the burst-loss model is in its bad state
+    bursting: bool,
     // Duration of the delay imposed between packets
     latency: Duration,
+    // The distribution of the variation of the delay, if any
+    jitter: Option<Jitter>,
+    // Value between 0 and 1, representing the chance a packet is held back by `reorder_delay`
+    reorder_chance: f64,
+    // The additional delay of a packet which is held back, so the packets after it overtake it
+    reorder_delay: Duration,
+    // Value between 0 and 1, representing the chance a packet is sent twice
+    duplication_chance: f64,
+    // Value between 0 and 1, representing the chance a bit of a packet is flipped
+    corruption_chance: f64,
+    // The number of bytes per second the link can carry, if limited
+    bandwidth: Option<u64>,
+    // The longest time a packet may wait for the link before it is dropped, if limited
+    max_queue_delay: Option<Duration>,
+    // The moment the link has sent all packets queued so far
+    link_free_at: Option<Instant>,
+    // The moment until which all packets are dropped, if any
+    blackout_until: Option<Instant>,
+    // The script which changes the conditions over time, and the moment it started
+    script: Option<LinkScript>,
+    script_start: Option<Instant>,
+    // The number of steps of the script which have been applied
+    script_position: usize,
     // Random number generator
     random: Random,
 }
 
 impl LinkConditioner {
     /// Creates and returns a LinkConditioner
-    #[allow(dead_code)]
     pub fn new() -> LinkConditioner {
         LinkConditioner {
             packet_loss: 0.0,
+            burst_loss: None,
+            bursting: false,
             latency: Duration::default(),
+            jitter: None,
+            reorder_chance: 0.0,
+            reorder_delay: Duration::default(),
+            duplication_chance: 0.0,
+            corruption_chance: 0.0,
+            bandwidth: None,
+            max_queue_delay: None,
+            link_free_at: None,
+            blackout_until: None,
+            script: None,
+            script_start: None,
+            script_position: 0,
             random: Random::new(0),
         }
     }
 
+    /// Creates a LinkConditioner with the conditions of the given preset.
+    pub fn from_preset(preset: Preset) -> LinkConditioner {
+        let mut link_conditioner = LinkConditioner::new();
+
+        match preset {
+            Preset::Lan => {
+                link_conditioner.set_latency(Duration::from_micros(500));
+                link_conditioner.set_jitter(Some(Jitter::Uniform(Duration::from_micros(200))));
+            }
+            Preset::BadWifi => {
+                link_conditioner.set_burst_loss(Some(BurstLoss {
+                    good_to_bad: 0.02,
+                    bad_to_good: 0.25,
+                    good_loss: 0.01,
+                    bad_loss: 0.5,
+                }));
+                link_conditioner.set_latency(Duration::from_millis(20));
+                link_conditioner.set_jitter(Some(Jitter::Exponential(Duration::from_millis(15))));
+                link_conditioner.set_reordering(0.01, Duration::from_millis(20));
+                link_conditioner.set_duplication(0.005);
+            }
+            Preset::Mobile3G => {
+                link_conditioner.set_burst_loss(Some(BurstLoss {
+                    good_to_bad: 0.01,
+                    bad_to_good: 0.3,
+                    good_loss: 0.005,
+                    bad_loss: 0.4,
+                }));
+                link_conditioner.set_latency(Duration::from_millis(150));
+                link_conditioner.set_jitter(Some(Jitter::Normal(Duration::from_millis(40))));
+                // 384 kbit/s, with a buffer of half a second.
+                link_conditioner.set_bandwidth(Some(48_000), Some(Duration::from_millis(500)));
+            }
+        }
+
+        link_conditioner
+    }
+
+    /// Replaces the conditions of this link conditioner with those of the given preset. Its random
+    /// number generator and script are kept.
+    pub fn apply_preset(&mut self, preset: Preset) {
+        let preset = LinkConditioner::from_preset(preset);
+
+        self.packet_loss = preset.packet_loss;
+        self.burst_loss = preset.burst_loss;
+        self.latency = preset.latency;
+        self.jitter = preset.jitter;
+        self.reorder_chance = preset.reorder_chance;
+        self.reorder_delay = preset.reorder_delay;
+        self.duplication_chance = preset.duplication_chance;
+        self.corruption_chance = preset.corruption_chance;
+        self.bandwidth = preset.bandwidth;
+        self.max_queue_delay = preset.max_queue_delay;
+    }
+
+    /// Seeds the random number generator from which all decisions of the link conditioner are drawn,
+    /// so that different link conditioners make different decisions.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::seed_from_u64(seed);
+    }
+
     /// Sets the packet loss rate of Link Conditioner
-    #[allow(dead_code)]
+    ///
+    /// Panics if the rate is not between 0 and 1.
     pub fn set_packet_loss(&mut self, rate: f64) {
+        assert_chance("packet loss rate", rate);
         self.packet_loss = rate;
     }
 
-    /// Sets the latency the link conditioner should apply to each packet
-    #[allow(dead_code)]
+    /// Sets the burst-loss model of the link conditioner. While it is set, it replaces the packet loss
+    /// rate.
+    ///
+    /// Panics if one of its chances is not between 0 and 1.
+    pub fn set_burst_loss(&mut self, burst_loss: Option<BurstLoss>) {
+        if let Some(burst_loss) = burst_loss {
+            assert_chance("good-to-bad chance", burst_loss.good_to_bad);
+            assert_chance("bad-to-good chance", burst_loss.bad_to_good);
+            assert_chance("good-state loss", burst_loss.good_loss);
+            assert_chance("bad-state loss", burst_loss.bad_loss);
+        }
+        self.burst_loss = burst_loss;
+        self.bursting = false;
+    }
+
+    /// Sets the script which changes the conditions of the link conditioner over time. The times of
+    /// the script count from the first packet which passes the link conditioner afterwards.
+    pub fn set_script(&mut self, script: Option<LinkScript>) {
+        self.script = script;
+        self.script_start = None;
+        self.script_position = 0;
+    }
+
+    /// Drops all packets which pass the link conditioner from the given time until the given
+    /// duration has passed.
+    pub fn black_out(&mut self, time: Instant, duration: Duration) {
+        self.blackout_until = Some(time + duration);
+    }
+
+    /// Sets the latency the link conditioner should apply to each packet. Packets are held back by
+    /// the socket and sent once the latency has passed.
     pub fn set_latency(&mut self, latency: Duration) {
         self.latency = latency
     }
 
+    /// Returns the latency the link conditioner applies to each packet.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Sets the distribution of the variation of the latency. Packets may overtake each other when
+    /// the jitter is larger than the interval between them.
+    pub fn set_jitter(&mut self, jitter: Option<Jitter>) {
+        self.jitter = jitter;
+    }
+
+    /// Sets the chance that a packet is held back for an additional `delay`, so that the packets sent
+    /// after it overtake it.
+    ///
+    /// Panics if the chance is not between 0 and 1.
+    pub fn set_reordering(&mut self, chance: f64, delay: Duration) {
+        assert_chance("reordering chance", chance);
+        self.reorder_chance = chance;
+        self.reorder_delay = delay;
+    }
+
+    /// Sets the chance that a packet is sent twice. Both copies are delayed independently.
+    ///
+    /// Panics if the chance is not between 0 and 1.
+    pub fn set_duplication(&mut self, chance: f64) {
+        assert_chance("duplication chance", chance);
+        self.duplication_chance = chance;
+    }
+
+    /// Sets the chance that a single, random bit of a packet is flipped.
+    ///
+    /// Panics if the chance is not between 0 and 1.
+    pub fn set_corruption(&mut self, chance: f64) {
+        assert_chance("corruption chance", chance);
+        self.corruption_chance = chance;
+    }
+
+    /// Limits the number of bytes per second the link can carry. Packets which exceed it are queued
+    /// until the link is free again. If `max_queue_delay` is given, packets which would have to wait
+    /// longer than that are dropped, like a router with a full buffer does.
+    pub fn set_bandwidth(
+        &mut self,
+        bytes_per_second: Option<u64>,
+        max_queue_delay: Option<Duration>,
+    ) {
+        self.bandwidth = bytes_per_second;
+        self.max_queue_delay = max_queue_delay;
+    }
+
     /// Function that checks to see if a packet should be dropped or not
     pub fn should_send(&mut self) -> bool {
-        self.random.gen_range(0.0, 1.0) >= self.packet_loss
+        match self.burst_loss {
+            Some(burst_loss) => {
+                let switch_chance = if self.bursting {
+                    burst_loss.bad_to_good
+                } else {
+                    burst_loss.good_to_bad
+                };
+                if self.draw(switch_chance) {
+                    self.bursting = !self.bursting;
+                }
+
+                let loss = if self.bursting {
+                    burst_loss.bad_loss
+                } else {
+                    burst_loss.good_loss
+                };
+                !self.draw(loss)
+            }
+            None => self.random.gen_range(0.0, 1.0) >= self.packet_loss,
+        }
+    }
+
+    /// Subjects a packet which enters the link at the given time to the conditions of the link.
+    /// Returns the copies of the packet which leave the link, with the time at which each of them
+    /// does. No copies are returned if the packet is lost.
+    pub fn condition(&mut self, payload: &[u8], time: Instant) -> Vec<(Instant, Box<[u8]>)> {
+        self.run_script(time);
+
+        let blacked_out = match self.blackout_until {
+            Some(blackout_until) => time < blackout_until,
+            None => false,
+        };
+        if blacked_out || !self.should_send() {
+            return Vec::new();
+        }
+
+        let copies = if self.draw(self.duplication_chance) {
+            2
+        } else {
+            1
+        };
+
+        (0..copies)
+            .filter_map(|_| {
+                let transmitted = self.transmit(payload.len(), time)?;
+
+                let mut copy = Box::<[u8]>::from(payload);
+                if !copy.is_empty() && self.draw(self.corruption_chance) {
+                    let bit = self.random.gen_range(0, copy.len() * 8);
+                    copy[bit / 8] ^= 1 << (bit % 8);
+                }
+
+                Some((transmitted + self.delay(), copy))
+            })
+            .collect()
+    }
+
+    // Applies the steps of the script which are due at the given time.
+    fn run_script(&mut self, time: Instant) {
+        let script = match self.script.take() {
+            Some(script) => script,
+            None => return,
+        };
+
+        let start = *self.script_start.get_or_insert(time);
+        let elapsed = time.saturating_duration_since(start);
+
+        while let Some((at, command)) = script.step(self.script_position) {
+            if at > elapsed {
+                break;
+            }
+            command.apply(self, start + at);
+            self.script_position += 1;
+        }
+
+        self.script = Some(script);
+    }
+
+    // Returns true with the given chance. Nothing is drawn for conditions which are disabled, so they
+    // do not change the decisions of the others.
+    fn draw(&mut self, chance: f64) -> bool {
+        chance > 0.0 && self.random.gen_bool(chance)
+    }
+
+    // Returns the moment a packet of the given size has been put on the link, or `None` if it has to
+    // wait too long for the link and is dropped.
+    fn transmit(&mut self, size: usize, time: Instant) -> Option<Instant> {
+        let bandwidth = match self.bandwidth {
+            Some(bandwidth) if bandwidth > 0 => bandwidth,
+            _ => return Some(time),
+        };
+
+        let start = match self.link_free_at {
+            Some(link_free_at) if link_free_at > time => link_free_at,
+            _ => time,
+        };
+
+        if let Some(max_queue_delay) = self.max_queue_delay {
+            if start - time > max_queue_delay {
+                return None;
+            }
+        }
+
+        let transmitted = start + Duration::from_nanos(size as u64 * 1_000_000_000 / bandwidth);
+        self.link_free_at = Some(transmitted);
+        Some(transmitted)
+    }
+
+    // Draws the time a packet takes to travel the link.
+    fn delay(&mut self) -> Duration {
+        let latency = self.latency.as_secs_f64();
+        let jitter = match self.jitter {
+            Some(Jitter::Uniform(max)) => {
+                let max = max.as_secs_f64();
+                if max > 0.0 {
+                    self.random.gen_range(-max, max)
+                } else {
+                    0.0
+                }
+            }
+            Some(Jitter::Normal(std_dev)) => {
+                self.random.sample(Normal::new(0.0, std_dev.as_secs_f64()))
+            }
+            Some(Jitter::Exponential(mean)) if mean > Duration::from_secs(0) => {
+                self.random.sample(Exp::new(1.0 / mean.as_secs_f64()))
+            }
+            Some(Jitter::Exponential(_)) | None => 0.0,
+        };
+
+        let mut delay = Duration::from_secs_f64((latency + jitter).max(0.0));
+        if self.draw(self.reorder_chance) {
+            delay += self.reorder_delay;
+        }
+        delay
     }
 }
 
@@ -53,3 +436,312 @@ impl Default for LinkConditioner {
         Self::new()
     }
 }
+
+/// Holds datagrams back until the moment they should be sent, or handled when they were received.
+#[derive(Debug, Default)]
+pub struct DelayQueue {
+    datagrams: BinaryHeap<DelayedDatagram>,
+    // The number of datagrams queued so far, which keeps datagrams due at the same time in order.
+    queued: u64,
+}
+
+impl DelayQueue {
+    /// Queues a datagram to or from the given address, which is due at the given time.
+    pub fn push(&mut self, address: SocketAddr, payload: Box<[u8]>, due_time: Instant) {
+        self.datagrams.push(DelayedDatagram {
+            due_time,
+            sequence: self.queued,
+            address,
+            payload,
+        });
+        self.queued += 1;
+    }
+
+    /// Removes and returns the next datagram which is due at the given time, if any.
+    pub fn pop_due(&mut self, time: Instant) -> Option<(SocketAddr, Box<[u8]>)> {
+        match self.datagrams.peek() {
+            Some(datagram) if datagram.due_time <= time => self
+                .datagrams
+                .pop()
+                .map(|datagram| (datagram.address, datagram.payload)),
+            _ => None,
+        }
+    }
+
+    /// Returns the time at which the next datagram is due, if any.
+    pub fn next_due_time(&self) -> Option<Instant> {
+        self.datagrams.peek().map(|datagram| datagram.due_time)
+    }
+}
+
+#[derive(Debug)]
+struct DelayedDatagram {
+    due_time: Instant,
+    sequence: u64,
+    address: SocketAddr,
+    payload: Box<[u8]>,
+}
+
+impl Ord for DelayedDatagram {
+    // The heap pops the datagram which is due first, and of those the one which was queued first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.due_time, other.sequence).cmp(&(self.due_time, self.sequence))
+    }
+}
+
+impl PartialOrd for DelayedDatagram {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DelayedDatagram {
+    fn eq(&self, other: &Self) -> bool {
+        self.sequence == other.sequence
+    }
+}
+
+impl Eq for DelayedDatagram {}
+
+// Panics with a message naming the condition if the chance is not between 0 and 1, like the chances
+// of a `LinkScript` are rejected when it is parsed.
+fn assert_chance(name: &str, chance: f64) {
+    assert!(
+        (0.0..=1.0).contains(&chance),
+        "The {} must be between 0 and 1, but is {}",
+        name,
+        chance
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BurstLoss, DelayQueue, Jitter, LinkConditioner, LinkScript, Preset, ScriptCommand,
+    };
+    use std::{
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn datagrams_are_released_in_order_of_due_time() {
+        let address: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let time = Instant::now();
+        let mut queue = DelayQueue::default();
+
+        queue.push(address, Box::new([1]), time + Duration::from_millis(20));
+        queue.push(address, Box::new([2]), time + Duration::from_millis(10));
+        queue.push(address, Box::new([3]), time + Duration::from_millis(10));
+
+        assert_eq!(
+            queue.next_due_time(),
+            Some(time + Duration::from_millis(10))
+        );
+        assert_eq!(queue.pop_due(time), None);
+
+        let due: Vec<_> = std::iter::from_fn(|| queue.pop_due(time + Duration::from_millis(15)))
+            .map(|(_, payload)| payload[0])
+            .collect();
+        assert_eq!(due, vec![2, 3]);
+
+        assert_eq!(
+            queue.pop_due(time + Duration::from_millis(20)),
+            Some((address, vec![1u8].into_boxed_slice()))
+        );
+        assert_eq!(queue.next_due_time(), None);
+    }
+
+    #[test]
+    fn packets_are_delayed_by_latency_and_jitter() {
+        let time = Instant::now();
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_latency(Duration::from_millis(100));
+        link_conditioner.set_jitter(Some(Jitter::Uniform(Duration::from_millis(20))));
+
+        let delays: Vec<_> = (0..100)
+            .map(|_| link_conditioner.condition(&[0], time)[0].0 - time)
+            .collect();
+
+        assert!(delays.iter().all(
+            |delay| *delay >= Duration::from_millis(80) && *delay <= Duration::from_millis(120)
+        ));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn reordered_packets_are_held_back() {
+        let time = Instant::now();
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_latency(Duration::from_millis(10));
+        link_conditioner.set_reordering(1.0, Duration::from_millis(50));
+
+        assert_eq!(
+            link_conditioner.condition(&[0], time)[0].0,
+            time + Duration::from_millis(60)
+        );
+    }
+
+    #[test]
+    fn duplicated_packets_are_sent_twice() {
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_duplication(1.0);
+
+        let copies = link_conditioner.condition(&[1, 2, 3], Instant::now());
+        assert_eq!(copies.len(), 2);
+        assert!(copies.iter().all(|(_, copy)| **copy == [1, 2, 3]));
+    }
+
+    #[test]
+    #[should_panic(expected = "The duplication chance must be between 0 and 1, but is 1.5")]
+    fn chance_above_one_is_rejected() {
+        LinkConditioner::new().set_duplication(1.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "The bad-state loss must be between 0 and 1, but is -0.1")]
+    fn negative_burst_loss_is_rejected() {
+        LinkConditioner::new().set_burst_loss(Some(BurstLoss {
+            good_to_bad: 0.1,
+            bad_to_good: 0.1,
+            good_loss: 0.0,
+            bad_loss: -0.1,
+        }));
+    }
+
+    #[test]
+    fn corrupted_packets_have_one_bit_flipped() {
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_corruption(1.0);
+
+        let payload = [0b1010_1010; 8];
+        let copies = link_conditioner.condition(&payload, Instant::now());
+
+        let flipped_bits: u32 = payload
+            .iter()
+            .zip(copies[0].1.iter())
+            .map(|(original, corrupted)| (original ^ corrupted).count_ones())
+            .sum();
+        assert_eq!(flipped_bits, 1);
+    }
+
+    #[test]
+    fn bandwidth_queues_and_drops_packets() {
+        let time = Instant::now();
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_bandwidth(Some(1000), Some(Duration::from_millis(250)));
+
+        // Each packet occupies the link for 100 milliseconds.
+        let due_times: Vec<_> = (0..3)
+            .map(|_| link_conditioner.condition(&[0; 100], time)[0].0 - time)
+            .collect();
+        assert_eq!(
+            due_times,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(300)
+            ]
+        );
+
+        // The next packet would have to wait 300 milliseconds for the link.
+        assert!(link_conditioner.condition(&[0; 100], time).is_empty());
+        assert_eq!(
+            link_conditioner.condition(&[0; 100], time + Duration::from_millis(200))[0].0,
+            time + Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn seed_changes_decisions() {
+        let mut first = LinkConditioner::new();
+        let mut second = LinkConditioner::new();
+        first.set_packet_loss(0.5);
+        second.set_packet_loss(0.5);
+        second.set_seed(1);
+
+        let first: Vec<_> = (0..64).map(|_| first.should_send()).collect();
+        let second: Vec<_> = (0..64).map(|_| second.should_send()).collect();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn burst_loss_drops_packets_in_bursts() {
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_burst_loss(Some(BurstLoss {
+            good_to_bad: 0.05,
+            bad_to_good: 0.2,
+            good_loss: 0.0,
+            bad_loss: 1.0,
+        }));
+
+        let sent: Vec<_> = (0..10_000)
+            .map(|_| link_conditioner.should_send())
+            .collect();
+        let lost = sent.iter().filter(|sent| !**sent).count();
+        let bursts = sent.windows(2).filter(|pair| pair[0] && !pair[1]).count();
+
+        // The link is in the bad state a fifth of the time, for five packets on average.
+        assert!(lost > 1000 && lost < 3000, "{} packets lost", lost);
+        assert!(
+            lost / bursts >= 3,
+            "{} packets lost in {} bursts",
+            lost,
+            bursts
+        );
+    }
+
+    #[test]
+    fn presets_are_found_by_name() {
+        assert_eq!(Preset::from_name("LAN"), Some(Preset::Lan));
+        assert_eq!(Preset::from_name("bad-wifi"), Some(Preset::BadWifi));
+        assert_eq!(Preset::from_name("3g"), Some(Preset::Mobile3G));
+        assert_eq!(Preset::from_name("dial-up"), None);
+
+        assert_eq!(
+            LinkConditioner::from_preset(Preset::Mobile3G).latency(),
+            Duration::from_millis(150)
+        );
+    }
+
+    #[test]
+    fn script_changes_conditions_over_time() {
+        let time = Instant::now();
+        let mut script = LinkScript::new();
+        script.add(
+            Duration::from_secs(10),
+            ScriptCommand::Blackout(Duration::from_secs(2)),
+        );
+        script.add(
+            Duration::from_secs(15),
+            ScriptCommand::Latency(Duration::from_millis(100)),
+        );
+
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_script(Some(script));
+
+        let mut condition = |offset: Duration| {
+            link_conditioner
+                .condition(&[0], time + offset)
+                .iter()
+                .map(|(due_time, _)| *due_time - time - offset)
+                .collect::<Vec<_>>()
+        };
+
+        // The script starts with the first packet.
+        assert_eq!(
+            condition(Duration::from_secs(0)),
+            vec![Duration::from_secs(0)]
+        );
+        assert_eq!(condition(Duration::from_secs(10)), vec![]);
+        assert_eq!(condition(Duration::from_millis(11_999)), vec![]);
+        assert_eq!(
+            condition(Duration::from_secs(12)),
+            vec![Duration::from_secs(0)]
+        );
+        assert_eq!(
+            condition(Duration::from_secs(15)),
+            vec![Duration::from_millis(100)]
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use super::{BurstLoss, Jitter, LinkConditioner, Preset};
+use crate::error::{LinkScriptErrorKind, Result};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// A change to the conditions of a [LinkConditioner], made by a [LinkScript].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScriptCommand {
+    /// Replaces all conditions with those of the preset.
+    Preset(Preset),
+    /// Sets the packet loss rate, see [LinkConditioner::set_packet_loss].
+    PacketLoss(f64),
+    /// Sets the burst-loss model, see [LinkConditioner::set_burst_loss].
+    BurstLoss(Option<BurstLoss>),
+    /// Sets the latency, see [LinkConditioner::set_latency].
+    Latency(Duration),
+    /// Sets the jitter, see [LinkConditioner::set_jitter].
+    Jitter(Option<Jitter>),
+    /// Sets the chance and delay of reordering, see [LinkConditioner::set_reordering].
+    Reordering(f64, Duration),
+    /// Sets the chance of duplication, see [LinkConditioner::set_duplication].
+    Duplication(f64),
+    /// Sets the chance of corruption, see [LinkConditioner::set_corruption].
+    Corruption(f64),
+    /// Sets the bandwidth and maximal queue delay, see [LinkConditioner::set_bandwidth].
+    Bandwidth(Option<u64>, Option<Duration>),
+    /// Drops all packets for the given duration, see [LinkConditioner::black_out].
+    Blackout(Duration),
+}
+
+impl ScriptCommand {
+    /// Applies the command to the link conditioner at the given time.
+    pub(crate) fn apply(self, link_conditioner: &mut LinkConditioner, time: Instant) {
+        match self {
+            ScriptCommand::Preset(preset) => link_conditioner.apply_preset(preset),
+            ScriptCommand::PacketLoss(rate) => link_conditioner.set_packet_loss(rate),
+            ScriptCommand::BurstLoss(burst_loss) => link_conditioner.set_burst_loss(burst_loss),
+            ScriptCommand::Latency(latency) => link_conditioner.set_latency(latency),
+            ScriptCommand::Jitter(jitter) => link_conditioner.set_jitter(jitter),
+            ScriptCommand::Reordering(chance, delay) => {
+                link_conditioner.set_reordering(chance, delay)
+            }
+            ScriptCommand::Duplication(chance) => link_conditioner.set_duplication(chance),
+            ScriptCommand::Corruption(chance) => link_conditioner.set_corruption(chance),
+            ScriptCommand::Bandwidth(bytes_per_second, max_queue_delay) => {
+                link_conditioner.set_bandwidth(bytes_per_second, max_queue_delay)
+            }
+            ScriptCommand::Blackout(duration) => link_conditioner.black_out(time, duration),
+        }
+    }
+}
+
+/// A sequence of changes to the conditions of a [LinkConditioner], each made at a given time after
+/// the script started. Scripts make it possible to reproduce the network conditions of a bug report,
+/// e.g. a blackout of two seconds after ten seconds of play.
+///
+/// Scripts can be built in code, or parsed from text in which each line holds the time of a change
+/// followed by a command and its arguments:
+///
+/// ```text
+/// # Start on a congested Wi-Fi network.
+/// 0s preset bad-wifi
+/// # The connection drops for two seconds.
+/// 10s blackout 2s
+/// 15s latency 200ms
+/// 15s jitter normal 30ms
+/// 20s loss 0.1
+/// 20s burst-loss 0.05 0.3 0 0.8
+/// 25s reordering 0.1 50ms
+/// 25s duplication 0.01
+/// 25s corruption 0.001
+/// 30s bandwidth 64000 500ms
+/// 40s jitter none
+/// 40s bandwidth none
+/// ```
+///
+/// Times and durations are given in seconds (`s`) or milliseconds (`ms`). The presets are named
+/// `lan`, `bad-wifi` and `3g`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkScript {
+    // Ordered by time, changes at the same time are made in the order they were added.
+    steps: Vec<(Duration, ScriptCommand)>,
+}
+
+impl LinkScript {
+    /// Constructs a new, empty `LinkScript`.
+    pub fn new() -> LinkScript {
+        LinkScript::default()
+    }
+
+    /// Adds a command which is applied once the given time has passed since the script started.
+    pub fn add(&mut self, at: Duration, command: ScriptCommand) {
+        let position = self
+            .steps
+            .iter()
+            .take_while(|(time, _)| *time <= at)
+            .count();
+        self.steps.insert(position, (at, command));
+    }
+
+    /// Parses a script from text, see [LinkScript] for the format.
+    pub fn parse(script: &str) -> Result<LinkScript> {
+        let mut link_script = LinkScript::new();
+
+        for (index, line) in script.lines().enumerate() {
+            let line_number = index + 1;
+            let mut words = line.split('#').next().unwrap_or("").split_whitespace();
+
+            let at = match words.next() {
+                Some(word) => {
+                    parse_duration(word).ok_or(LinkScriptErrorKind::InvalidTime(line_number))?
+                }
+                None => continue,
+            };
+            let command = words
+                .next()
+                .ok_or(LinkScriptErrorKind::UnknownCommand(line_number))?;
+            let arguments: Vec<_> = words.collect();
+
+            let command = parse_command(command, &arguments, line_number)?;
+            link_script.add(at, command);
+        }
+
+        Ok(link_script)
+    }
+
+    /// Reads and parses a script from the file at the given path, see [LinkScript] for the format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LinkScript> {
+        LinkScript::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the step with the given index, with the time at which it is due.
+    pub(crate) fn step(&self, index: usize) -> Option<(Duration, ScriptCommand)> {
+        self.steps.get(index).copied()
+    }
+}
+
+fn parse_command(command: &str, arguments: &[&str], line_number: usize) -> Result<ScriptCommand> {
+    let invalid = || LinkScriptErrorKind::InvalidArguments(line_number);
+
+    let command = match (command, arguments) {
+        ("preset", [name]) => ScriptCommand::Preset(Preset::from_name(name).ok_or_else(invalid)?),
+        ("loss", [rate]) => ScriptCommand::PacketLoss(parse_chance(rate).ok_or_else(invalid)?),
+        ("burst-loss", ["none"]) => ScriptCommand::BurstLoss(None),
+        ("burst-loss", [good_to_bad, bad_to_good, good_loss, bad_loss]) => {
+            ScriptCommand::BurstLoss(Some(BurstLoss {
+                good_to_bad: parse_chance(good_to_bad).ok_or_else(invalid)?,
+                bad_to_good: parse_chance(bad_to_good).ok_or_else(invalid)?,
+                good_loss: parse_chance(good_loss).ok_or_else(invalid)?,
+                bad_loss: parse_chance(bad_loss).ok_or_else(invalid)?,
+            }))
+        }
+        ("latency", [latency]) => {
+            ScriptCommand::Latency(parse_duration(latency).ok_or_else(invalid)?)
+        }
+        ("jitter", ["none"]) => ScriptCommand::Jitter(None),
+        ("jitter", [distribution, duration]) => {
+            let duration = parse_duration(duration).ok_or_else(invalid)?;
+            let jitter = match *distribution {
+                "uniform" => Jitter::Uniform(duration),
+                "normal" => Jitter::Normal(duration),
+                "exponential" => Jitter::Exponential(duration),
+                _ => return Err(invalid().into()),
+            };
+            ScriptCommand::Jitter(Some(jitter))
+        }
+        ("reordering", [chance, delay]) => ScriptCommand::Reordering(
+            parse_chance(chance).ok_or_else(invalid)?,
+            parse_duration(delay).ok_or_else(invalid)?,
+        ),
+        ("duplication", [chance]) => {
+            ScriptCommand::Duplication(parse_chance(chance).ok_or_else(invalid)?)
+        }
+        ("corruption", [chance]) => {
+            ScriptCommand::Corruption(parse_chance(chance).ok_or_else(invalid)?)
+        }
+        ("bandwidth", ["none"]) => ScriptCommand::Bandwidth(None, None),
+        ("bandwidth", [bytes_per_second]) => {
+            ScriptCommand::Bandwidth(Some(bytes_per_second.parse().map_err(|_| invalid())?), None)
+        }
+        ("bandwidth", [bytes_per_second, max_queue_delay]) => ScriptCommand::Bandwidth(
+            Some(bytes_per_second.parse().map_err(|_| invalid())?),
+            Some(parse_duration(max_queue_delay).ok_or_else(invalid)?),
+        ),
+        ("blackout", [duration]) => {
+            ScriptCommand::Blackout(parse_duration(duration).ok_or_else(invalid)?)
+        }
+        ("preset", _)
+        | ("loss", _)
+        | ("burst-loss", _)
+        | ("latency", _)
+        | ("jitter", _)
+        | ("reordering", _)
+        | ("duplication", _)
+        | ("corruption", _)
+        | ("bandwidth", _)
+        | ("blackout", _) => return Err(invalid().into()),
+        _ => return Err(LinkScriptErrorKind::UnknownCommand(line_number).into()),
+    };
+
+    Ok(command)
+}
+
+// Parses a duration in seconds (`1.5s`) or milliseconds (`200ms`).
+fn parse_duration(word: &str) -> Option<Duration> {
+    let seconds = if let Some(milliseconds) = word.strip_suffix("ms") {
+        milliseconds.parse::<f64>().ok()? / 1000.0
+    } else {
+        word.strip_suffix('s')?.parse::<f64>().ok()?
+    };
+
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
+
+// Parses a chance between 0 and 1.
+fn parse_chance(word: &str) -> Option<f64> {
+    word.parse::<f64>()
+        .ok()
+        .filter(|chance| (0.0..=1.0).contains(chance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkScript, ScriptCommand};
+    use crate::{
+        error::{ErrorKind, LinkScriptErrorKind},
+        net::link_conditioner::{BurstLoss, Jitter, Preset},
+    };
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn script_is_parsed() {
+        let script = LinkScript::parse(
+            "# A comment\n\
+             10s blackout 2s\n\
+             0s preset bad-wifi # The script starts here\n\
+             \n\
+             1.5s latency 200ms\n\
+             1.5s jitter normal 30ms\n\
+             20s burst-loss 0.05 0.3 0 0.8\n\
+             30s bandwidth 64000 500ms\n\
+             40s bandwidth none\n",
+        )
+        .unwrap();
+
+        let mut expected = LinkScript::new();
+        expected.add(
+            Duration::from_secs(0),
+            ScriptCommand::Preset(Preset::BadWifi),
+        );
+        expected.add(
+            Duration::from_millis(1500),
+            ScriptCommand::Latency(Duration::from_millis(200)),
+        );
+        expected.add(
+            Duration::from_millis(1500),
+            ScriptCommand::Jitter(Some(Jitter::Normal(Duration::from_millis(30)))),
+        );
+        expected.add(
+            Duration::from_secs(10),
+            ScriptCommand::Blackout(Duration::from_secs(2)),
+        );
+        expected.add(
+            Duration::from_secs(20),
+            ScriptCommand::BurstLoss(Some(BurstLoss {
+                good_to_bad: 0.05,
+                bad_to_good: 0.3,
+                good_loss: 0.0,
+                bad_loss: 0.8,
+            })),
+        );
+        expected.add(
+            Duration::from_secs(30),
+            ScriptCommand::Bandwidth(Some(64000), Some(Duration::from_millis(500))),
+        );
+        expected.add(
+            Duration::from_secs(40),
+            ScriptCommand::Bandwidth(None, None),
+        );
+
+        assert_eq!(script, expected);
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        let cases = [
+            ("ten blackout 2s", LinkScriptErrorKind::InvalidTime(1)),
+            ("0s\n1s teleport", LinkScriptErrorKind::UnknownCommand(1)),
+            (
+                "0s loss 0.1\n1s teleport",
+                LinkScriptErrorKind::UnknownCommand(2),
+            ),
+            ("0s loss 1.5", LinkScriptErrorKind::InvalidArguments(1)),
+            (
+                "0s preset dial-up",
+                LinkScriptErrorKind::InvalidArguments(1),
+            ),
+            (
+                "0s latency 10ms 20ms",
+                LinkScriptErrorKind::InvalidArguments(1),
+            ),
+        ];
+
+        for (script, expected) in cases.iter() {
+            match LinkScript::parse(script) {
+                Err(ErrorKind::LinkScriptError(error)) => assert_eq!(&error, expected),
+                result => panic!("Unexpected result for {:?}: {:?}", script, result),
+            }
+        }
+    }
+
+    #[test]
+    fn script_is_loaded_from_file() {
+        let path = std::env::temp_dir().join(format!("laminar-script-{}.txt", std::process::id()));
+        fs::write(&path, "10s blackout 2s\n").unwrap();
+
+        let script = LinkScript::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut expected = LinkScript::new();
+        expected.add(
+            Duration::from_secs(10),
+            ScriptCommand::Blackout(Duration::from_secs(2)),
+        );
+        assert_eq!(script.unwrap(), expected);
+    }
+}
@@ -0,0 +1,327 @@
+//! This module provides the readiness notifications the polling loop of a socket blocks on, so it
+//! only wakes up when there is something to do.
+
+use crate::{
+    net::transport::DatagramTransport,
+    packet::{Packet, PacketId},
+};
+use crossbeam_channel::{Sender, TrySendError};
+use log::error;
+use mio::{Evented, Events, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::{
+    io,
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
+
+// Readiness of the transport.
+const TRANSPORT: Token = Token(0);
+// Readiness of the packets sent through a `PacketSender`.
+const USER: Token = Token(1);
+
+/// The longest time to block when the readiness of the transport cannot be polled.
+const UNREGISTERED_TRANSPORT_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Blocks until the transport is readable, a packet is sent through a [PacketSender] or a timeout
+/// passes.
+#[derive(Debug)]
+pub struct Poller {
+    poll: Poll,
+    events: Events,
+    // Keeps the readiness of the sent packets registered.
+    _registration: Registration,
+    // Keeps the readiness of the transport registered, if it is signalled by a `TransportWaker`.
+    _transport_registration: Option<Registration>,
+    set_readiness: SetReadiness,
+    // Whether the readiness of the transport is polled.
+    transport_registered: bool,
+}
+
+impl Poller {
+    /// Constructs a new `Poller` for the given transport, which receives at most `event_buffer_size`
+    /// readiness events at once.
+    ///
+    /// If the readiness of the transport cannot be polled, it is checked at least once per
+    /// millisecond.
+    pub fn new<T: DatagramTransport>(
+        transport: &T,
+        event_buffer_size: usize,
+    ) -> io::Result<Poller> {
+        let poll = Poll::new()?;
+        let (registration, set_readiness) = Registration::new2();
+        poll.register(&registration, USER, Ready::readable(), PollOpt::level())?;
+        let mut transport_registration = None;
+        let transport_registered = transport.register(&mut TransportReadiness {
+            poll: &poll,
+            registration: &mut transport_registration,
+        })?;
+
+        Ok(Poller {
+            poll,
+            events: Events::with_capacity(event_buffer_size.max(1)),
+            _registration: registration,
+            _transport_registration: transport_registration,
+            set_readiness,
+            transport_registered,
+        })
+    }
+
+    /// Returns a handle which wakes this poller.
+    pub fn waker(&self) -> SetReadiness {
+        self.set_readiness.clone()
+    }
+
+    /// Blocks until the transport is readable, the poller is woken or the timeout passes. If the
+    /// timeout is `None`, it blocks until one of the others happens.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = match timeout {
+            Some(timeout) if !self.transport_registered => {
+                Some(timeout.min(UNREGISTERED_TRANSPORT_TIMEOUT))
+            }
+            None if !self.transport_registered => Some(UNREGISTERED_TRANSPORT_TIMEOUT),
+            timeout => timeout,
+        };
+
+        self.poll.poll(&mut self.events, timeout)?;
+
+        // Packets which are sent from now on wake the poller again.
+        self.set_readiness.set_readiness(Ready::empty())
+    }
+}
+
+/// Registers how the polling loop of a socket learns that its transport may have a datagram to
+/// receive, see [DatagramTransport::register].
+#[derive(Debug)]
+pub struct TransportReadiness<'a> {
+    poll: &'a Poll,
+    registration: &'a mut Option<Registration>,
+}
+
+impl<'a> TransportReadiness<'a> {
+    /// Returns a waker with which the transport signals that datagrams are waiting to be received.
+    pub fn waker(&mut self) -> io::Result<TransportWaker> {
+        let (registration, set_readiness) = Registration::new2();
+        self.register_evented(&registration)?;
+        *self.registration = Some(registration);
+        Ok(TransportWaker { set_readiness })
+    }
+
+    /// Polls the readiness of the given file descriptor, which is readable while datagrams are
+    /// waiting to be received.
+    #[cfg(unix)]
+    pub fn register_fd(&mut self, fd: RawFd) -> io::Result<()> {
+        self.register_evented(&mio::unix::EventedFd(&fd))
+    }
+
+    /// Polls the readiness of the given handle of the transports of this crate.
+    pub(crate) fn register_evented<E: Evented>(&mut self, evented: &E) -> io::Result<()> {
+        self.poll
+            .register(evented, TRANSPORT, Ready::readable(), PollOpt::level())
+    }
+}
+
+/// A thread-safe handle with which a transport wakes the polling loop of its socket, see
+/// [TransportReadiness::waker].
+#[derive(Debug, Clone)]
+pub struct TransportWaker {
+    set_readiness: SetReadiness,
+}
+
+impl TransportWaker {
+    /// Marks the transport as readable, which wakes the polling loop, or as not readable once every
+    /// waiting datagram was received. The polling loop keeps waking up while the transport is
+    /// readable.
+    pub fn set_readable(&self, readable: bool) -> io::Result<()> {
+        let readiness = if readable {
+            Ready::readable()
+        } else {
+            Ready::empty()
+        };
+        self.set_readiness.set_readiness(readiness)
+    }
+}
+
+/// A thread-safe handle with which packets can be sent through a socket that is busy running its
+/// polling loop. Sending a packet wakes the polling loop.
+#[derive(Debug, Clone)]
+pub struct PacketSender {
+    sender: Sender<(PacketId, Packet)>,
+    next_packet_id: Arc<AtomicU64>,
+    waker: SetReadiness,
+    // Whether sending waits for room when the packet queue is full.
+    block_when_full: bool,
+}
+
+impl PacketSender {
+    /// Constructs a new `PacketSender`, which takes the ids of its packets from the given counter
+    /// and wakes the poller of the given waker. If `block_when_full` is true, sending waits for
+    /// room in a full packet queue.
+    pub(crate) fn new(
+        sender: Sender<(PacketId, Packet)>,
+        next_packet_id: Arc<AtomicU64>,
+        waker: SetReadiness,
+        block_when_full: bool,
+    ) -> PacketSender {
+        PacketSender {
+            sender,
+            next_packet_id,
+            waker,
+            block_when_full,
+        }
+    }
+
+    /// Enqueues a packet to be sent by the socket and returns its id. Returns the packet if the
+    /// packet queue is full, unless `block_on_full_packet_queue` is enabled, or if the socket was
+    /// dropped.
+    ///
+    /// The polling loop rejects reliable packets which would exceed `max_in_flight_bytes`, and
+    /// packets to a connection which holds back `max_paced_packets` packets already. It emits a
+    /// `Lost` event for every rejected packet.
+    pub fn send(&self, packet: Packet) -> Result<PacketId, TrySendError<Packet>> {
+        let id = self.next_id();
+        let result = if self.block_when_full {
+            self.sender
+                .send((id, packet))
+                .map_err(|error| TrySendError::Disconnected(error.0))
+        } else {
+            self.sender.try_send((id, packet))
+        };
+        self.handle_result(id, result)
+    }
+
+    /// Returns the id for the next packet which is sent.
+    pub(crate) fn next_id(&self) -> PacketId {
+        PacketId::next(&self.next_packet_id)
+    }
+
+    #[cfg(feature = "async")]
+    /// Enqueues a packet under the given id without ever blocking, even if
+    /// `block_on_full_packet_queue` is enabled.
+    pub(crate) fn try_send_with_id(
+        &self,
+        id: PacketId,
+        packet: Packet,
+    ) -> Result<PacketId, TrySendError<Packet>> {
+        let result = self.sender.try_send((id, packet));
+        self.handle_result(id, result)
+    }
+
+    #[cfg(feature = "async")]
+    /// Returns true if sending waits for room in a full packet queue.
+    pub(crate) fn blocks_when_full(&self) -> bool {
+        self.block_when_full
+    }
+
+    fn handle_result(
+        &self,
+        id: PacketId,
+        result: Result<(), TrySendError<(PacketId, Packet)>>,
+    ) -> Result<PacketId, TrySendError<Packet>> {
+        match result {
+            Ok(()) => {
+                self.wake();
+                Ok(id)
+            }
+            Err(TrySendError::Full((_, packet))) => Err(TrySendError::Full(packet)),
+            Err(TrySendError::Disconnected((_, packet))) => Err(TrySendError::Disconnected(packet)),
+        }
+    }
+
+    /// Wakes the polling loop of the socket without sending a packet.
+    pub(crate) fn wake(&self) {
+        if let Err(e) = self.waker.set_readiness(Ready::readable()) {
+            error!("Could not wake the polling loop: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Poller, TransportReadiness, TransportWaker};
+    use crate::net::DatagramTransport;
+    use std::{
+        io,
+        net::{SocketAddr, UdpSocket},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    const WAIT: Duration = Duration::from_millis(50);
+
+    // Returns how long the poller waited, at most `WAIT`.
+    fn waited(poller: &mut Poller) -> Duration {
+        let start = Instant::now();
+        poller.wait(Some(WAIT)).unwrap();
+        start.elapsed()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn udp_socket_wakes_the_poller() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut poller = Poller::new(&socket, 16).unwrap();
+        assert!(poller.transport_registered);
+
+        // The poller only wakes up once a datagram arrives.
+        assert!(waited(&mut poller) >= WAIT);
+
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(&[1, 2, 3], socket.local_addr().unwrap())
+            .unwrap();
+        assert!(waited(&mut poller) < WAIT);
+    }
+
+    // A transport which wakes the poller with a `TransportWaker`.
+    struct WakingTransport {
+        waker: Arc<Mutex<Option<TransportWaker>>>,
+    }
+
+    impl DatagramTransport for WakingTransport {
+        fn send_to(&mut self, payload: &[u8], _address: SocketAddr) -> io::Result<usize> {
+            Ok(payload.len())
+        }
+
+        fn recv_from(&mut self, _buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok("10.0.0.1:1000".parse().unwrap())
+        }
+
+        fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn register(&self, readiness: &mut TransportReadiness<'_>) -> io::Result<bool> {
+            *self.waker.lock().unwrap() = Some(readiness.waker()?);
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn transport_waker_wakes_the_poller() {
+        let waker = Arc::new(Mutex::new(None));
+        let transport = WakingTransport {
+            waker: waker.clone(),
+        };
+        let mut poller = Poller::new(&transport, 16).unwrap();
+        let waker = waker.lock().unwrap().take().unwrap();
+
+        assert!(waited(&mut poller) >= WAIT);
+
+        waker.set_readable(true).unwrap();
+        assert!(waited(&mut poller) < WAIT);
+        // The poller keeps waking up until the transport is not readable anymore.
+        assert!(waited(&mut poller) < WAIT);
+
+        // Changing the readiness may wake the poller once more.
+        waker.set_readable(false).unwrap();
+        waited(&mut poller);
+        assert!(waited(&mut poller) >= WAIT);
+    }
+}
@@ -1,10 +1,9 @@
 use crate::config::Config;
-use crate::sequence_buffer::CongestionData;
 
 use std::time::Duration;
 
 /// Represents the quality of a network.
-#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum NetworkQuality {
     /// Connection is generally good, minimal packet loss or latency
     Good,
@@ -12,11 +11,25 @@ pub enum NetworkQuality {
     Bad,
 }
 
-/// This type helps with calculating the round trip time from any packet.
-/// It is able to smooth out the network jitter if there is any.
+/// The gain with which an acknowledged or lost packet updates the recent packet loss.
+const PACKET_LOSS_SMOOTHING_FACTOR: f32 = 0.05;
+/// The gain with which a sample updates the round trip time variance, as recommended by RFC 6298.
+const RTT_VARIANCE_FACTOR: f64 = 0.25;
+/// The retransmission timeout is the smoothed round trip time plus this many times its variance.
+const RTT_VARIANCE_MULTIPLIER: u32 = 4;
+/// The upper bound of the retransmission timeout, as recommended by RFC 6298.
+const MAX_RETRANSMISSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// This type helps with calculating the round trip time (rtt) from acknowledged packets.
+///
+/// It estimates the smoothed rtt, the rtt variance and the minimal rtt as described by
+/// [RFC 6298](https://tools.ietf.org/html/rfc6298), so a single late packet does not directly bring
+/// down the network quality estimation.
 pub struct RttMeasurer {
     config: Config,
-    rtt: f32,
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    min_rtt: Option<Duration>,
 }
 
 impl RttMeasurer {
@@ -24,63 +37,139 @@ impl RttMeasurer {
     pub fn new(config: &Config) -> RttMeasurer {
         RttMeasurer {
             config: config.clone(),
-            rtt: 0.,
+            smoothed_rtt: None,
+            rtt_variance: Duration::default(),
+            min_rtt: None,
+        }
+    }
+
+    /// Updates the estimation with the round trip time measured for an acknowledged packet.
+    ///
+    /// The first sample initializes the smoothed rtt and half of it the variance, later samples are
+    /// blended in with `Config::rtt_smoothing_factor` and the variance gain of 1/4.
+    pub fn update(&mut self, rtt: Duration) {
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+
+        match self.smoothed_rtt {
+            Some(smoothed_rtt) => {
+                let smoothing_factor = f64::from(self.config.rtt_smoothing_factor);
+                let deviation = rtt.abs_diff(smoothed_rtt);
+
+                self.rtt_variance = self.rtt_variance.mul_f64(1. - RTT_VARIANCE_FACTOR)
+                    + deviation.mul_f64(RTT_VARIANCE_FACTOR);
+                self.smoothed_rtt = Some(
+                    smoothed_rtt.mul_f64(1. - smoothing_factor) + rtt.mul_f64(smoothing_factor),
+                );
+            }
+            None => {
+                self.smoothed_rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            }
         }
     }
 
-    /// This will calculate the round trip time (rtt) from the given acknowledgment.
-    /// Where after it updates the rtt from the given connection.
-    pub fn calculate_rrt(&mut self, congestion_data: Option<&mut CongestionData>) {
-        self.rtt = self.get_smoothed_rtt(congestion_data);
+    /// Returns the smoothed round trip time, if any packet was measured yet.
+    pub fn smoothed_rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
     }
 
-    #[cfg(test)]
-    pub fn get_rtt(&self) -> f32 {
-        return self.rtt;
+    /// Returns the variance of the round trip time, which is a measure of the network jitter.
+    pub fn rtt_variance(&self) -> Duration {
+        self.rtt_variance
     }
 
-    /// This will get the smoothed round trip time (rtt) from the time we last heard from a packet.
-    fn get_smoothed_rtt(&self, congestion_avoidance_entry: Option<&mut CongestionData>) -> f32 {
-        match congestion_avoidance_entry {
-            Some(avoidance_data) => {
-                let elapsed_time = avoidance_data.sending_time.elapsed();
+    /// Returns the lowest round trip time which was measured, if any packet was measured yet.
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
 
-                let rtt_time = self.as_milliseconds(elapsed_time);
+    /// Returns the duration after which an unacknowledged packet should be considered lost.
+    ///
+    /// This is the smoothed round trip time (rtt) plus four times its variance, but never less than
+    /// the maximal allowed rtt, which is also used before any packet was measured.
+    pub fn retransmission_timeout(&self) -> Duration {
+        let rtt_max_value = Duration::from_millis(u64::from(self.config.rtt_max_value));
+        let estimated_timeout = self
+            .smoothed_rtt
+            .map_or(Duration::default(), |smoothed_rtt| {
+                smoothed_rtt + self.rtt_variance * RTT_VARIANCE_MULTIPLIER
+            });
 
-                self.smooth_out_rtt(rtt_time)
-            }
-            None => 0.0,
+        estimated_timeout
+            .max(rtt_max_value)
+            .min(MAX_RETRANSMISSION_TIMEOUT)
+    }
+}
+
+/// Decides the quality of a connection from its round trip time and its recent packet loss.
+///
+/// The quality turns `Bad` as soon as either exceeds its threshold, and only turns `Good` again once
+/// both fell below their thresholds reduced by `Config::quality_hysteresis`.
+pub struct QualityMonitor {
+    rtt_threshold: Duration,
+    packet_loss_threshold: f32,
+    hysteresis: f32,
+    packet_loss: f32,
+    quality: NetworkQuality,
+}
+
+impl QualityMonitor {
+    /// Creates a new `QualityMonitor`, which considers the connection `Good` until it measured otherwise.
+    pub fn new(config: &Config) -> QualityMonitor {
+        QualityMonitor {
+            rtt_threshold: Duration::from_millis(u64::from(config.rtt_max_value)),
+            packet_loss_threshold: config.packet_loss_threshold,
+            hysteresis: config.quality_hysteresis,
+            packet_loss: 0.,
+            quality: NetworkQuality::Good,
         }
     }
 
-    /// Converts a duration to milliseconds.
-    ///
-    /// `as_milliseconds` is not supported yet supported in rust stable.
-    /// See this stackoverflow post for more info: https://stackoverflow.com/questions/36816072/how-do-i-get-a-duration-as-a-number-of-milliseconds-in-rust
-    fn as_milliseconds(&self, duration: Duration) -> u64 {
-        let nanos = u64::from(duration.subsec_nanos());
-        (1000 * 1000 * 1000 * duration.as_secs() + nanos) / (1000 * 1000)
+    /// Counts a packet which was acknowledged.
+    pub fn process_ack(&mut self) {
+        self.packet_loss *= 1. - PACKET_LOSS_SMOOTHING_FACTOR;
     }
 
-    /// Smooth out round trip time (rtt) value by the specified smoothing factor.
-    ///
-    /// First we subtract the max allowed rtt.
-    /// This way we can see by how many we are off from the max allowed rtt.
-    /// Then we multiply with or smoothing factor.
-    ///
-    /// We do this so that if one packet has an bad rtt it will not directly bring down the or network quality estimation.
-    /// The default is 10% smoothing so if in total or packet is 50 milliseconds later than max allowed rtt we will increase or rtt estimation with 5.
-    fn smooth_out_rtt(&self, rtt: u64) -> f32 {
-        let exceeded_rrt_time = rtt as i64 - i64::from(self.config.rtt_max_value);
-        exceeded_rrt_time as f32 * self.config.rtt_smoothing_factor
+    /// Counts a packet which was considered lost.
+    pub fn process_loss(&mut self) {
+        self.packet_loss =
+            self.packet_loss * (1. - PACKET_LOSS_SMOOTHING_FACTOR) + PACKET_LOSS_SMOOTHING_FACTOR;
+    }
+
+    /// Re-evaluates the quality with the given smoothed round trip time, and returns the new quality
+    /// if it changed.
+    pub fn update(&mut self, smoothed_rtt: Option<Duration>) -> Option<NetworkQuality> {
+        let rtt = smoothed_rtt.unwrap_or_default();
+
+        let quality = match self.quality {
+            NetworkQuality::Good
+                if rtt > self.rtt_threshold || self.packet_loss > self.packet_loss_threshold =>
+            {
+                NetworkQuality::Bad
+            }
+            NetworkQuality::Bad
+                if rtt < self.rtt_threshold.mul_f32(1. - self.hysteresis)
+                    && self.packet_loss < self.packet_loss_threshold * (1. - self.hysteresis) =>
+            {
+                NetworkQuality::Good
+            }
+            quality => quality,
+        };
+
+        if quality == self.quality {
+            return None;
+        }
+        self.quality = quality;
+        Some(quality)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::RttMeasurer;
+    use super::{QualityMonitor, RttMeasurer};
     use crate::config::Config;
     use crate::net::connection::VirtualConnection;
+    use crate::net::NetworkQuality;
     use std::net::ToSocketAddrs;
     use std::time::{Duration, Instant};
 
@@ -97,28 +186,138 @@ mod test {
     }
 
     #[test]
-    fn convert_duration_to_milliseconds_test() {
-        let network_quality = RttMeasurer::new(&Config::default());
-        let milliseconds1 = network_quality.as_milliseconds(Duration::from_secs(1));
-        let milliseconds2 = network_quality.as_milliseconds(Duration::from_millis(1500));
-        let milliseconds3 = network_quality.as_milliseconds(Duration::from_millis(1671));
+    fn first_sample_initializes_the_estimation() {
+        let mut rtt_measurer = RttMeasurer::new(&Config::default());
+        assert_eq!(rtt_measurer.smoothed_rtt(), None);
+        assert_eq!(rtt_measurer.min_rtt(), None);
+
+        rtt_measurer.update(Duration::from_millis(100));
+
+        assert_eq!(
+            rtt_measurer.smoothed_rtt(),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(rtt_measurer.rtt_variance(), Duration::from_millis(50));
+        assert_eq!(rtt_measurer.min_rtt(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn samples_are_blended_into_the_estimation() {
+        let config = Config {
+            // for test purpose make sure we set smoothing factor to 10%.
+            rtt_smoothing_factor: 0.10,
+            ..Config::default()
+        };
+        let mut rtt_measurer = RttMeasurer::new(&config);
+
+        rtt_measurer.update(Duration::from_millis(100));
+        rtt_measurer.update(Duration::from_millis(300));
+
+        // 10% of the 200ms by which the sample exceeds the estimation is added to it.
+        assert_eq!(
+            rtt_measurer.smoothed_rtt(),
+            Some(Duration::from_millis(120))
+        );
+        // 3/4 of the previous 50ms variance plus 1/4 of the 200ms deviation.
+        assert_eq!(rtt_measurer.rtt_variance(), Duration::from_micros(87_500));
+        assert_eq!(rtt_measurer.min_rtt(), Some(Duration::from_millis(100)));
+
+        rtt_measurer.update(Duration::from_millis(20));
+        assert_eq!(
+            rtt_measurer.smoothed_rtt(),
+            Some(Duration::from_millis(110))
+        );
+        assert_eq!(rtt_measurer.min_rtt(), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn retransmission_timeout_follows_the_estimation() {
+        let config = Config {
+            rtt_max_value: 250,
+            ..Config::default()
+        };
+        let mut rtt_measurer = RttMeasurer::new(&config);
+        assert_eq!(
+            rtt_measurer.retransmission_timeout(),
+            Duration::from_millis(250)
+        );
+
+        // A fast connection is not resent to before the maximal allowed rtt.
+        rtt_measurer.update(Duration::from_millis(20));
+        assert_eq!(
+            rtt_measurer.retransmission_timeout(),
+            Duration::from_millis(250)
+        );
+
+        let mut rtt_measurer = RttMeasurer::new(&config);
+        rtt_measurer.update(Duration::from_millis(200));
+        // 200ms smoothed rtt plus four times the 100ms variance.
+        assert_eq!(
+            rtt_measurer.retransmission_timeout(),
+            Duration::from_millis(600)
+        );
+
+        rtt_measurer.update(Duration::from_secs(100));
+        assert_eq!(
+            rtt_measurer.retransmission_timeout(),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn quality_turns_bad_when_rtt_exceeds_the_threshold() {
+        let config = Config {
+            rtt_max_value: 250,
+            quality_hysteresis: 0.20,
+            ..Config::default()
+        };
+        let mut quality_monitor = QualityMonitor::new(&config);
+
+        assert_eq!(quality_monitor.update(None), None);
+        assert_eq!(
+            quality_monitor.update(Some(Duration::from_millis(300))),
+            Some(NetworkQuality::Bad)
+        );
 
-        assert_eq!(milliseconds1, 1000);
-        assert_eq!(milliseconds2, 1500);
-        assert_eq!(milliseconds3, 1671);
+        // The rtt has to fall below 200ms before the quality is good again.
+        assert_eq!(
+            quality_monitor.update(Some(Duration::from_millis(240))),
+            None
+        );
+        assert_eq!(
+            quality_monitor.update(Some(Duration::from_millis(190))),
+            Some(NetworkQuality::Good)
+        );
+        assert_eq!(
+            quality_monitor.update(Some(Duration::from_millis(240))),
+            None
+        );
     }
 
     #[test]
-    fn smooth_out_rtt() {
-        let mut config = Config::default();
-        // for test purpose make sure we set smoothing factor to 10%.
-        config.rtt_smoothing_factor = 0.10;
-        config.rtt_max_value = 250;
+    fn quality_turns_bad_when_packet_loss_exceeds_the_threshold() {
+        let config = Config {
+            packet_loss_threshold: 0.10,
+            quality_hysteresis: 0.20,
+            ..Config::default()
+        };
+        let mut quality_monitor = QualityMonitor::new(&config);
+        let rtt = Some(Duration::from_millis(50));
 
-        let network_quality = RttMeasurer::new(&config);
-        let smoothed_rtt = network_quality.smooth_out_rtt(300);
+        // A single lost packet does not make the quality bad.
+        quality_monitor.process_loss();
+        assert_eq!(quality_monitor.update(rtt), None);
 
-        // 300ms has exceeded 50ms over the max allowed rtt. So we check if or smoothing factor is now 10% from 50.
-        assert_eq!(smoothed_rtt, 5.0);
+        for _ in 0..2 {
+            quality_monitor.process_loss();
+        }
+        assert_eq!(quality_monitor.update(rtt), Some(NetworkQuality::Bad));
+
+        // The recent packet loss has to fall below 8% before the quality is good again.
+        while quality_monitor.packet_loss >= 0.08 {
+            assert_eq!(quality_monitor.update(rtt), None);
+            quality_monitor.process_ack();
+        }
+        assert_eq!(quality_monitor.update(rtt), Some(NetworkQuality::Good));
     }
 }
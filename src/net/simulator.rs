@@ -0,0 +1,431 @@
+//! This module provides a deterministic, in-memory network on which sockets can be tested without
+//! binding real ports or sleeping.
+//!
+//! The [NetworkSimulator] owns the sockets of the network and a virtual clock, which only advances
+//! when asked to. Every tick the datagrams whose delivery time has come are delivered and every socket
+//! is polled with the virtual time. Packet loss, latency and jitter are drawn from a seeded random
+//! number generator, so a simulation with the same seed always plays out the same way.
+
+use crate::{
+    config::Config,
+    error::Result,
+    net::{socket::Socket, DatagramTransport},
+};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg as Random;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// The conditions every datagram of a [NetworkSimulator] is subjected to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// Value between 0 and 1, representing the chance a datagram is dropped.
+    pub packet_loss: f64,
+    /// The time it takes a datagram to arrive.
+    pub latency: Duration,
+    /// The maximal additional delay of a datagram, which is drawn uniformly for each datagram. Datagrams
+    /// which are sent shortly after each other may overtake each other, so a jitter larger than the
+    /// interval between datagrams reorders them.
+    pub jitter: Duration,
+}
+
+/// An in-memory network of sockets, which runs on a virtual clock.
+///
+/// ```
+/// use laminar::{Config, NetworkSimulator, Packet, SocketEvent};
+/// use std::time::Duration;
+///
+/// let mut simulator = NetworkSimulator::new(42);
+/// let server = "10.0.0.1:1000".parse().unwrap();
+/// let client = "10.0.0.2:1000".parse().unwrap();
+/// simulator.add_socket(server, Config::default()).unwrap();
+/// simulator.add_socket(client, Config::default()).unwrap();
+///
+/// simulator
+///     .socket(client)
+///     .send(Packet::reliable_unordered(server, vec![1, 2, 3]))
+///     .unwrap();
+/// simulator.advance(Duration::from_millis(10));
+///
+/// assert_eq!(
+///     simulator.socket(server).recv(),
+///     Some(SocketEvent::Connect(client, None))
+/// );
+/// ```
+#[derive(Debug)]
+pub struct NetworkSimulator {
+    network: SimulatedNetwork,
+    sockets: BTreeMap<SocketAddr, Socket<SimulatedTransport>>,
+    tick: Duration,
+}
+
+impl NetworkSimulator {
+    /// Constructs a new, empty `NetworkSimulator`, whose random decisions are derived from the given
+    /// seed. The virtual clock starts at the current time and advances in ticks of one millisecond.
+    pub fn new(seed: u64) -> NetworkSimulator {
+        NetworkSimulator {
+            network: SimulatedNetwork {
+                state: Arc::new(Mutex::new(NetworkState {
+                    conditions: NetworkConditions::default(),
+                    random: Random::seed_from_u64(seed),
+                    time: Instant::now(),
+                    sent: 0,
+                    in_flight: BinaryHeap::new(),
+                    inboxes: HashMap::new(),
+                })),
+            },
+            sockets: BTreeMap::new(),
+            tick: Duration::from_millis(1),
+        }
+    }
+
+    /// Sets the conditions all datagrams sent from now on are subjected to.
+    pub fn set_conditions(&mut self, conditions: NetworkConditions) {
+        self.network.state().conditions = conditions;
+    }
+
+    /// Sets the interval at which datagrams are delivered and sockets are polled while the clock
+    /// advances.
+    pub fn set_tick(&mut self, tick: Duration) {
+        assert!(tick > Duration::from_secs(0), "The tick must not be zero");
+        self.tick = tick;
+    }
+
+    /// Returns the current time of the virtual clock.
+    pub fn now(&self) -> Instant {
+        self.network.state().time
+    }
+
+    /// Adds a socket with the given config to the network, bound to the given address. The socket is
+    /// always non-blocking, regardless of `blocking_mode`. Returns an error of the kind `AddrInUse`
+    /// if a socket is bound to the address already.
+    pub fn add_socket(&mut self, address: SocketAddr, mut config: Config) -> Result<()> {
+        if self.sockets.contains_key(&address) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse).into());
+        }
+
+        config.blocking_mode = false;
+        let transport = SimulatedTransport {
+            address,
+            network: self.network.clone(),
+        };
+        self.network
+            .state()
+            .inboxes
+            .insert(address, VecDeque::new());
+        self.sockets
+            .insert(address, Socket::with_transport(transport, config)?);
+        Ok(())
+    }
+
+    /// Removes the socket bound to the given address from the network. Datagrams sent to the address
+    /// from now on are dropped.
+    pub fn remove_socket(&mut self, address: SocketAddr) -> Option<Socket<SimulatedTransport>> {
+        self.network.state().inboxes.remove(&address);
+        self.sockets.remove(&address)
+    }
+
+    /// Returns the socket bound to the given address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no socket is bound to the address.
+    pub fn socket(&mut self, address: SocketAddr) -> &mut Socket<SimulatedTransport> {
+        self.sockets
+            .get_mut(&address)
+            .unwrap_or_else(|| panic!("No socket is bound to {}", address))
+    }
+
+    /// Advances the virtual clock by the given duration, one tick at a time. Every tick, the datagrams
+    /// which have arrived are delivered and then every socket is polled. Datagrams therefore arrive
+    /// no earlier than the tick after they were sent.
+    pub fn advance(&mut self, duration: Duration) {
+        let end = self.now() + duration;
+
+        loop {
+            let time = (self.now() + self.tick).min(end);
+            self.network.deliver(time);
+
+            for socket in self.sockets.values_mut() {
+                socket.manual_poll(time);
+            }
+
+            if time >= end {
+                break;
+            }
+        }
+    }
+}
+
+// The state shared between the simulator and the transports of its sockets.
+#[derive(Debug, Clone)]
+struct SimulatedNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+#[derive(Debug)]
+struct NetworkState {
+    conditions: NetworkConditions,
+    random: Random,
+    time: Instant,
+    // The number of datagrams sent, which orders datagrams arriving at the same time.
+    sent: u64,
+    in_flight: BinaryHeap<InFlight>,
+    // The datagrams which have arrived at each bound address.
+    inboxes: HashMap<SocketAddr, VecDeque<Datagram>>,
+}
+
+// A datagram which has arrived, with the address it came from.
+type Datagram = (Box<[u8]>, SocketAddr);
+
+impl SimulatedNetwork {
+    fn state(&self) -> MutexGuard<'_, NetworkState> {
+        self.state.lock().expect("Network lock poisoned")
+    }
+
+    // Moves the clock to the given time and delivers the datagrams which have arrived by then.
+    fn deliver(&self, time: Instant) {
+        let mut state = self.state();
+        state.time = time;
+
+        while state
+            .in_flight
+            .peek()
+            .map(|datagram| datagram.arrival <= time)
+            .unwrap_or(false)
+        {
+            let datagram = state.in_flight.pop().expect("A datagram was peeked");
+            if let Some(inbox) = state.inboxes.get_mut(&datagram.destination) {
+                inbox.push_back((datagram.payload, datagram.source));
+            }
+        }
+    }
+
+    fn send(&self, payload: &[u8], source: SocketAddr, destination: SocketAddr) {
+        let mut state = self.state();
+        let conditions = state.conditions.clone();
+
+        if state.random.gen_range(0.0, 1.0) < conditions.packet_loss {
+            return;
+        }
+
+        let jitter = if conditions.jitter > Duration::from_secs(0) {
+            let nanos = conditions.jitter.as_nanos() as u64;
+            Duration::from_nanos(state.random.gen_range(0, nanos + 1))
+        } else {
+            Duration::from_secs(0)
+        };
+
+        let datagram = InFlight {
+            arrival: state.time + conditions.latency + jitter,
+            sequence: state.sent,
+            source,
+            destination,
+            payload: payload.into(),
+        };
+        state.sent += 1;
+        state.in_flight.push(datagram);
+    }
+}
+
+// A datagram travelling through the network.
+#[derive(Debug)]
+struct InFlight {
+    arrival: Instant,
+    sequence: u64,
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: Box<[u8]>,
+}
+
+impl Ord for InFlight {
+    // The heap pops the datagram which arrives first, and of those the one which was sent first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.arrival, other.sequence).cmp(&(self.arrival, self.sequence))
+    }
+}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        self.sequence == other.sequence
+    }
+}
+
+impl Eq for InFlight {}
+
+/// The [DatagramTransport] of a socket in a [NetworkSimulator]. It never blocks.
+#[derive(Debug)]
+pub struct SimulatedTransport {
+    address: SocketAddr,
+    network: SimulatedNetwork,
+}
+
+impl DatagramTransport for SimulatedTransport {
+    fn send_to(&mut self, payload: &[u8], address: SocketAddr) -> io::Result<usize> {
+        self.network.send(payload, self.address, address);
+        Ok(payload.len())
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let datagram = self
+            .network
+            .state()
+            .inboxes
+            .get_mut(&self.address)
+            .and_then(VecDeque::pop_front);
+        let (datagram, address) = datagram.ok_or(io::ErrorKind::WouldBlock)?;
+
+        // Like UDP, the part of the datagram which does not fit into the buffer is discarded.
+        let len = datagram.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&datagram[..len]);
+        Ok((len, address))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.address)
+    }
+
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NetworkConditions, NetworkSimulator};
+    use crate::{Config, Packet, SocketEvent};
+    use std::{net::SocketAddr, time::Duration};
+
+    fn server_addr() -> SocketAddr {
+        "10.0.0.1:1000".parse().unwrap()
+    }
+
+    fn client_addr() -> SocketAddr {
+        "10.0.0.2:1000".parse().unwrap()
+    }
+
+    fn simulator(seed: u64, conditions: NetworkConditions) -> NetworkSimulator {
+        let mut simulator = NetworkSimulator::new(seed);
+        simulator.set_conditions(conditions);
+        simulator
+            .add_socket(server_addr(), Config::default())
+            .unwrap();
+        simulator
+            .add_socket(client_addr(), Config::default())
+            .unwrap();
+        simulator
+    }
+
+    // Sends numbered packets from the client and returns the numbers of the packets the server
+    // received.
+    fn exchange(simulator: &mut NetworkSimulator) -> Vec<u8> {
+        for number in 0..50 {
+            simulator
+                .socket(client_addr())
+                .send(Packet::unreliable(server_addr(), vec![number]))
+                .unwrap();
+            simulator.advance(Duration::from_millis(5));
+        }
+        simulator.advance(Duration::from_millis(500));
+
+        let mut numbers = Vec::new();
+        while let Some(event) = simulator.socket(server_addr()).recv() {
+            if let SocketEvent::Packet(packet) = event {
+                numbers.push(packet.payload()[0]);
+            }
+        }
+        numbers
+    }
+
+    #[test]
+    fn datagrams_arrive_after_latency() {
+        let mut simulator = simulator(
+            0,
+            NetworkConditions {
+                latency: Duration::from_millis(100),
+                ..NetworkConditions::default()
+            },
+        );
+
+        // The handshake takes three trips through the network, and the client only learns that it
+        // was accepted after a fourth.
+        simulator
+            .socket(client_addr())
+            .send(Packet::unreliable(server_addr(), vec![1]))
+            .unwrap();
+        simulator.advance(Duration::from_millis(300));
+        assert_eq!(simulator.socket(server_addr()).recv(), None);
+
+        simulator.advance(Duration::from_millis(3));
+        assert_eq!(
+            simulator.socket(server_addr()).recv(),
+            Some(SocketEvent::Connect(client_addr(), None))
+        );
+        simulator.advance(Duration::from_millis(200));
+        while simulator.socket(server_addr()).recv().is_some() {}
+
+        // The packet is sent on the next tick and arrives a tick after its latency.
+        simulator
+            .socket(client_addr())
+            .send(Packet::unreliable(server_addr(), vec![2]))
+            .unwrap();
+
+        simulator.advance(Duration::from_millis(100));
+        assert_eq!(simulator.socket(server_addr()).recv(), None);
+
+        simulator.advance(Duration::from_millis(1));
+        assert_eq!(
+            simulator.socket(server_addr()).recv(),
+            Some(SocketEvent::Packet(Packet::unreliable(
+                client_addr(),
+                vec![2]
+            )))
+        );
+    }
+
+    #[test]
+    fn simulation_with_same_seed_is_reproducible() {
+        let conditions = NetworkConditions {
+            packet_loss: 0.3,
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(30),
+        };
+
+        let first = exchange(&mut simulator(7, conditions.clone()));
+        let second = exchange(&mut simulator(7, conditions.clone()));
+        let other = exchange(&mut simulator(8, conditions));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+
+        // Some packets are lost and the jitter reorders others.
+        assert!(first.len() < 50);
+        assert!(first.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn address_can_only_be_added_once() {
+        let mut simulator = simulator(0, NetworkConditions::default());
+
+        assert!(simulator
+            .add_socket(server_addr(), Config::default())
+            .is_err());
+
+        assert!(simulator.remove_socket(server_addr()).is_some());
+        assert!(simulator
+            .add_socket(server_addr(), Config::default())
+            .is_ok());
+    }
+}
@@ -1,32 +1,70 @@
-use crate::either::Either::{Left, Right};
 use crate::{
     config::Config,
-    error::{ErrorKind, Result},
-    net::{connection::ActiveConnections, events::SocketEvent, link_conditioner::LinkConditioner},
-    packet::{DeliveryGuarantee, Outgoing, Packet},
+    error::{BackpressureErrorKind, ConnectTokenErrorKind, ErrorKind, Result},
+    infrastructure::ReassemblyStats,
+    net::{
+        connect_token::{ConnectToken, PrivateConnectToken},
+        connection::ActiveConnections,
+        events::{send_event, DisconnectReason, SocketEvent},
+        handshake::{
+            connection_accepted_packet, derive_connection_keys, handshake_packet,
+            read_challenge_response, read_connection_accepted, read_connection_request,
+            read_cookie, ChallengeCookie, ChallengeIssuer,
+        },
+        link_conditioner::{DelayQueue, LinkConditioner},
+        poller::{PacketSender, Poller},
+        stats::ConnectionStats,
+        transport::DatagramTransport,
+        virtual_connection::ConnectionState,
+    },
+    packet::{
+        header::AckWindow, DeliveryGuarantee, Outgoing, Packet, PacketId, PacketReader, PacketType,
+        SequenceNumber,
+    },
 };
-use crossbeam_channel::{self, unbounded, Receiver, SendError, Sender, TryRecvError};
-use log::error;
+use crossbeam_channel::{
+    self, bounded, unbounded, Receiver, SendError, Sender, TryRecvError, TrySendError,
+};
+use log::{debug, error, warn};
 use std::{
-    self, io,
+    self,
+    borrow::Cow,
+    collections::HashMap,
+    io,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
-    thread::{sleep, yield_now},
-    time::{Duration, Instant},
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant, SystemTime},
 };
 
 /// A reliable UDP socket implementation with configurable reliability and ordering guarantees.
+///
+/// The socket runs on a UDP socket by default, but can run on any [DatagramTransport].
 #[derive(Debug)]
-pub struct Socket {
-    socket: UdpSocket,
+pub struct Socket<T = UdpSocket> {
+    socket: T,
     config: Config,
     connections: ActiveConnections,
+    challenges: ChallengeIssuer,
+    // The moment the socket was built on both clocks, so the expiry of connect tokens can be
+    // compared with the time of a poll.
+    created: (Instant, SystemTime),
     recv_buffer: Vec<u8>,
+    poller: Poller,
     link_conditioner: Option<LinkConditioner>,
+    inbound_link_conditioner: Option<LinkConditioner>,
+    // The packets held back by the link conditioners.
+    outbound_delay_queue: DelayQueue,
+    inbound_delay_queue: DelayQueue,
     event_sender: Sender<SocketEvent>,
-    packet_receiver: Receiver<Packet>,
+    packet_receiver: Receiver<(PacketId, Packet)>,
+    // The id of the next packet that is sent, shared with the packet senders.
+    next_packet_id: Arc<AtomicU64>,
+    // The reliable bytes sent to each address which wait in the packet queue, when the amount of
+    // reliable bytes in flight is limited.
+    queued_reliable_bytes: HashMap<SocketAddr, usize>,
 
     receiver: Receiver<SocketEvent>,
-    sender: Sender<Packet>,
+    sender: Sender<(PacketId, Packet)>,
 }
 
 enum UdpSocketState {
@@ -52,7 +90,7 @@ impl Socket {
         let loopback = Ipv4Addr::new(127, 0, 0, 1);
         let address = SocketAddrV4::new(loopback, 0);
         let socket = UdpSocket::bind(address)?;
-        Self::bind_internal(socket, config)
+        Self::with_transport(socket, config)
     }
 
     /// Binds to the socket and then sets up `ActiveConnections` to manage the "connections".
@@ -62,21 +100,39 @@ impl Socket {
     /// This function allows you to configure laminar with the passed configuration.
     pub fn bind_with_config<A: ToSocketAddrs>(addresses: A, config: Config) -> Result<Self> {
         let socket = UdpSocket::bind(addresses)?;
-        Self::bind_internal(socket, config)
+        Self::with_transport(socket, config)
     }
+}
 
-    fn bind_internal(socket: UdpSocket, config: Config) -> Result<Self> {
+impl<T: DatagramTransport> Socket<T> {
+    /// Sets up `ActiveConnections` on top of the given transport, with the given configuration.
+    pub fn with_transport(mut socket: T, config: Config) -> Result<Self> {
+        config.validate()?;
         socket.set_nonblocking(!config.blocking_mode)?;
-        let (event_sender, event_receiver) = unbounded();
-        let (packet_sender, packet_receiver) = unbounded();
+        let (event_sender, event_receiver) = match config.event_queue_size {
+            Some(size) => bounded(size),
+            None => unbounded(),
+        };
+        let (packet_sender, packet_receiver) = match config.packet_queue_size {
+            Some(size) => bounded(size),
+            None => unbounded(),
+        };
         Ok(Socket {
             recv_buffer: vec![0; config.receive_buffer_max_size],
+            poller: Poller::new(&socket, config.socket_event_buffer_size)?,
             socket,
             config,
             connections: ActiveConnections::new(),
+            challenges: ChallengeIssuer::new(Instant::now()),
+            created: (Instant::now(), SystemTime::now()),
             link_conditioner: None,
+            inbound_link_conditioner: None,
+            outbound_delay_queue: DelayQueue::default(),
+            inbound_delay_queue: DelayQueue::default(),
             event_sender,
             packet_receiver,
+            next_packet_id: Arc::new(AtomicU64::new(0)),
+            queued_reliable_bytes: HashMap::new(),
 
             sender: packet_sender,
             receiver: event_receiver,
@@ -86,8 +142,13 @@ impl Socket {
     /// Returns a handle to the packet sender which provides a thread-safe way to enqueue packets
     /// to be processed. This should be used when the socket is busy running its polling loop in a
     /// separate thread.
-    pub fn get_packet_sender(&mut self) -> Sender<Packet> {
-        self.sender.clone()
+    pub fn get_packet_sender(&mut self) -> PacketSender {
+        PacketSender::new(
+            self.sender.clone(),
+            self.next_packet_id.clone(),
+            self.poller.waker(),
+            self.config.block_on_full_packet_queue,
+        )
     }
 
     /// Returns a handle to the event receiver which provides a thread-safe way to retrieve events
@@ -98,12 +159,43 @@ impl Socket {
     }
 
     /// Send a packet
-    pub fn send(&mut self, packet: Packet) -> Result<()> {
-        match self.sender.send(packet) {
-            Ok(_) => Ok(()),
-            Err(error) => Err(ErrorKind::SendError(SendError(SocketEvent::Packet(
-                error.0,
-            )))),
+    ///
+    /// Returns the id with which the `Acked` and `Lost` events refer to the packet.
+    ///
+    /// Fails with a `BackpressureError` when the packet is reliable and the connection already has
+    /// `max_in_flight_bytes` unacknowledged, when the connection holds back `max_paced_packets`
+    /// packets, or when the packet queue is full (see [Config]). Unlike a `PacketSender`, this never
+    /// waits for room in the packet queue, because only the polling of this socket makes room.
+    pub fn send(&mut self, packet: Packet) -> Result<PacketId> {
+        let addr = packet.addr();
+        if !packet.is_large_message() && self.connections.is_pacer_full(&addr) {
+            return Err(BackpressureErrorKind::PacingQueueFull.into());
+        }
+
+        let reliable_bytes = match packet.delivery_guarantee() {
+            DeliveryGuarantee::Reliable
+                if self.config.max_in_flight_bytes.is_some() && !packet.is_large_message() =>
+            {
+                packet.payload().len()
+            }
+            _ => 0,
+        };
+        if reliable_bytes > 0 && self.exceeds_in_flight_limit(&addr, reliable_bytes) {
+            return Err(BackpressureErrorKind::InFlightLimitReached.into());
+        }
+
+        let id = PacketId::next(&self.next_packet_id);
+        match self.sender.try_send((id, packet)) {
+            Ok(_) => {
+                if reliable_bytes > 0 {
+                    *self.queued_reliable_bytes.entry(addr).or_insert(0) += reliable_bytes;
+                }
+                Ok(id)
+            }
+            Err(TrySendError::Full(_)) => Err(BackpressureErrorKind::PacketQueueFull.into()),
+            Err(TrySendError::Disconnected((_, packet))) => {
+                Err(ErrorKind::SendError(SendError(SocketEvent::Packet(packet))))
+            }
         }
     }
 
@@ -117,21 +209,52 @@ impl Socket {
     }
 
     /// Entry point to the run loop. This should run in a spawned thread since calls to `poll.poll`
-    /// are blocking. We will default this to sleeping for 1ms.
+    /// are blocking. Between polls the loop blocks for at most `socket_polling_timeout` (from config).
     pub fn start_polling(&mut self) {
-        self.start_polling_with_duration(Some(Duration::from_millis(1)))
+        self.start_polling_with_duration(self.config.socket_polling_timeout)
     }
 
-    /// Run the polling loop with a specified sleep duration. This should run in a spawned thread
-    /// since calls to `poll.poll` are blocking.
-    pub fn start_polling_with_duration(&mut self, sleep_duration: Option<Duration>) {
+    /// Run the polling loop, blocking for at most the specified duration between polls. This should
+    /// run in a spawned thread since calls to `poll.poll` are blocking.
+    ///
+    /// The loop wakes up as soon as a packet arrives, a packet is sent through a [PacketSender], or
+    /// the next protocol timer (handshake, heartbeat, resend or idle timeout) expires. If the duration
+    /// is None, the loop only wakes up for those.
+    pub fn start_polling_with_duration(&mut self, max_duration: Option<Duration>) {
         // Nothing should break out of this loop!
         loop {
             self.manual_poll(Instant::now());
-            match sleep_duration {
-                None => yield_now(),
-                Some(duration) => sleep(duration),
-            };
+            self.wait_for_readiness(max_duration);
+        }
+    }
+
+    /// Blocks until there is something to do for `manual_poll`, or until `max_duration` passes.
+    pub(crate) fn wait_for_readiness(&mut self, max_duration: Option<Duration>) {
+        // A blocking socket blocks while receiving already.
+        if self.config.blocking_mode {
+            return;
+        }
+
+        let time = Instant::now();
+        let next_timer = [
+            self.connections.next_timer(time),
+            self.outbound_delay_queue.next_due_time(),
+            self.inbound_delay_queue.next_due_time(),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied();
+        let timeout = match (next_timer, max_duration) {
+            (Some(timer), Some(max_duration)) => {
+                Some(timer.saturating_duration_since(time).min(max_duration))
+            }
+            (Some(timer), None) => Some(timer.saturating_duration_since(time)),
+            (None, max_duration) => max_duration,
+        };
+
+        if let Err(e) = self.poller.wait(timeout) {
+            error!("Encountered an error polling for readiness: {:?}", e);
         }
     }
 
@@ -146,42 +269,169 @@ impl Socket {
             }
         }
 
+        // Handle the packets the inbound link conditioner held back until now
+        self.handle_delayed_datagrams(time);
+
+        // Send the packets the link conditioner held back until now
+        if let Err(e) = self.send_delayed_packets(time) {
+            match e {
+                ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                _ => error!("There was an error sending a delayed packet: {:?}", e),
+            }
+        }
+
+        // Send the packets the congestion controllers held back until now
+        if let Err(e) = self.send_paced_packets(time) {
+            match e {
+                ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                _ => error!("There was an error sending a paced packet: {:?}", e),
+            }
+        }
+
         // Now grab all the packets waiting to be sent and send them
-        while let Ok(p) = self.packet_receiver.try_recv() {
-            if let Err(e) = self.send_to(p, time) {
-                match e {
-                    ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                    _ => error!("There was an error sending packet: {:?}", e),
-                }
+        self.queued_reliable_bytes.clear();
+        while let Ok((id, p)) = self.packet_receiver.try_recv() {
+            self.send_queued_packet(id, p, time);
+        }
+
+        // Stream the chunks of large messages and acknowledge the chunks which were received
+        if let Err(e) = self.send_large_message_packets(time) {
+            match e {
+                ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                _ => error!("There was an error sending a large message packet: {:?}", e),
+            }
+        }
+
+        // Continue the handshake with connections which are not yet established
+        if let Err(e) = self.send_handshake_packets(time) {
+            match e {
+                ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                _ => error!("There was an error sending a handshake packet: {:?}", e),
             }
         }
 
+        // Resend reliable packets which have not been acknowledged in time
+        if let Err(e) = self.resend_expired_packets(time) {
+            match e {
+                ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                _ => error!("There was an error resending a packet: {:?}", e),
+            }
+        }
+
+        // Discard partially received packets whose fragments did not all arrive in time
+        for connection in self.connections.iter_mut() {
+            connection.discard_expired_fragments(time);
+        }
+
         // Check for idle clients
         if let Err(e) = self.handle_idle_clients(time) {
             error!("Encountered an error when sending TimeoutEvent: {:?}", e);
         }
 
-        // Finally send heartbeat packets to connections that require them, if enabled
-        if let Some(heartbeat_interval) = self.config.heartbeat_interval {
-            if let Err(e) = self.send_heartbeat_packets(heartbeat_interval, time) {
-                match e {
-                    ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                    _ => error!("There was an error sending a heartbeat packet: {:?}", e),
-                }
+        // Finally send heartbeat packets to connections that require them, to acknowledge the
+        // packets they received or to keep them alive if enabled
+        if let Err(e) = self.send_heartbeat_packets(time) {
+            match e {
+                ErrorKind::IOError(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                _ => error!("There was an error sending a heartbeat packet: {:?}", e),
             }
         }
     }
 
-    /// Set the link conditioner for this socket. See [LinkConditioner] for further details.
+    /// Connect to the server with the given address, presenting the given connect token.
+    ///
+    /// The handshake starts on the next poll. Packets sent to the server before the handshake has
+    /// completed are queued until it has. Returns an error if the token does not give permission to
+    /// connect to the server.
+    pub fn connect_with_token(
+        &mut self,
+        addr: SocketAddr,
+        token: &ConnectToken,
+        time: Instant,
+    ) -> Result<()> {
+        if !token.server_addresses().contains(&addr) {
+            return Err(ConnectTokenErrorKind::UnknownServerAddress.into());
+        }
+
+        let connection = self
+            .connections
+            .get_or_insert_connection(addr, &self.config, time);
+        if !connection.is_connected() {
+            connection.set_connect_token(token);
+        }
+
+        Ok(())
+    }
+
+    /// Disconnect from the remote endpoint with the given address.
+    ///
+    /// The remote endpoint is notified with `disconnect_redundancy` disconnect packets (see
+    /// [Config]). Packets that were not yet acknowledged by the remote endpoint are discarded.
+    pub fn disconnect(&mut self, addr: SocketAddr, time: Instant) -> Result<()> {
+        if self.connections.connection_state(&addr).is_none() {
+            return Ok(());
+        }
+
+        // The disconnect packets are sent before the connection is removed, so they can be
+        // encrypted. Several are sent, since the remote endpoint only notices a lost one once the
+        // connection times out.
+        let packet = handshake_packet(PacketType::Disconnect, &[]);
+        let result = (0..self.config.disconnect_redundancy.max(1))
+            .try_for_each(|_| self.send_packet(&addr, &packet, time).map(|_| ()));
+
+        self.connections.remove_connection(&addr);
+        result
+    }
+
+    /// Set the link conditioner for the packets this socket sends. See [LinkConditioner] for further
+    /// details.
     pub fn set_link_conditioner(&mut self, link_conditioner: Option<LinkConditioner>) {
         self.link_conditioner = link_conditioner;
     }
 
+    /// Set the link conditioner for the packets this socket receives. See [LinkConditioner] for
+    /// further details.
+    pub fn set_inbound_link_conditioner(&mut self, link_conditioner: Option<LinkConditioner>) {
+        self.inbound_link_conditioner = link_conditioner;
+    }
+
+    /// Returns the counters of the partially received packets from the given address which were
+    /// discarded before all of their fragments arrived, if there is a connection with it.
+    pub fn reassembly_stats(&self, addr: SocketAddr) -> Option<ReassemblyStats> {
+        self.connections.reassembly_stats(&addr)
+    }
+
+    /// Returns a snapshot of the round trip time, loss and traffic of the connection with the given
+    /// address, if there is one.
+    pub fn connection_stats(&self, addr: SocketAddr) -> Option<ConnectionStats> {
+        self.connections.connection_stats(&addr)
+    }
+
+    /// Returns a snapshot of the round trip time, loss and traffic of every connection, including
+    /// the connections which are still performing their handshake.
+    pub fn connections(&self) -> Vec<(SocketAddr, ConnectionStats)> {
+        self.connections.stats()
+    }
+
     /// Get the local socket address
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.socket.local_addr()?)
     }
 
+    /// Returns the config of this socket.
+    #[cfg(feature = "async")]
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Switches the underlying UDP socket to non-blocking mode.
+    #[cfg(feature = "async")]
+    pub(crate) fn set_nonblocking(&mut self) -> Result<()> {
+        self.socket.set_nonblocking(true)?;
+        self.config.blocking_mode = false;
+        Ok(())
+    }
+
     /// Iterate through all of the idle connections based on `idle_connection_timeout` config and
     /// remove them from the active connections. For each connection removed, we will send a
     /// `SocketEvent::TimeOut` event to the `event_sender` channel.
@@ -191,19 +441,40 @@ impl Socket {
             .idle_connections(self.config.idle_connection_timeout, time);
         for address in idle_addresses {
             self.connections.remove_connection(&address);
-            self.event_sender.send(SocketEvent::Timeout(address))?;
+            send_event(&self.event_sender, SocketEvent::Timeout(address))?;
         }
 
         Ok(())
     }
 
-    /// Iterate over all connections which have not sent a packet for a duration of at least
-    /// `heartbeat_interval` (from config), and send a heartbeat packet to each.
-    fn send_heartbeat_packets(
-        &mut self,
-        heartbeat_interval: Duration,
-        time: Instant,
-    ) -> Result<usize> {
+    /// Iterate over all connections which are still being established and (re)send the next
+    /// handshake packet to each, at most once every `handshake_interval` (from config).
+    fn send_handshake_packets(&mut self, time: Instant) -> Result<usize> {
+        let handshake_interval = self.config.handshake_interval;
+        let handshake_packets_and_addrs = self
+            .connections
+            .iter_mut()
+            .filter_map(|connection| {
+                connection
+                    .create_handshake_packet(handshake_interval, time)
+                    .map(|packet| (packet, connection.remote_address))
+            })
+            .collect::<Vec<_>>();
+
+        let mut bytes_sent = 0;
+
+        for (handshake_packet, address) in handshake_packets_and_addrs {
+            bytes_sent += self.send_packet(&address, &handshake_packet, time)?;
+        }
+
+        Ok(bytes_sent)
+    }
+
+    /// Iterate over all connections which received packets they did not acknowledge yet, or which
+    /// have not sent a packet for a duration of at least `heartbeat_interval` (from config), and
+    /// send a heartbeat packet to each.
+    fn send_heartbeat_packets(&mut self, time: Instant) -> Result<usize> {
+        let heartbeat_interval = self.config.heartbeat_interval;
         let heartbeat_packets_and_addrs = self
             .connections
             .heartbeat_required_connections(heartbeat_interval, time)
@@ -218,58 +489,140 @@ impl Socket {
         let mut bytes_sent = 0;
 
         for (heartbeat_packet, address) in heartbeat_packets_and_addrs {
-            if self.should_send_packet() {
-                bytes_sent += self.send_packet(&address, &heartbeat_packet.contents())?;
+            bytes_sent += self.send_packet(&address, &heartbeat_packet.contents(), time)?;
+        }
+
+        Ok(bytes_sent)
+    }
+
+    /// Iterate over all connections and resend the reliable packets which have not been
+    /// acknowledged within the retransmission timeout of that connection.
+    fn resend_expired_packets(&mut self, time: Instant) -> Result<usize> {
+        let mut resends = Vec::new();
+
+        for connection in self.connections.iter_mut() {
+            let expired = connection.gather_expired_packets(time, &self.event_sender)?;
+            for outgoing in connection.process_resends(&expired, time)? {
+                match outgoing {
+                    Outgoing::Packet(packet) => resends.push((
+                        connection.remote_address,
+                        packet.contents(),
+                        packet.sequence(),
+                    )),
+                    Outgoing::Fragments(packets) => {
+                        resends.extend(packets.into_iter().map(|packet| {
+                            (
+                                connection.remote_address,
+                                packet.contents(),
+                                packet.sequence(),
+                            )
+                        }))
+                    }
+                }
             }
         }
 
+        let mut bytes_sent = 0;
+
+        for (address, payload, sequence) in resends {
+            bytes_sent += self.send_paced_packet(&address, payload, sequence, time)?;
+        }
+
+        Ok(bytes_sent)
+    }
+
+    /// Iterate over all connections and send the acknowledgments of received chunks, and the chunks
+    /// of large messages which are due to be sent.
+    fn send_large_message_packets(&mut self, time: Instant) -> Result<usize> {
+        let packets = self
+            .connections
+            .iter_mut()
+            .filter(|connection| connection.is_connected())
+            .flat_map(|connection| {
+                let address = connection.remote_address;
+                connection
+                    .gather_large_message_packets(time)
+                    .into_iter()
+                    .map(move |packet| (address, packet))
+            })
+            .collect::<Vec<_>>();
+
+        let mut bytes_sent = 0;
+
+        for (address, packet) in packets {
+            bytes_sent += self.send_paced_packet(&address, packet, None, time)?;
+        }
+
         Ok(bytes_sent)
     }
 
     // Serializes and sends a `Packet` on the socket. On success, returns the number of bytes written.
-    fn send_to(&mut self, packet: Packet, time: Instant) -> Result<usize> {
+    //
+    // If there is no established connection with the receiver yet, the packet is queued until the
+    // connection handshake has completed.
+    fn send_to(&mut self, id: PacketId, packet: Packet, time: Instant) -> Result<usize> {
+        if packet.delivery_guarantee() == DeliveryGuarantee::Reliable
+            && !packet.is_large_message()
+            && self.exceeds_in_flight_limit(&packet.addr(), packet.payload().len())
+        {
+            return Err(BackpressureErrorKind::InFlightLimitReached.into());
+        }
+
         let connection =
             self.connections
                 .get_or_insert_connection(packet.addr(), &self.config, time);
+        if !connection.is_connected() {
+            connection.queue_packet_until_connected(id, packet);
+            return Ok(0);
+        }
 
-        let dropped = connection.gather_dropped_packets();
-        let mut processed_packets: Vec<Outgoing> = dropped
-            .iter()
-            .flat_map(|waiting_packet| {
-                connection.process_outgoing(
-                    &waiting_packet.payload,
-                    // Because a delivery guarantee is only sent with reliable packets
-                    DeliveryGuarantee::Reliable,
-                    // This is stored with the dropped packet because they could be mixed
-                    waiting_packet.ordering_guarantee,
-                    waiting_packet.item_identifier,
-                    time,
-                )
-            })
-            .collect();
+        // Large messages are streamed by `send_large_message_packets`.
+        if packet.is_large_message() {
+            connection.queue_large_message(id, packet.payload())?;
+            return Ok(0);
+        }
+
+        if connection.is_pacer_full() {
+            return Err(BackpressureErrorKind::PacingQueueFull.into());
+        }
+
+        let dropped = connection.gather_dropped_packets(time, &self.event_sender)?;
+        let mut processed_packets = connection.process_resends(&dropped, time)?;
 
         let processed_packet = connection.process_outgoing(
+            id,
             packet.payload(),
             packet.delivery_guarantee(),
             packet.order_guarantee(),
             None,
             time,
         )?;
+        if let Some(time_to_live) = packet.time_to_live() {
+            connection.expire_last_packet_at(time + time_to_live);
+        }
 
         processed_packets.push(processed_packet);
 
         let mut bytes_sent = 0;
 
         for processed_packet in processed_packets {
-            if self.should_send_packet() {
-                match processed_packet {
-                    Outgoing::Packet(outgoing) => {
-                        bytes_sent += self.send_packet(&packet.addr(), &outgoing.contents())?;
-                    }
-                    Outgoing::Fragments(packets) => {
-                        for outgoing in packets {
-                            bytes_sent += self.send_packet(&packet.addr(), &outgoing.contents())?;
-                        }
+            match processed_packet {
+                Outgoing::Packet(outgoing) => {
+                    bytes_sent += self.send_paced_packet(
+                        &packet.addr(),
+                        outgoing.contents(),
+                        outgoing.sequence(),
+                        time,
+                    )?;
+                }
+                Outgoing::Fragments(packets) => {
+                    for outgoing in packets {
+                        bytes_sent += self.send_paced_packet(
+                            &packet.addr(),
+                            outgoing.contents(),
+                            outgoing.sequence(),
+                            time,
+                        )?;
                     }
                 }
             }
@@ -277,6 +630,21 @@ impl Socket {
         Ok(bytes_sent)
     }
 
+    // Returns true if sending `bytes` more reliable bytes to the given address would exceed
+    // `max_in_flight_bytes`, counting the bytes waiting in the packet queue as in flight. A packet is
+    // always allowed when nothing is in flight, so packets larger than the limit can still be sent
+    // one at a time.
+    fn exceeds_in_flight_limit(&self, addr: &SocketAddr, bytes: usize) -> bool {
+        match self.config.max_in_flight_bytes {
+            Some(max_in_flight_bytes) => {
+                let in_flight_bytes = self.connections.in_flight_bytes(addr)
+                    + self.queued_reliable_bytes.get(addr).copied().unwrap_or(0);
+                in_flight_bytes > 0 && in_flight_bytes + bytes > max_in_flight_bytes
+            }
+            None => false,
+        }
+    }
+
     // On success the packet will be sent on the `event_sender`
     fn recv_from(&mut self, time: Instant) -> Result<UdpSocketState> {
         match self.socket.recv_from(&mut self.recv_buffer) {
@@ -284,24 +652,21 @@ impl Socket {
                 if recv_len == 0 {
                     return Err(ErrorKind::ReceivedDataToShort)?;
                 }
-                let received_payload = &self.recv_buffer[..recv_len];
-
-                if !self.connections.exists(&address) {
-                    self.event_sender.send(SocketEvent::Connect(address))?;
-                }
-
-                let connection =
-                    self.connections
-                        .get_or_create_connection(address, &self.config, time);
-
-                match connection {
-                    Left(existing) => {
-                        existing.process_incoming(received_payload, &self.event_sender, time)?;
-                    }
-                    Right(mut anonymous) => {
-                        anonymous.process_incoming(received_payload, &self.event_sender, time)?;
+                // Take the buffer so the handlers can borrow `self` mutably.
+                let recv_buffer = std::mem::take(&mut self.recv_buffer);
+                let result = match &mut self.inbound_link_conditioner {
+                    Some(link_conditioner) => {
+                        for (due_time, datagram) in
+                            link_conditioner.condition(&recv_buffer[..recv_len], time)
+                        {
+                            self.inbound_delay_queue.push(address, datagram, due_time);
+                        }
+                        Ok(())
                     }
-                }
+                    None => self.handle_datagram(address, &recv_buffer[..recv_len], time),
+                };
+                self.recv_buffer = recv_buffer;
+                result?;
             }
             Err(e) => {
                 if e.kind() != io::ErrorKind::WouldBlock {
@@ -320,128 +685,1579 @@ impl Socket {
         }
     }
 
-    // Send a single packet over the UDP socket.
-    fn send_packet(&self, addr: &SocketAddr, payload: &[u8]) -> Result<usize> {
-        let bytes_sent = self.socket.send_to(payload, addr)?;
-        Ok(bytes_sent)
-    }
+    // Handles a single datagram received from the given address. Encrypted datagrams are decrypted
+    // with the keys of the connection before they are handled.
+    fn handle_datagram(
+        &mut self,
+        address: SocketAddr,
+        datagram: &[u8],
+        time: Instant,
+    ) -> Result<()> {
+        let header = PacketReader::new(datagram).read_standard_header()?;
 
-    // In the presence of a link conditioner, we would like it to determine whether or not we should
-    // send a packet.
-    fn should_send_packet(&mut self) -> bool {
-        if let Some(link_conditioner) = &mut self.link_conditioner {
-            link_conditioner.should_send()
+        if !header.is_current_protocol() {
+            return Err(ErrorKind::ProtocolVersionMismatch);
+        }
+
+        if header.packet_type() == PacketType::Encrypted {
+            let packet = match self.connections.get_mut(&address) {
+                Some(connection) => connection.decrypt(datagram)?,
+                None => {
+                    debug!(
+                        "Ignoring encrypted packet from unknown address {}.",
+                        address
+                    );
+                    return Ok(());
+                }
+            };
+
+            self.handle_packet(address, &packet, true, time)
         } else {
-            true
+            self.handle_packet(address, datagram, false, time)
         }
     }
 
-    #[cfg(test)]
-    fn connection_count(&self) -> usize {
-        self.connections.count()
-    }
+    // Handles a single packet received from the given address. Handshake packets are handled
+    // here, other packets are only processed if they come from an established connection.
+    fn handle_packet(
+        &mut self,
+        address: SocketAddr,
+        payload: &[u8],
+        decrypted: bool,
+        time: Instant,
+    ) -> Result<()> {
+        let mut packet_reader = PacketReader::new(payload);
+        let header = packet_reader.read_standard_header()?;
+
+        if let Some(connection) = self.connections.get_mut(&address) {
+            // Once a connection has keys, only the packets that are sent before the other side
+            // knows them are accepted unencrypted.
+            let encryption_required = !matches!(
+                header.packet_type(),
+                PacketType::ConnectionRequest
+                    | PacketType::ConnectionChallenge
+                    | PacketType::ChallengeResponse
+                    | PacketType::ConnectionDenied
+            );
+
+            if encryption_required && connection.is_encrypted() && !decrypted {
+                debug!("Ignoring unencrypted packet from {}.", address);
+                return Ok(());
+            }
 
-    #[cfg(test)]
-    fn forget_all_incoming_packets(&mut self) {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        loop {
-            match self.socket.recv_from(&mut self.recv_buffer) {
-                Ok((recv_len, _address)) => {
-                    if recv_len == 0 {
-                        panic!("Received data too short");
+            connection.last_heard = time;
+            connection.record_received(payload.len());
+        }
+
+        match header.packet_type() {
+            PacketType::ConnectionRequest => {
+                // Never answer with more data than an unverified address sent us.
+                match read_connection_request(&packet_reader.read_payload()) {
+                    Some((nonce, connect_token)) => {
+                        self.handle_connection_request(address, nonce, connect_token, time)
                     }
-                    &self.recv_buffer[..recv_len];
+                    None => Ok(()),
                 }
-                Err(e) => {
-                    if e.kind() != io::ErrorKind::WouldBlock {
-                        panic!("Encountered an error receiving data: {:?}", e);
-                    } else {
-                        return;
+            }
+            PacketType::ConnectionChallenge => {
+                if let Some(cookie) = read_cookie(&packet_reader.read_payload()) {
+                    self.handle_connection_challenge(address, cookie, time)?;
+                }
+                Ok(())
+            }
+            PacketType::ChallengeResponse => {
+                match read_challenge_response(&packet_reader.read_payload()) {
+                    Some((cookie, window, connect_token)) => self.handle_challenge_response(
+                        address,
+                        &cookie,
+                        window,
+                        connect_token,
+                        time,
+                    ),
+                    None => Ok(()),
+                }
+            }
+            PacketType::ConnectionAccepted => {
+                let window = read_connection_accepted(&packet_reader.read_payload());
+                match (self.connections.get_mut(&address), window) {
+                    (Some(connection), Some(window)) if !connection.is_connected() => {
+                        connection.set_ack_window(window);
+                        self.establish_connection(address, false, time)
                     }
+                    _ => Ok(()),
+                }
+            }
+            PacketType::ConnectionDenied => {
+                let is_connecting = self.connections.connection_state(&address)
+                    == Some(ConnectionState::Connecting);
+                if is_connecting {
+                    self.connections.remove_connection(&address);
+                    send_event(
+                        &self.event_sender,
+                        SocketEvent::Disconnect(address, DisconnectReason::Denied),
+                    )?;
+                }
+                Ok(())
+            }
+            PacketType::Disconnect => {
+                if self.connections.remove_connection(&address).is_some() {
+                    send_event(
+                        &self.event_sender,
+                        SocketEvent::Disconnect(address, DisconnectReason::Closed),
+                    )?;
+                }
+                Ok(())
+            }
+            PacketType::Packet
+            | PacketType::Fragment
+            | PacketType::Heartbeat
+            | PacketType::Chunk
+            | PacketType::ChunkAck => match self.connections.get_mut(&address) {
+                Some(connection) if connection.is_connected() => {
+                    connection.process_incoming(payload, &self.event_sender, time)
                 }
+                _ => {
+                    debug!("Ignoring packet from unconnected address {}.", address);
+                    Ok(())
+                }
+            },
+            PacketType::Encrypted => {
+                debug!("Ignoring doubly encrypted packet from {}.", address);
+                Ok(())
             }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        net::constants::{ACKED_PACKET_HEADER, FRAGMENT_HEADER_SIZE, STANDARD_HEADER_SIZE},
-        Config, LinkConditioner, Packet, Socket, SocketEvent,
-    };
-    use std::collections::HashSet;
-    use std::net::{SocketAddr, UdpSocket};
-    use std::time::{Duration, Instant};
+    // A remote endpoint wants to connect, challenge it to prove it owns its address. No state is
+    // kept for the remote endpoint until it answers the challenge.
+    fn handle_connection_request(
+        &mut self,
+        address: SocketAddr,
+        nonce: u64,
+        connect_token: &[u8],
+        time: Instant,
+    ) -> Result<()> {
+        let packet = match self.connections.get_mut(&address) {
+            Some(connection) if connection.is_connected() => {
+                // The remote endpoint did not receive our acceptance yet.
+                connection_accepted_packet(connection.ack_window())
+            }
+            Some(connection) if connection.request_nonce() > nonce => {
+                // We are requesting a connection with each other, the remote endpoint will
+                // challenge us instead.
+                return Ok(());
+            }
+            _ => {
+                if self.config.connect_token_key.is_some()
+                    && self.open_connect_token(connect_token, time).is_none()
+                {
+                    debug!(
+                        "Ignoring connection request without valid token from {}.",
+                        address
+                    );
+                    return Ok(());
+                }
 
-    #[test]
-    fn binding_to_any() {
-        assert![Socket::bind_any().is_ok()];
-        assert![Socket::bind_any_with_config(Config::default()).is_ok()];
+                let cookie = self.challenges.issue(address, time);
+                handshake_packet(PacketType::ConnectionChallenge, &cookie)
+            }
+        };
+
+        self.send_packet(&address, &packet, time)?;
+
+        Ok(())
     }
 
-    #[test]
-    fn blocking_sender_and_receiver() {
-        let cfg = Config::default();
+    // The remote endpoint challenged our connection request, answer it straight away.
+    fn handle_connection_challenge(
+        &mut self,
+        address: SocketAddr,
+        cookie: ChallengeCookie,
+        time: Instant,
+    ) -> Result<()> {
+        let connection = match self.connections.get_mut(&address) {
+            // Later challenges are answers to requests we resent, the first one is kept.
+            Some(connection)
+                if !connection.is_connected() && !connection.has_challenge_cookie() =>
+            {
+                connection
+            }
+            _ => return Ok(()),
+        };
 
-        let mut client = Socket::bind_any_with_config(cfg.clone()).unwrap();
-        let mut server = Socket::bind_any_with_config(Config {
-            blocking_mode: true,
-            ..cfg
-        })
-        .unwrap();
+        connection.set_challenge_cookie(cookie);
+        // The keys of a connect token take precedence over the pre-shared key.
+        match (
+            connection.connect_token_keys(&cookie),
+            self.config.encryption_key,
+        ) {
+            (Some(keys), _) => connection.set_keys(&keys),
+            (None, Some(encryption_key)) => {
+                connection.set_keys(&derive_connection_keys(&encryption_key, &cookie, false))
+            }
+            (None, None) => {}
+        }
 
-        let server_addr = server.local_addr().unwrap();
-        let client_addr = client.local_addr().unwrap();
+        let response = connection.create_handshake_packet(Duration::from_secs(0), time);
+        if let Some(response) = response {
+            self.send_packet(&address, &response, time)?;
+        }
 
-        let time = Instant::now();
+        Ok(())
+    }
 
-        client
-            .send(Packet::unreliable(
-                server_addr,
-                b"Hello world!".iter().cloned().collect::<Vec<_>>(),
-            ))
-            .unwrap();
+    // A remote endpoint answered our challenge, accept it if the answer is correct.
+    fn handle_challenge_response(
+        &mut self,
+        address: SocketAddr,
+        cookie: &ChallengeCookie,
+        window: AckWindow,
+        connect_token: &[u8],
+        time: Instant,
+    ) -> Result<()> {
+        let accepted_window = match self.connections.get_mut(&address) {
+            Some(connection) if connection.is_connected() => Some(connection.ack_window()),
+            _ => None,
+        };
 
-        client.manual_poll(time);
-        server.manual_poll(time);
+        if let Some(accepted_window) = accepted_window {
+            // The remote endpoint did not receive our acceptance yet.
+            self.send_packet(&address, &connection_accepted_packet(accepted_window), time)?;
+            return Ok(());
+        }
 
-        assert_eq![SocketEvent::Connect(client_addr), server.recv().unwrap()];
-        if let SocketEvent::Packet(packet) = server.recv().unwrap() {
-            assert_eq![b"Hello world!", packet.payload()];
-        } else {
-            panic!["Did not receive a packet when it should"];
+        if !self
+            .challenges
+            .redeem(address, cookie, self.config.idle_connection_timeout, time)
+        {
+            debug!("Ignoring invalid challenge response from {}.", address);
+            return Ok(());
         }
-    }
 
-    #[test]
-    fn using_sender_and_receiver() {
-        let server_addr = "127.0.0.1:12310".parse::<SocketAddr>().unwrap();
-        let client_addr = "127.0.0.1:12311".parse::<SocketAddr>().unwrap();
+        let connect_token = match self.config.connect_token_key {
+            Some(_) => match self.open_connect_token(connect_token, time) {
+                Some(connect_token) => Some(connect_token),
+                None => {
+                    debug!(
+                        "Ignoring challenge response without valid token from {}.",
+                        address
+                    );
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
 
-        let mut server = Socket::bind(server_addr).unwrap();
-        let mut client = Socket::bind(client_addr).unwrap();
+        // A client may only be connected once, no matter from which address.
+        let is_duplicate_client = match connect_token {
+            Some(ref connect_token) => self
+                .connections
+                .has_client(connect_token.client_data().client_id()),
+            None => false,
+        };
+        let is_full = match self.config.max_connections {
+            Some(max_connections) => self.connections.connected_count() >= max_connections,
+            None => false,
+        };
 
-        let time = Instant::now();
+        if is_duplicate_client || is_full {
+            self.send_packet(
+                &address,
+                &handshake_packet(PacketType::ConnectionDenied, &[]),
+                time,
+            )?;
+            return Ok(());
+        }
+
+        let connection = self
+            .connections
+            .get_or_insert_connection(address, &self.config, time);
+        // Both endpoints have to be able to keep track of the window, so the smaller one is used.
+        connection.set_ack_window(window.min(AckWindow::from_packets(self.config.ack_window_size)));
+        match (connect_token, self.config.encryption_key) {
+            (Some(connect_token), _) => {
+                connection.set_keys(&connect_token.server_keys(cookie));
+                connection.set_client_data(connect_token.client_data().clone());
+            }
+            (None, Some(encryption_key)) => {
+                connection.set_keys(&derive_connection_keys(&encryption_key, cookie, true));
+            }
+            (None, None) => {}
+        }
+
+        self.establish_connection(address, true, time)
+    }
+
+    // Completes the handshake of the connection with the given address, after which the packets
+    // that were waiting for it are sent. The acceptance is sent first if `accept` is true.
+    fn establish_connection(
+        &mut self,
+        address: SocketAddr,
+        accept: bool,
+        time: Instant,
+    ) -> Result<()> {
+        let (queued_packets, client_data, window) = match self.connections.get_mut(&address) {
+            Some(connection) => (
+                connection.establish(),
+                connection.client_data().cloned(),
+                connection.ack_window(),
+            ),
+            None => return Ok(()),
+        };
+
+        if accept {
+            self.send_packet(&address, &connection_accepted_packet(window), time)?;
+        }
+
+        send_event(
+            &self.event_sender,
+            SocketEvent::Connect(address, client_data),
+        )?;
+
+        for (id, packet) in queued_packets {
+            self.send_queued_packet(id, packet, time);
+        }
+
+        Ok(())
+    }
+
+    // Sends a packet the application handed to the socket earlier. A packet which the connection
+    // has no room for is rejected, and reported as lost so the application learns about it.
+    fn send_queued_packet(&mut self, id: PacketId, packet: Packet, time: Instant) {
+        let addr = packet.addr();
+        match self.send_to(id, packet, time) {
+            Ok(_) => {}
+            Err(ErrorKind::IOError(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(ErrorKind::BackpressureError(e)) => {
+                warn!("Rejected packet to {}: {}", addr, e);
+                if let Err(e) = send_event(&self.event_sender, SocketEvent::Lost(addr, id)) {
+                    error!("There was an error reporting a rejected packet: {:?}", e);
+                }
+            }
+            Err(e) => error!("There was an error sending packet: {:?}", e),
+        }
+    }
+
+    // Reads the private part of a connect token, if it was generated for this server and has not
+    // expired yet.
+    fn open_connect_token(
+        &self,
+        connect_token: &[u8],
+        time: Instant,
+    ) -> Option<PrivateConnectToken> {
+        let private_key = self.config.connect_token_key?;
+        let local_addr = self.socket.local_addr().ok()?;
+
+        let (created, created_system_time) = self.created;
+        let now = match time.checked_duration_since(created) {
+            Some(elapsed) => created_system_time + elapsed,
+            None => created_system_time - created.duration_since(time),
+        };
+        PrivateConnectToken::open(&private_key, connect_token, now)
+            .filter(|connect_token| connect_token.allows_server(local_addr))
+    }
+
+    // Sends a packet of an established connection once its congestion controller allows it. Packets
+    // which would exceed the send rate are held back and sent by later polls, the packet is only
+    // considered sent from then on.
+    fn send_paced_packet(
+        &mut self,
+        addr: &SocketAddr,
+        packet: Box<[u8]>,
+        sequence: Option<SequenceNumber>,
+        time: Instant,
+    ) -> Result<usize> {
+        let packets = match self.connections.get_mut(addr) {
+            Some(connection) => connection.pace_packet(packet, sequence, time),
+            None => vec![packet],
+        };
+
+        let mut bytes_sent = 0;
+        for packet in packets {
+            bytes_sent += self.send_packet(addr, &packet, time)?;
+        }
+        Ok(bytes_sent)
+    }
+
+    /// Iterate over all connections and send the packets their congestion controller held back,
+    /// as far as their send rate allows by now.
+    fn send_paced_packets(&mut self, time: Instant) -> Result<usize> {
+        let packets = self
+            .connections
+            .iter_mut()
+            .flat_map(|connection| {
+                let address = connection.remote_address;
+                connection
+                    .take_paced_packets(time)
+                    .into_iter()
+                    .map(move |packet| (address, packet))
+            })
+            .collect::<Vec<_>>();
+
+        let mut bytes_sent = 0;
+
+        for (address, packet) in packets {
+            bytes_sent += self.send_packet(&address, &packet, time)?;
+        }
+
+        Ok(bytes_sent)
+    }
+
+    // Send a single packet over the UDP socket, encrypted if the connection has keys.
+    //
+    // In the presence of a link conditioner, the packet is subjected to its conditions and held back
+    // until it leaves the simulated link.
+    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8], time: Instant) -> Result<usize> {
+        let payload = match self.connections.get_mut(addr) {
+            Some(connection) => {
+                connection.record_sent(payload.len());
+                connection.encrypt(payload)?
+            }
+            None => Cow::Borrowed(payload),
+        };
+
+        match &mut self.link_conditioner {
+            Some(link_conditioner) => {
+                for (due_time, datagram) in link_conditioner.condition(&payload, time) {
+                    self.outbound_delay_queue.push(*addr, datagram, due_time);
+                }
+                self.send_delayed_packets(time)?;
+                Ok(payload.len())
+            }
+            None => Ok(self.socket.send_to(&payload, *addr)?),
+        }
+    }
+
+    // Sends the packets held back by the outbound link conditioner which are due.
+    fn send_delayed_packets(&mut self, time: Instant) -> Result<usize> {
+        let mut bytes_sent = 0;
+
+        while let Some((address, payload)) = self.outbound_delay_queue.pop_due(time) {
+            bytes_sent += self.socket.send_to(&payload, address)?;
+        }
+
+        Ok(bytes_sent)
+    }
+
+    // Handles the packets held back by the inbound link conditioner which are due.
+    fn handle_delayed_datagrams(&mut self, time: Instant) {
+        while let Some((address, datagram)) = self.inbound_delay_queue.pop_due(time) {
+            if let Err(e) = self.handle_datagram(address, &datagram, time) {
+                error!("Encountered an error receiving data: {:?}", e);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn connection_count(&self) -> usize {
+        self.connections.count()
+    }
+
+    #[cfg(test)]
+    fn forget_all_incoming_packets(&mut self) {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        loop {
+            match self.socket.recv_from(&mut self.recv_buffer) {
+                Ok((recv_len, _address)) => {
+                    if recv_len == 0 {
+                        panic!("Received data too short");
+                    }
+                    &self.recv_buffer[..recv_len];
+                }
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        panic!("Encountered an error receiving data: {:?}", e);
+                    } else {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        net::{
+            constants::{
+                ACKED_PACKET_HEADER, ARRANGING_PACKET_HEADER, FRAGMENT_HEADER_SIZE,
+                STANDARD_HEADER_SIZE,
+            },
+            handshake::{connection_request_packet, handshake_packet},
+            virtual_connection::ConnectionState,
+        },
+        packet::{
+            header::AckWindow, DeliveryGuarantee, OrderingGuarantee, OutgoingPacketBuilder,
+            PacketId, PacketType,
+        },
+        BackpressureErrorKind, BurstLoss, ChannelNetwork, Config, ConfigErrorKind,
+        CongestionControl, CongestionController, ConnectToken, DatagramTransport, DisconnectReason,
+        ErrorKind, Jitter, LinkConditioner, NetworkQuality, Packet, Socket, SocketEvent,
+    };
+    use crossbeam_channel::TrySendError;
+    use std::collections::HashSet;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn binding_to_any() {
+        assert![Socket::bind_any().is_ok()];
+        assert![Socket::bind_any_with_config(Config::default()).is_ok()];
+    }
+
+    #[test]
+    fn binding_with_a_send_rate_of_zero_fails() {
+        let config = Config {
+            max_send_rate: Some(0),
+            ..Config::default()
+        };
+        match Socket::bind_any_with_config(config) {
+            Err(ErrorKind::ConfigError(ConfigErrorKind::ZeroSendRate)) => {}
+            _ => panic!("Expected the config to be rejected"),
+        }
+
+        let config = Config {
+            max_send_rate: Some(1000),
+            send_burst_size: 0,
+            ..Config::default()
+        };
+        match Socket::bind_any_with_config(config) {
+            Err(ErrorKind::ConfigError(ConfigErrorKind::ZeroSendBurstSize)) => {}
+            _ => panic!("Expected the config to be rejected"),
+        }
+    }
+
+    #[test]
+    fn blocking_sender_and_receiver() {
+        let cfg = Config::default();
+
+        let mut client = Socket::bind_any_with_config(cfg.clone()).unwrap();
+        let mut server = Socket::bind_any_with_config(Config {
+            blocking_mode: true,
+            ..cfg
+        })
+        .unwrap();
+
+        let server_addr = server.local_addr().unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        let time = Instant::now();
+
+        client
+            .send(Packet::unreliable(
+                server_addr,
+                b"Hello world!".iter().cloned().collect::<Vec<_>>(),
+            ))
+            .unwrap();
+
+        poll_handshake(&mut client, &mut server, time);
+
+        assert_eq![
+            SocketEvent::Connect(client_addr, None),
+            server.recv().unwrap()
+        ];
+        if let SocketEvent::Packet(packet) = server.recv().unwrap() {
+            assert_eq![b"Hello world!", packet.payload()];
+        } else {
+            panic!["Did not receive a packet when it should"];
+        }
+    }
+
+    #[test]
+    fn using_sender_and_receiver() {
+        let server_addr = "127.0.0.1:12310".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12311".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind(server_addr).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let time = Instant::now();
+
+        let sender = client.get_packet_sender();
+        let receiver = server.get_event_receiver();
+
+        sender
+            .send(Packet::reliable_unordered(
+                server_addr,
+                b"Hello world!".iter().cloned().collect::<Vec<_>>(),
+            ))
+            .unwrap();
+
+        poll_handshake(&mut client, &mut server, time);
+
+        assert_eq![Ok(SocketEvent::Connect(client_addr, None)), receiver.recv()];
+        if let SocketEvent::Packet(packet) = receiver.recv().unwrap() {
+            assert_eq![b"Hello world!", packet.payload()];
+        } else {
+            panic!["Did not receive a packet when it should"];
+        }
+    }
+
+    #[test]
+    fn polling_loop_wakes_up_for_sent_packets() {
+        let server_addr = "127.0.0.1:12400".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12401".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind(server_addr).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let sender = client.get_packet_sender();
+        let receiver = server.get_event_receiver();
+
+        // Without a polling timeout, the loops only wake up for readiness and protocol timers.
+        thread::spawn(move || server.start_polling_with_duration(None));
+        thread::spawn(move || client.start_polling_with_duration(None));
+
+        sender
+            .send(Packet::reliable_unordered(server_addr, vec![1, 2, 3]))
+            .unwrap();
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Ok(SocketEvent::Connect(client_addr, None))
+        );
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Ok(SocketEvent::Packet(Packet::reliable_unordered(
+                client_addr,
+                vec![1, 2, 3]
+            )))
+        );
+    }
+
+    #[test]
+    fn sockets_communicate_over_channel_transport() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![1, 2, 3]))
+            .unwrap();
+
+        let now = Instant::now();
+        poll_handshake(&mut client, &mut server, now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_addr, None)
+        );
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::reliable_unordered(client_addr, vec![1, 2, 3]))
+        );
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Connect(server_addr, None)
+        );
+    }
+
+    #[test]
+    fn link_conditioner_latency_delays_packets() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_latency(Duration::from_millis(150));
+        client.set_link_conditioner(Some(link_conditioner));
+
+        client
+            .send(Packet::unreliable(server_addr, vec![1, 2, 3]))
+            .unwrap();
+
+        client.manual_poll(time);
+        server.manual_poll(time);
+        assert_eq!(server.recv(), None);
+
+        client.manual_poll(time + Duration::from_millis(149));
+        server.manual_poll(time + Duration::from_millis(149));
+        assert_eq!(server.recv(), None);
+
+        client.manual_poll(time + Duration::from_millis(150));
+        server.manual_poll(time + Duration::from_millis(150));
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::unreliable(
+                client_addr,
+                vec![1, 2, 3]
+            )))
+        );
+    }
+
+    #[test]
+    fn inbound_link_conditioner_delays_received_packets() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_latency(Duration::from_millis(50));
+        server.set_inbound_link_conditioner(Some(link_conditioner));
+
+        client
+            .send(Packet::unreliable(server_addr, vec![1, 2, 3]))
+            .unwrap();
+        client.manual_poll(time);
+
+        server.manual_poll(time);
+        assert_eq!(server.recv(), None);
+
+        server.manual_poll(time + Duration::from_millis(50));
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::unreliable(
+                client_addr,
+                vec![1, 2, 3]
+            )))
+        );
+    }
+
+    #[test]
+    fn ordered_packets_survive_adverse_link() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        // Corrupted packets are rejected because they cannot be decrypted.
+        let config = Config {
+            encryption_key: Some([7; 32]),
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(0.1);
+        link_conditioner.set_latency(Duration::from_millis(20));
+        link_conditioner.set_jitter(Some(Jitter::Uniform(Duration::from_millis(10))));
+        link_conditioner.set_reordering(0.2, Duration::from_millis(30));
+        link_conditioner.set_duplication(0.2);
+        link_conditioner.set_corruption(0.1);
+        client.set_link_conditioner(Some(link_conditioner.clone()));
+        link_conditioner.set_seed(1);
+        server.set_link_conditioner(Some(link_conditioner));
+
+        for number in 0..50 {
+            client
+                .send(Packet::reliable_ordered(server_addr, vec![number], None))
+                .unwrap();
+        }
+
+        let start = Instant::now();
+        for tick in 0..1000 {
+            let time = start + Duration::from_millis(5 * tick);
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+
+        let mut received = Vec::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                received.push(packet.payload()[0]);
+            }
+        }
+        assert_eq!(received, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn smaller_ack_window_is_negotiated() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::with_transport(
+            network.bind(server_addr).unwrap(),
+            Config {
+                ack_window_size: 64,
+                ..Config::default()
+            },
+        )
+        .unwrap();
+        let mut client = Socket::with_transport(
+            network.bind(client_addr).unwrap(),
+            Config {
+                ack_window_size: 128,
+                ..Config::default()
+            },
+        )
+        .unwrap();
+
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(0.2);
+        client.set_link_conditioner(Some(link_conditioner.clone()));
+        server.set_link_conditioner(Some(link_conditioner));
+
+        for number in 0..100 {
+            client
+                .send(Packet::reliable_ordered(server_addr, vec![number], None))
+                .unwrap();
+        }
+
+        let start = Instant::now();
+        for tick in 0..1000 {
+            let time = start + Duration::from_millis(5 * tick);
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+
+        for (socket, address) in &mut [(&mut server, client_addr), (&mut client, server_addr)] {
+            let connection = socket.connections.get_mut(address).unwrap();
+            assert_eq!(connection.ack_window(), AckWindow::Bits64);
+        }
+
+        let mut received = Vec::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                received.push(packet.payload()[0]);
+            }
+        }
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn acknowledged_and_lost_packets_are_reported() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            acknowledgment_events: true,
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        let reliable = client
+            .send(Packet::reliable_unordered(server_addr, vec![1]))
+            .unwrap();
+        let unreliable = client
+            .send(Packet::unreliable(server_addr, vec![2]))
+            .unwrap();
+        assert_ne!(reliable, unreliable);
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        // The acknowledgments travel back with the next packet of the server.
+        server
+            .send(Packet::unreliable(client_addr, vec![3]))
+            .unwrap();
+        server.manual_poll(time);
+        client.manual_poll(time);
+
+        let events: Vec<_> = std::iter::from_fn(|| client.recv()).collect();
+        assert!(events.contains(&SocketEvent::Acked(server_addr, reliable)));
+        assert!(events.contains(&SocketEvent::Acked(server_addr, unreliable)));
+
+        // Unreliable packets are only considered lost once the acknowledgments skipped them, not
+        // when they are merely not acknowledged in time.
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(1.0);
+        server.set_inbound_link_conditioner(Some(link_conditioner));
+
+        let lost = client
+            .send(Packet::unreliable(server_addr, vec![4]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+        client.manual_poll(time + Duration::from_secs(1));
+        assert_eq!(client.recv(), None);
+
+        server.set_inbound_link_conditioner(None);
+        let time = time + Duration::from_secs(1);
+        for number in 0..33 {
+            client
+                .send(Packet::unreliable(server_addr, vec![number]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        server.manual_poll(time);
+        client.manual_poll(time);
+        while let Some(SocketEvent::Acked(..)) = client.recv() {}
+
+        // The packet fell out of the acknowledgment window, which is noticed with the next send.
+        client
+            .send(Packet::unreliable(server_addr, vec![5]))
+            .unwrap();
+        client.manual_poll(time);
+        assert_eq!(client.recv(), Some(SocketEvent::Lost(server_addr, lost)));
+        assert_eq!(client.recv(), None);
+    }
+
+    #[test]
+    fn connection_stats_report_traffic_and_losses() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+        let connected = client.connection_stats(server_addr).unwrap();
+        let server_connected = server.connection_stats(client_addr).unwrap();
+        // The packet which opened the connection was acknowledged during the handshake.
+        assert_eq!(connected.smoothed_rtt, Some(Duration::default()));
+        assert_eq!(connected.packets_in_flight, 0);
+        assert_eq!(client.connection_stats(client_addr), None);
+
+        for id in 0..3 {
+            client
+                .send(Packet::reliable_unordered(server_addr, vec![id; 10]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        assert_eq!(
+            client
+                .connection_stats(server_addr)
+                .unwrap()
+                .packets_in_flight,
+            3
+        );
+
+        // The server acknowledges the packets with a heartbeat.
+        let ack_time = time + Duration::from_millis(40);
+        server.manual_poll(ack_time);
+        client.manual_poll(ack_time);
+
+        let stats = client.connection_stats(server_addr).unwrap();
+        // 10% of the 40ms round trip time is blended into the estimation.
+        assert_eq!(stats.smoothed_rtt, Some(Duration::from_millis(4)));
+        assert_eq!(stats.min_rtt, Some(Duration::default()));
+        assert_eq!(stats.packets_sent, connected.packets_sent + 3);
+        assert_eq!(stats.packets_received, connected.packets_received + 1);
+        assert_eq!(stats.packets_acked, connected.packets_acked + 3);
+        assert_eq!(stats.bytes_acked, 30);
+        assert_eq!(stats.packets_in_flight, 0);
+        assert_eq!(stats.packet_loss, 0.);
+        assert_eq!(stats.last_heard, ack_time);
+        assert_eq!(stats.last_sent, time);
+        // The server does not count the handshake packets it received before it accepted the client.
+        assert_eq!(
+            server.connection_stats(client_addr).unwrap().bytes_received
+                - server_connected.bytes_received,
+            stats.bytes_sent - connected.bytes_sent
+        );
+
+        // A packet which is not acknowledged in time is considered lost and resent.
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(1.0);
+        client.set_link_conditioner(Some(link_conditioner));
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![3; 10]))
+            .unwrap();
+        client.manual_poll(ack_time);
+        client.manual_poll(ack_time + Duration::from_secs(1));
+
+        let stats = client.connection_stats(server_addr).unwrap();
+        assert_eq!(stats.packets_lost, connected.packets_lost + 1);
+        assert_eq!(stats.packets_resent, 1);
+        assert_eq!(stats.bytes_resent, 10);
+        assert!(stats.packet_loss > 0.);
+        assert_eq!(
+            client.connections(),
+            vec![(server_addr, client.connection_stats(server_addr).unwrap())]
+        );
+    }
+
+    #[test]
+    fn reliable_packets_over_the_in_flight_limit_are_rejected() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            max_in_flight_bytes: Some(10),
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![1; 6]))
+            .unwrap();
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![2; 4]))
+            .unwrap();
+        // Packets waiting in the queue count as in flight.
+        match client.send(Packet::reliable_unordered(server_addr, vec![3])) {
+            Err(ErrorKind::BackpressureError(BackpressureErrorKind::InFlightLimitReached)) => {}
+            result => panic!(
+                "Expected the in-flight limit to be reached, got {:?}",
+                result
+            ),
+        }
+        // Unreliable packets are not limited.
+        client
+            .send(Packet::unreliable(server_addr, vec![4; 20]))
+            .unwrap();
+
+        client.manual_poll(time);
+        server.manual_poll(time);
+        assert!(client
+            .send(Packet::reliable_unordered(server_addr, vec![3]))
+            .is_err());
+
+        // Once the packets are acknowledged there is room again.
+        server
+            .send(Packet::unreliable(client_addr, vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        client.manual_poll(time);
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![3; 10]))
+            .unwrap();
+    }
+
+    #[test]
+    fn packets_rejected_by_the_polling_loop_are_reported_lost() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            max_in_flight_bytes: Some(10),
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
+        // The sender cannot know what is in flight, so the polling loop checks the limit.
+        let sender = client.get_packet_sender();
+        sender
+            .send(Packet::reliable_unordered(server_addr, vec![1; 10]))
+            .unwrap();
+        let rejected = sender
+            .send(Packet::reliable_unordered(server_addr, vec![2; 10]))
+            .unwrap();
+
+        client.manual_poll(time);
+        assert_eq!(
+            client.recv(),
+            Some(SocketEvent::Lost(server_addr, rejected))
+        );
+        assert_eq!(client.recv(), None);
+    }
+
+    #[test]
+    fn sending_fails_when_the_packet_queue_is_full() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            packet_queue_size: Some(2),
+            ..Config::default()
+        };
+
+        let mut client = Socket::with_transport(
+            network.bind("10.0.0.2:1000".parse().unwrap()).unwrap(),
+            config,
+        )
+        .unwrap();
         let sender = client.get_packet_sender();
-        let receiver = server.get_event_receiver();
 
+        client
+            .send(Packet::unreliable(server_addr, vec![1]))
+            .unwrap();
         sender
-            .send(Packet::reliable_unordered(
+            .send(Packet::unreliable(server_addr, vec![2]))
+            .unwrap();
+        match client.send(Packet::unreliable(server_addr, vec![3])) {
+            Err(ErrorKind::BackpressureError(BackpressureErrorKind::PacketQueueFull)) => {}
+            result => panic!("Expected the packet queue to be full, got {:?}", result),
+        }
+        assert_eq!(
+            sender.send(Packet::unreliable(server_addr, vec![4])),
+            Err(TrySendError::Full(Packet::unreliable(server_addr, vec![4])))
+        );
+
+        // Polling empties the queue.
+        client.manual_poll(Instant::now());
+        client
+            .send(Packet::unreliable(server_addr, vec![3]))
+            .unwrap();
+    }
+
+    #[test]
+    fn socket_send_does_not_wait_for_room_in_the_packet_queue() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            packet_queue_size: Some(1),
+            block_on_full_packet_queue: true,
+            ..Config::default()
+        };
+
+        let mut client = Socket::with_transport(
+            network.bind("10.0.0.2:1000".parse().unwrap()).unwrap(),
+            config,
+        )
+        .unwrap();
+
+        client
+            .send(Packet::unreliable(server_addr, vec![1]))
+            .unwrap();
+        // Only polling this socket makes room, so waiting would never return.
+        match client.send(Packet::unreliable(server_addr, vec![2])) {
+            Err(ErrorKind::BackpressureError(BackpressureErrorKind::PacketQueueFull)) => {}
+            result => panic!("Expected the packet queue to be full, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn events_are_discarded_when_the_event_queue_is_full() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::with_transport(
+            network.bind(server_addr).unwrap(),
+            Config {
+                event_queue_size: Some(2),
+                ..Config::default()
+            },
+        )
+        .unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        for number in 0..3 {
+            client
+                .send(Packet::unreliable(server_addr, vec![number]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::unreliable(
+                client_addr,
+                vec![0]
+            )))
+        );
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::unreliable(
+                client_addr,
+                vec![1]
+            )))
+        );
+        assert_eq!(server.recv(), None);
+    }
+
+    #[test]
+    fn congestion_controller_paces_outgoing_packets() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            congestion_control: CongestionControl::Binary {
+                good_send_rate: 100,
+                bad_send_rate: 10,
+            },
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let start = Instant::now();
+        connect(&mut client, &mut server, start);
+
+        let time = start + Duration::from_secs(1);
+        for number in 0..20 {
+            client
+                .send(Packet::reliable_unordered(server_addr, vec![number]))
+                .unwrap();
+        }
+
+        let mut received = 0;
+        for tick in 0..20 {
+            client.manual_poll(time + Duration::from_millis(10 * tick));
+            server.manual_poll(time + Duration::from_millis(10 * tick));
+            while let Some(event) = server.recv() {
+                if let SocketEvent::Packet(_) = event {
+                    received += 1;
+                }
+            }
+            // The good send rate allows a packet every 10 milliseconds.
+            assert_eq!(received, tick + 1);
+        }
+    }
+
+    #[test]
+    fn send_rate_limit_spreads_bursts_over_polls() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            max_send_rate: Some(100_000),
+            send_burst_size: 10_000,
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let start = Instant::now();
+        connect(&mut client, &mut server, start);
+
+        let time = start + Duration::from_secs(1);
+        for _ in 0..50 {
+            client
+                .send(Packet::unreliable(server_addr, vec![0; 1000]))
+                .unwrap();
+        }
+
+        let mut received_bytes = Vec::new();
+        for tick in 0..5 {
+            client.manual_poll(time + Duration::from_millis(100 * tick));
+            server.manual_poll(time + Duration::from_millis(100 * tick));
+            let mut bytes = 0;
+            while let Some(event) = server.recv() {
+                if let SocketEvent::Packet(packet) = event {
+                    bytes += packet.payload().len();
+                }
+            }
+            received_bytes.push(bytes);
+        }
+
+        // A burst first, after which the rate allows 10 000 bytes every 100 milliseconds. The
+        // headers count towards the rate too, so slightly fewer payload bytes arrive.
+        assert!(received_bytes.iter().all(|bytes| *bytes <= 10_000));
+        assert!(received_bytes.iter().all(|bytes| *bytes >= 8_000));
+
+        // The poll wakes up once the next held back packet may be sent.
+        let connection = client.connections.get_mut(&server_addr).unwrap();
+        let next_timer = connection.next_timer(time);
+        assert!(next_timer > time + Duration::from_millis(400));
+        assert!(next_timer <= time + Duration::from_millis(500));
+    }
+
+    #[test]
+    fn held_back_packets_are_only_tracked_once_they_are_sent() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            max_send_rate: Some(1000),
+            send_burst_size: 1000,
+            max_paced_packets: 2,
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let start = Instant::now();
+        connect(&mut client, &mut server, start);
+
+        // The burst allows the first packet, the others are held back for about a second each.
+        let time = start + Duration::from_secs(2);
+        for _ in 0..3 {
+            client
+                .send(Packet::reliable_unordered(server_addr, vec![0; 900]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        match client.send(Packet::reliable_unordered(server_addr, vec![0; 900])) {
+            Err(ErrorKind::BackpressureError(BackpressureErrorKind::PacingQueueFull)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Every packet is acknowledged right after it left the pacer, so no round trip time includes
+        // the time it was held back and none is resent.
+        for tick in 0..3 {
+            let time = time + Duration::from_secs(tick);
+            client.manual_poll(time);
+            server.manual_poll(time);
+            client.manual_poll(time);
+        }
+
+        let stats = client.connection_stats(server_addr).unwrap();
+        assert_eq!(stats.smoothed_rtt, Some(Duration::from_secs(0)));
+        assert_eq!(stats.packets_resent, 0);
+        assert_eq!(stats.packets_in_flight, 0);
+    }
+
+    // Halves the send rate whenever a packet is lost, like the decrease of an AIMD controller.
+    struct HalvingController {
+        send_rate: f64,
+    }
+
+    impl CongestionController for HalvingController {
+        fn on_ack(&mut self, _rtt: Duration, _time: Instant) {}
+
+        fn on_loss(&mut self, _time: Instant) {
+            self.send_rate /= 2.;
+        }
+
+        fn send_rate(&self) -> Option<f64> {
+            Some(self.send_rate)
+        }
+
+        fn quality(&self) -> NetworkQuality {
+            if self.send_rate < 100. {
+                NetworkQuality::Bad
+            } else {
+                NetworkQuality::Good
+            }
+        }
+    }
+
+    #[test]
+    fn custom_congestion_controller_reports_mode_changes() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            congestion_control: CongestionControl::Custom(Arc::new(|_: &Config| {
+                Box::new(HalvingController { send_rate: 100. }) as Box<dyn CongestionController>
+            })),
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        // Drop everything the server receives from now on.
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(1.0);
+        server.set_inbound_link_conditioner(Some(link_conditioner));
+
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![1]))
+            .unwrap();
+        client.manual_poll(time);
+        // The controller allows 100 packets per second, so the packet is held back until 10
+        // milliseconds after the packet the client connected with.
+        client.manual_poll(time + Duration::from_millis(10));
+        assert_eq!(client.recv(), None);
+
+        client.manual_poll(time + Duration::from_millis(310));
+        assert_eq!(
+            client.recv(),
+            Some(SocketEvent::CongestionChanged(
                 server_addr,
-                b"Hello world!".iter().cloned().collect::<Vec<_>>(),
+                NetworkQuality::Bad
             ))
+        );
+    }
+
+    #[test]
+    fn quality_changes_are_reported_with_hysteresis() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let mut time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        // A single slow packet is smoothed out.
+        assert_eq!(
+            round_trip(
+                &mut client,
+                &mut server,
+                Duration::from_millis(20),
+                &mut time
+            ),
+            vec![]
+        );
+        assert_eq!(
+            round_trip(
+                &mut client,
+                &mut server,
+                Duration::from_millis(400),
+                &mut time
+            ),
+            vec![]
+        );
+
+        let mut events = Vec::new();
+        while events.is_empty() {
+            events = round_trip(
+                &mut client,
+                &mut server,
+                Duration::from_millis(400),
+                &mut time,
+            );
+        }
+        assert_eq!(
+            events,
+            vec![SocketEvent::QualityChanged(
+                server_addr,
+                NetworkQuality::Bad
+            )]
+        );
+
+        // The quality only recovers once the smoothed round trip time dropped well below the 250ms
+        // threshold.
+        let mut events = Vec::new();
+        while events.is_empty() {
+            let stats = client.connection_stats(server_addr).unwrap();
+            assert!(stats.smoothed_rtt.unwrap() >= Duration::from_millis(200));
+            events = round_trip(
+                &mut client,
+                &mut server,
+                Duration::from_millis(20),
+                &mut time,
+            );
+        }
+        assert_eq!(
+            events,
+            vec![SocketEvent::QualityChanged(
+                server_addr,
+                NetworkQuality::Good
+            )]
+        );
+        let stats = client.connection_stats(server_addr).unwrap();
+        assert!(stats.smoothed_rtt.unwrap() < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn reliable_packet_expires_after_time_to_live() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        // Drop everything the server receives from now on.
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(1.0);
+        server.set_inbound_link_conditioner(Some(link_conditioner));
+
+        let id = client
+            .send(
+                Packet::reliable_unordered(server_addr, vec![1])
+                    .with_ttl(Duration::from_millis(500)),
+            )
             .unwrap();
+        client.manual_poll(time);
+
+        // The packet is still resent before its deadline.
+        client.manual_poll(time + Duration::from_millis(300));
+        assert_eq!(client.recv(), None);
+
+        client.manual_poll(time + Duration::from_millis(600));
+        assert_eq!(client.recv(), Some(SocketEvent::Expired(server_addr, id)));
+
+        client.manual_poll(time + Duration::from_secs(2));
+        assert_eq!(client.recv(), None);
+        let connection = client.connections.get_mut(&server_addr).unwrap();
+        assert_eq!(
+            connection.next_timer(time),
+            time + Config::default().idle_connection_timeout
+        );
+    }
+
+    #[test]
+    fn ordered_packet_is_resent_past_its_time_to_live() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(1.0);
+        server.set_inbound_link_conditioner(Some(link_conditioner));
+
+        client
+            .send(
+                Packet::reliable_ordered(server_addr, vec![1], None)
+                    .with_ttl(Duration::from_millis(500)),
+            )
+            .unwrap();
         client.manual_poll(time);
         server.manual_poll(time);
 
-        assert_eq![Ok(SocketEvent::Connect(client_addr)), receiver.recv()];
-        if let SocketEvent::Packet(packet) = receiver.recv().unwrap() {
-            assert_eq![b"Hello world!", packet.payload()];
-        } else {
-            panic!["Did not receive a packet when it should"];
+        // The packet does not expire, so it still arrives once the link recovers.
+        client.manual_poll(time + Duration::from_millis(600));
+        assert_eq!(client.recv(), None);
+
+        server.set_inbound_link_conditioner(None);
+        client.manual_poll(time + Duration::from_secs(2));
+        server.manual_poll(time + Duration::from_secs(2));
+        match server.recv() {
+            Some(SocketEvent::Packet(packet)) => assert_eq!(packet.payload(), [1]),
+            event => panic!("Expected the ordered packet, got {:?}", event),
         }
     }
 
@@ -451,6 +2267,7 @@ mod tests {
         let mut client = Socket::bind("127.0.0.1:12336".parse::<SocketAddr>().unwrap()).unwrap();
 
         let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
         // Send a packet that the server ignores/drops
         client
@@ -488,6 +2305,79 @@ mod tests {
         panic!["Did not receive the ignored packet"];
     }
 
+    #[test]
+    fn unacked_packet_is_resent_after_timeout() {
+        let server_addr = "127.0.0.1:12373".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12374".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind(server_addr).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        client
+            .send(Packet::reliable_unordered(
+                server_addr,
+                b"Do not arrive".to_vec(),
+            ))
+            .unwrap();
+        client.manual_poll(time);
+
+        // Drop the inbound packet, this simulates a network error
+        server.forget_all_incoming_packets();
+
+        // No new packets are sent, the resend is driven by the retransmission timeout alone
+        client.manual_poll(time + Duration::from_millis(1));
+        server.manual_poll(time);
+        assert_eq!(server.recv(), None);
+
+        client.manual_poll(time + Duration::from_secs(1));
+        server.manual_poll(time);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::reliable_unordered(
+                client_addr,
+                b"Do not arrive".to_vec(),
+            ))
+        );
+    }
+
+    #[test]
+    fn packet_is_not_resent_more_than_max_resends() {
+        let server_addr = "127.0.0.1:12375".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12376".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind(server_addr).unwrap();
+        let mut client = Socket::bind_with_config(
+            client_addr,
+            Config {
+                max_packet_resends: Some(1),
+                ..Config::default()
+            },
+        )
+        .unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        client
+            .send(Packet::reliable_unordered(server_addr, vec![1, 2, 3]))
+            .unwrap();
+        client.manual_poll(time);
+        server.forget_all_incoming_packets();
+
+        // The first timeout resends the packet
+        client.manual_poll(time + Duration::from_secs(1));
+        server.forget_all_incoming_packets();
+
+        // The second timeout gives up on it
+        client.manual_poll(time + Duration::from_secs(2));
+        server.manual_poll(time);
+        assert_eq!(server.recv(), None);
+    }
+
     #[test]
     fn receiving_does_not_allow_denial_of_service() {
         let mut server = Socket::bind("127.0.0.1:12337".parse::<SocketAddr>().unwrap()).unwrap();
@@ -508,26 +2398,77 @@ mod tests {
         client.manual_poll(time);
         server.manual_poll(time);
 
-        for _ in 0..6 {
-            assert![server.recv().is_some()];
-        }
+        // The server only challenged the connection request, it shall not have any connection in
+        // its connection table yet
         assert![server.recv().is_none()];
+        assert_eq![0, server.connection_count()];
+
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        // The server only adds to its table after the client answered the challenge
+        assert_eq![1, server.connection_count()];
+    }
+
+    #[test]
+    fn packets_from_unconnected_address_are_ignored() {
+        let server_addr = "127.0.0.1:12377".parse::<SocketAddr>().unwrap();
+        let mut server = Socket::bind(server_addr).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:12378").unwrap();
+
+        let packet = OutgoingPacketBuilder::new(&[1, 2, 3])
+            .with_default_header(
+                PacketType::Packet,
+                DeliveryGuarantee::Unreliable,
+                OrderingGuarantee::None,
+            )
+            .build();
+
+        client.send_to(&packet.contents(), server_addr).unwrap();
+        server.manual_poll(Instant::now());
+
+        assert_eq![None, server.recv()];
+        assert_eq![0, server.connection_count()];
+    }
+
+    #[test]
+    fn connection_request_is_answered_with_smaller_challenge() {
+        let server_addr = "127.0.0.1:12386".parse::<SocketAddr>().unwrap();
+        let mut server = Socket::bind(server_addr).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:12387").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
 
-        // The server shall not have any connection in its connection table even though it received
-        // packets
+        let request = connection_request_packet(0, &[]);
+        client.send_to(&request, server_addr).unwrap();
+        server.manual_poll(Instant::now());
+
+        let mut buffer = [0; 1500];
+        let (len, _) = client.recv_from(&mut buffer).unwrap();
+        assert!(len <= request.len());
         assert_eq![0, server.connection_count()];
+    }
 
-        server
-            .send(Packet::unreliable(
-                "127.0.0.1:12338".parse::<SocketAddr>().unwrap(),
-                vec![1],
-            ))
+    #[test]
+    fn unpadded_connection_request_is_ignored() {
+        let server_addr = "127.0.0.1:12388".parse::<SocketAddr>().unwrap();
+        let mut server = Socket::bind(server_addr).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:12389").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
             .unwrap();
 
-        server.manual_poll(time);
+        client
+            .send_to(
+                &handshake_packet(PacketType::ConnectionRequest, &[]),
+                server_addr,
+            )
+            .unwrap();
+        server.manual_poll(Instant::now());
 
-        // The server only adds to its table after having sent explicitly
-        assert_eq![1, server.connection_count()];
+        let mut buffer = [0; 1500];
+        assert!(client.recv_from(&mut buffer).is_err());
     }
 
     #[test]
@@ -536,6 +2477,7 @@ mod tests {
         let mut client = Socket::bind("127.0.0.1:12330".parse::<SocketAddr>().unwrap()).unwrap();
 
         let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
         // Send a packet that the server ignores/drops
         client
@@ -578,6 +2520,7 @@ mod tests {
         let mut client = Socket::bind("127.0.0.1:12334".parse::<SocketAddr>().unwrap()).unwrap();
 
         let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
         // Send a packet that the server ignores/drops
         client
@@ -625,6 +2568,7 @@ mod tests {
         let mut client = Socket::bind(client_addr).unwrap();
 
         let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
         for id in 0..100 {
             client
@@ -639,13 +2583,19 @@ mod tests {
 
         while let Some(message) = server.recv() {
             match message {
-                SocketEvent::Connect(_) => {}
+                SocketEvent::Connect(_, _)
+                | SocketEvent::Acked(..)
+                | SocketEvent::Lost(..)
+                | SocketEvent::Expired(..)
+                | SocketEvent::Progress(..)
+                | SocketEvent::CongestionChanged(..)
+                | SocketEvent::QualityChanged(..) => {}
                 SocketEvent::Packet(packet) => {
                     let byte = packet.payload()[0];
                     assert![!seen.contains(&byte)];
                     seen.insert(byte);
                 }
-                SocketEvent::Timeout(_) => {
+                SocketEvent::Timeout(_) | SocketEvent::Disconnect(..) => {
                     panic!["This should not happen, as we've not advanced time"];
                 }
             }
@@ -670,8 +2620,7 @@ mod tests {
 
         let time = Instant::now();
 
-        client.manual_poll(time);
-        server.manual_poll(time);
+        poll_handshake(&mut client, &mut server, time);
 
         assert!(server.recv().is_some());
         assert!(server.recv().is_some());
@@ -693,8 +2642,7 @@ mod tests {
         }
 
         let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
+        poll_handshake(&mut client, &mut server, now);
 
         assert!(server.recv().is_some());
         assert!(server.recv().is_some());
@@ -702,13 +2650,16 @@ mod tests {
     }
 
     #[test]
-    fn sending_large_unreliable_packet_should_fail() {
+    fn sending_packet_larger_than_max_packet_size_should_fail() {
         let mut server = Socket::bind("127.0.0.1:12370".parse::<SocketAddr>().unwrap()).unwrap();
+        let mut client = Socket::bind("127.0.0.1:12360".parse::<SocketAddr>().unwrap()).unwrap();
+        connect(&mut client, &mut server, Instant::now());
 
         assert_eq!(
             server
                 .send_to(
-                    Packet::unreliable("127.0.0.1:12360".parse().unwrap(), vec![1; 5000]),
+                    PacketId(0),
+                    Packet::unreliable("127.0.0.1:12360".parse().unwrap(), vec![1; 30000]),
                     Instant::now(),
                 )
                 .is_err(),
@@ -716,36 +2667,166 @@ mod tests {
         );
     }
 
+    #[test]
+    fn large_message_is_streamed_over_lossy_link() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+        let config = Config {
+            acknowledgment_events: true,
+            ..Config::default()
+        };
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), config.clone()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), config).unwrap();
+
+        let start = Instant::now();
+        connect(&mut client, &mut server, start);
+
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_packet_loss(0.1);
+        link_conditioner.set_latency(Duration::from_millis(10));
+        link_conditioner.set_reordering(0.2, Duration::from_millis(20));
+        client.set_link_conditioner(Some(link_conditioner.clone()));
+        link_conditioner.set_seed(1);
+        server.set_link_conditioner(Some(link_conditioner));
+
+        // Far more than the 255 fragments a fragmented packet is limited to.
+        let message: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+        let id = client
+            .send(Packet::large_message(server_addr, message.clone()))
+            .unwrap();
+
+        for tick in 0..2000 {
+            let time = start + Duration::from_millis(5 * tick);
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+
+        let mut progress = Vec::new();
+        let mut received = None;
+        while let Some(event) = server.recv() {
+            match event {
+                SocketEvent::Progress(addr, message_progress) => {
+                    assert_eq!(addr, client_addr);
+                    assert_eq!(message_progress.total_bytes, message.len());
+                    progress.push(message_progress.received_bytes);
+                }
+                SocketEvent::Packet(packet) => received = Some(packet),
+                _ => {}
+            }
+        }
+
+        assert_eq!(received.unwrap().payload(), message.as_slice());
+        assert!(progress.len() > 90);
+        assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let mut acked = false;
+        while let Some(event) = client.recv() {
+            acked |= event == SocketEvent::Acked(server_addr, id);
+        }
+        assert!(acked);
+    }
+
+    #[test]
+    fn large_packets_are_fragmented_with_every_guarantee() {
+        let network = ChannelNetwork::new();
+        let server_addr = "10.0.0.1:1000".parse::<SocketAddr>().unwrap();
+        let client_addr = "10.0.0.2:1000".parse::<SocketAddr>().unwrap();
+
+        let mut server =
+            Socket::with_transport(network.bind(server_addr).unwrap(), Config::default()).unwrap();
+        let mut client =
+            Socket::with_transport(network.bind(client_addr).unwrap(), Config::default()).unwrap();
+
+        let time = Instant::now();
+        connect(&mut client, &mut server, time);
+
+        let packets = vec![
+            Packet::unreliable(server_addr, vec![1; 4000]),
+            Packet::unreliable_sequenced(server_addr, vec![2; 4000], Some(1)),
+            Packet::reliable_unordered(server_addr, vec![3; 4000]),
+            Packet::reliable_sequenced(server_addr, vec![4; 4000], Some(2)),
+            Packet::reliable_ordered(server_addr, vec![5; 4000], Some(3)),
+            Packet::reliable_ordered(server_addr, vec![6; 100], Some(3)),
+        ];
+        for packet in packets.iter().cloned() {
+            client.send(packet).unwrap();
+        }
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        let received: Vec<_> = std::iter::from_fn(|| server.recv()).collect();
+        let expected: Vec<_> = packets
+            .into_iter()
+            .map(|packet| {
+                SocketEvent::Packet(Packet::new(
+                    client_addr,
+                    Box::from(packet.payload()),
+                    packet.delivery_guarantee(),
+                    packet.order_guarantee(),
+                ))
+            })
+            .collect();
+        assert_eq!(received, expected);
+    }
+
     #[test]
     fn send_returns_right_size() {
         let mut server = Socket::bind("127.0.0.1:12371".parse::<SocketAddr>().unwrap()).unwrap();
+        let mut client = Socket::bind("127.0.0.1:12361".parse::<SocketAddr>().unwrap()).unwrap();
+        connect(&mut client, &mut server, Instant::now());
 
         assert_eq!(
             server
                 .send_to(
+                    PacketId(0),
                     Packet::unreliable("127.0.0.1:12361".parse().unwrap(), vec![1; 1024]),
                     Instant::now(),
                 )
                 .unwrap(),
-            1024 + STANDARD_HEADER_SIZE as usize
+            1024 + (STANDARD_HEADER_SIZE + ACKED_PACKET_HEADER) as usize
         );
     }
 
     #[test]
     fn fragmentation_send_returns_right_size() {
         let mut server = Socket::bind("127.0.0.1:12372".parse::<SocketAddr>().unwrap()).unwrap();
+        let mut client = Socket::bind("127.0.0.1:12362".parse::<SocketAddr>().unwrap()).unwrap();
+        connect(&mut client, &mut server, Instant::now());
 
         let fragment_packet_size = STANDARD_HEADER_SIZE + FRAGMENT_HEADER_SIZE;
 
-        // the first fragment of an sequence of fragments contains also the acknowledgment header.
+        // the first fragment of an sequence of fragments contains also the acknowledgment header,
+        // and the arranging header which identifies the reliable packet.
         assert_eq!(
             server
                 .send_to(
+                    PacketId(0),
                     Packet::reliable_unordered("127.0.0.1:12362".parse().unwrap(), vec![1; 4000]),
                     Instant::now(),
                 )
                 .unwrap(),
-            4000 + (fragment_packet_size * 4 + ACKED_PACKET_HEADER) as usize
+            4000 + (fragment_packet_size * 4 + ACKED_PACKET_HEADER + ARRANGING_PACKET_HEADER)
+                as usize
+        );
+    }
+
+    #[test]
+    fn packets_are_queued_until_connected() {
+        let mut server = Socket::bind("127.0.0.1:12379".parse::<SocketAddr>().unwrap()).unwrap();
+
+        assert_eq!(
+            server
+                .send_to(
+                    PacketId(0),
+                    Packet::unreliable("127.0.0.1:12380".parse().unwrap(), vec![1; 1024]),
+                    Instant::now(),
+                )
+                .unwrap(),
+            0
         );
     }
 
@@ -762,15 +2843,329 @@ mod tests {
             .unwrap();
 
         let now = Instant::now();
+        poll_handshake(&mut client, &mut server, now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect("127.0.0.1:12344".parse().unwrap(), None)
+        );
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Connect("127.0.0.1:12345".parse().unwrap(), None)
+        );
+    }
+
+    #[test]
+    fn connection_is_denied_when_server_is_full() {
+        let server_addr = "127.0.0.1:12381".parse::<SocketAddr>().unwrap();
+        let first_addr = "127.0.0.1:12382".parse::<SocketAddr>().unwrap();
+        let second_addr = "127.0.0.1:12383".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config(
+            server_addr,
+            Config {
+                max_connections: Some(1),
+                ..Config::default()
+            },
+        )
+        .unwrap();
+        let mut first = Socket::bind(first_addr).unwrap();
+        let mut second = Socket::bind(second_addr).unwrap();
+
+        let now = Instant::now();
+        connect(&mut first, &mut server, now);
+
+        second
+            .send(Packet::unreliable(server_addr, vec![1]))
+            .unwrap();
+        poll_handshake(&mut second, &mut server, now);
+
+        assert_eq!(
+            second.recv().unwrap(),
+            SocketEvent::Disconnect(server_addr, DisconnectReason::Denied)
+        );
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+    }
+
+    #[test]
+    fn disconnect_notifies_remote_endpoint() {
+        let server_addr = "127.0.0.1:12384".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12385".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind(server_addr).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let now = Instant::now();
+        connect(&mut client, &mut server, now);
+
+        client.disconnect(server_addr, now).unwrap();
+        assert_eq!(client.connection_count(), 0);
+
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Disconnect(client_addr, DisconnectReason::Closed)
+        );
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    #[test]
+    fn disconnect_survives_a_lost_disconnect_packet() {
+        let server_addr = "127.0.0.1:12410".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12411".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind(server_addr).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let now = Instant::now();
+        connect(&mut client, &mut server, now);
+
+        // The link switches between dropping and sending every packet, starting with dropping the
+        // first disconnect packet.
+        let mut link_conditioner = LinkConditioner::new();
+        link_conditioner.set_burst_loss(Some(BurstLoss {
+            good_to_bad: 1.0,
+            bad_to_good: 1.0,
+            good_loss: 0.0,
+            bad_loss: 1.0,
+        }));
+        client.set_link_conditioner(Some(link_conditioner));
+
+        client.disconnect(server_addr, now).unwrap();
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Disconnect(client_addr, DisconnectReason::Closed)
+        );
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    #[test]
+    fn encrypted_connection_ignores_unencrypted_packets() {
+        let config = Config {
+            encryption_key: Some([3; 32]),
+            ..Config::default()
+        };
+
+        let server_addr = "127.0.0.1:12390".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12391".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config(server_addr, config.clone()).unwrap();
+        let mut client = Socket::bind_with_config(client_addr, config).unwrap();
+
+        let now = Instant::now();
+        connect(&mut client, &mut server, now);
+
+        client
+            .send(Packet::unreliable(server_addr, vec![1, 2, 3]))
+            .unwrap();
         client.manual_poll(now);
         server.manual_poll(now);
 
         assert_eq!(
             server.recv().unwrap(),
-            SocketEvent::Connect("127.0.0.1:12344".parse().unwrap())
+            SocketEvent::Packet(Packet::unreliable(client_addr, vec![1, 2, 3]))
+        );
+
+        // Anyone can send an unencrypted disconnect on behalf of the client.
+        client
+            .socket
+            .send_to(&handshake_packet(PacketType::Disconnect, &[]), server_addr)
+            .unwrap();
+        server.manual_poll(now);
+
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+
+        client.disconnect(server_addr, now).unwrap();
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Disconnect(client_addr, DisconnectReason::Closed)
+        );
+    }
+
+    #[test]
+    fn endpoints_with_different_keys_do_not_connect() {
+        let server_config = Config {
+            encryption_key: Some([3; 32]),
+            ..Config::default()
+        };
+        let client_config = Config {
+            encryption_key: Some([4; 32]),
+            ..Config::default()
+        };
+
+        let server_addr = "127.0.0.1:12392".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12393".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config(server_addr, server_config).unwrap();
+        let mut client = Socket::bind_with_config(client_addr, client_config).unwrap();
+
+        client
+            .send(Packet::unreliable(server_addr, vec![1, 2, 3]))
+            .unwrap();
+
+        let now = Instant::now();
+        poll_handshake(&mut client, &mut server, now);
+
+        assert_eq!(client.recv(), None);
+        assert_eq!(
+            client.connections.connection_state(&server_addr),
+            Some(ConnectionState::Connecting)
+        );
+    }
+
+    #[test]
+    fn server_accepts_client_with_connect_token() {
+        let private_key = [5; 32];
+        let config = Config {
+            connect_token_key: Some(private_key),
+            ..Config::default()
+        };
+
+        let server_addr = "127.0.0.1:12394".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12395".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config(server_addr, config).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let token = ConnectToken::generate(
+            &private_key,
+            42,
+            &[server_addr],
+            Duration::from_secs(30),
+            b"red team",
+        )
+        .unwrap();
+
+        let now = Instant::now();
+        client.connect_with_token(server_addr, &token, now).unwrap();
+        client
+            .send(Packet::unreliable(server_addr, vec![1, 2, 3]))
+            .unwrap();
+
+        poll_handshake(&mut client, &mut server, now);
+
+        match server.recv() {
+            Some(SocketEvent::Connect(addr, Some(client_data))) => {
+                assert_eq!(addr, client_addr);
+                assert_eq!(client_data.client_id(), 42);
+                assert_eq!(client_data.user_data(), b"red team");
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(client_addr, vec![1, 2, 3]))
+        );
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Connect(server_addr, None)
         );
     }
 
+    #[test]
+    fn server_bound_to_all_interfaces_accepts_connect_token() {
+        let private_key = [5; 32];
+        let config = Config {
+            connect_token_key: Some(private_key),
+            ..Config::default()
+        };
+
+        let server_addr = "127.0.0.1:12406".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12407".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config("0.0.0.0:12406", config).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let token = ConnectToken::generate(
+            &private_key,
+            42,
+            &[server_addr],
+            Duration::from_secs(30),
+            &[],
+        )
+        .unwrap();
+        let now = Instant::now();
+        client.connect_with_token(server_addr, &token, now).unwrap();
+
+        poll_handshake(&mut client, &mut server, now);
+
+        match server.recv() {
+            Some(SocketEvent::Connect(addr, Some(client_data))) => {
+                assert_eq!(addr, client_addr);
+                assert_eq!(client_data.client_id(), 42);
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn server_ignores_client_with_expired_connect_token() {
+        let private_key = [5; 32];
+        let config = Config {
+            connect_token_key: Some(private_key),
+            ..Config::default()
+        };
+
+        let server_addr = "127.0.0.1:12408".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12409".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config(server_addr, config).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        let token = ConnectToken::generate(
+            &private_key,
+            42,
+            &[server_addr],
+            Duration::from_secs(30),
+            &[],
+        )
+        .unwrap();
+        let later = Instant::now() + Duration::from_secs(60);
+        client
+            .connect_with_token(server_addr, &token, later)
+            .unwrap();
+
+        poll_handshake(&mut client, &mut server, later);
+
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    #[test]
+    fn server_ignores_client_without_connect_token() {
+        let config = Config {
+            connect_token_key: Some([5; 32]),
+            ..Config::default()
+        };
+
+        let server_addr = "127.0.0.1:12396".parse::<SocketAddr>().unwrap();
+        let client_addr = "127.0.0.1:12397".parse::<SocketAddr>().unwrap();
+
+        let mut server = Socket::bind_with_config(server_addr, config).unwrap();
+        let mut client = Socket::bind(client_addr).unwrap();
+
+        // A token generated with another private key is as good as none.
+        let token =
+            ConnectToken::generate(&[6; 32], 42, &[server_addr], Duration::from_secs(30), &[])
+                .unwrap();
+        let now = Instant::now();
+        client.connect_with_token(server_addr, &token, now).unwrap();
+
+        poll_handshake(&mut client, &mut server, now);
+
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 0);
+        assert_eq!(client.recv(), None);
+    }
+
     #[test]
     fn disconnect_event_occurs() {
         let mut config = Config::default();
@@ -787,14 +3182,20 @@ mod tests {
             .unwrap();
 
         let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
+        poll_handshake(&mut client, &mut server, now);
 
-        assert_eq!(server.recv().unwrap(), SocketEvent::Connect(client_addr));
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_addr, None)
+        );
         assert_eq!(
             server.recv().unwrap(),
             SocketEvent::Packet(Packet::unreliable(client_addr, vec![0, 1, 2]))
         );
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Connect(server_addr, None)
+        );
 
         // Acknowledge the client
         server
@@ -844,18 +3245,23 @@ mod tests {
             .unwrap();
 
         let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
+        poll_handshake(&mut client, &mut server, now);
 
-        // Make sure the connection was successful on the server side
-        assert_eq!(server.recv().unwrap(), SocketEvent::Connect(client_addr));
+        // Make sure the connection was successful on both sides
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_addr, None)
+        );
         assert_eq!(
             server.recv().unwrap(),
             SocketEvent::Packet(Packet::unreliable(client_addr, vec![0, 1, 2]))
         );
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Connect(server_addr, None)
+        );
 
         // Acknowledge the client
-        // This way, the server also knows about the connection and sends heartbeats
         server
             .send(Packet::unreliable(client_addr, vec![]))
             .unwrap();
@@ -882,6 +3288,66 @@ mod tests {
         assert_eq!(server.recv(), None);
     }
 
+    // Sends a packet from the client, which the server acknowledges after the given round trip time,
+    // and returns the quality changes the client reported.
+    fn round_trip<T: DatagramTransport>(
+        client: &mut Socket<T>,
+        server: &mut Socket<T>,
+        rtt: Duration,
+        time: &mut Instant,
+    ) -> Vec<SocketEvent> {
+        let server_addr = server.local_addr().unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        client
+            .send(Packet::unreliable(server_addr, vec![1]))
+            .unwrap();
+        client.manual_poll(*time);
+        *time += rtt;
+        server.manual_poll(*time);
+        server
+            .send(Packet::unreliable(client_addr, vec![2]))
+            .unwrap();
+        server.manual_poll(*time);
+        client.manual_poll(*time);
+
+        while server.recv().is_some() {}
+        std::iter::from_fn(|| client.recv())
+            .filter(|event| matches!(event, SocketEvent::QualityChanged(..)))
+            .collect()
+    }
+
+    // Polls the client and the server in turns, enough times for a connection handshake initiated
+    // by the client to complete and for the packets queued during the handshake to arrive.
+    fn poll_handshake<T: DatagramTransport>(
+        client: &mut Socket<T>,
+        server: &mut Socket<T>,
+        time: Instant,
+    ) {
+        for _ in 0..3 {
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+    }
+
+    // Establishes a connection between the client and the server and discards the events that
+    // were emitted for it.
+    fn connect<T: DatagramTransport>(
+        client: &mut Socket<T>,
+        server: &mut Socket<T>,
+        time: Instant,
+    ) {
+        client
+            .send(Packet::unreliable(server.local_addr().unwrap(), vec![]))
+            .unwrap();
+        poll_handshake(client, server, time);
+        // The client processes the acknowledgment of the packet it connected with.
+        client.manual_poll(time);
+
+        while client.recv().is_some() {}
+        while server.recv().is_some() {}
+    }
+
     fn create_test_packet(id: u8, addr: &str) -> Packet {
         let payload = vec![id];
         Packet::reliable_unordered(addr.parse().unwrap(), payload)
@@ -907,6 +3373,7 @@ mod tests {
         let mut client = Socket::bind(LOCAL_ADDR.parse::<SocketAddr>().unwrap()).unwrap();
 
         let now = Instant::now();
+        connect(&mut client, &mut server, now);
 
         // Send enough packets to ensure that we must have dropped packets.
         for i in 0..35 {
@@ -926,8 +3393,7 @@ mod tests {
         }
 
         // Ensure that we get the correct number of events to the server.
-        // 35 connect events plus the 35 messages
-        assert_eq!(events.len(), 70);
+        assert_eq!(events.len(), 35);
 
         // Finally the server decides to send us a message back. This necessarily will include
         // the ack information for 33 of the sent 35 packets.
@@ -942,8 +3408,8 @@ mod tests {
             }
         }
 
-        // This next sent message should end up sending the 2 unacked messages plus the new messages
-        // with payload 35
+        // This next sent message should end up resending the 2 messages which fell out of the
+        // acknowledgment window of the server, plus the new message with payload 35
         events.clear();
         client.send(create_test_packet(35, REMOTE_ADDR)).unwrap();
         client.manual_poll(now);
@@ -955,6 +3421,9 @@ mod tests {
                 break;
             }
         }
+        while let Some(event) = server.recv() {
+            events.push(event);
+        }
 
         let sent_events: Vec<u8> = events
             .iter()
@@ -963,7 +3432,15 @@ mod tests {
                 _ => None,
             })
             .collect();
+        // The server already received the resent messages, so it drops them.
         assert_eq!(sent_events, vec![35]);
+        assert_eq!(
+            client
+                .connection_stats(REMOTE_ADDR.parse().unwrap())
+                .unwrap()
+                .packets_resent,
+            2
+        );
     }
 
     #[quickcheck_macros::quickcheck]
@@ -990,6 +3467,7 @@ mod tests {
         let mut client = Socket::bind(client_addr).unwrap();
 
         let time = Instant::now();
+        connect(&mut client, &mut server, time);
 
         // We give both the server and the client a really bad bidirectional link
         let link_conditioner = {
@@ -1027,10 +3505,16 @@ mod tests {
                         SocketEvent::Packet(pkt) => {
                             set.insert(pkt.payload()[0]);
                         }
-                        SocketEvent::Timeout(_) => {
+                        SocketEvent::Timeout(_) | SocketEvent::Disconnect(..) => {
                             panic!["Unable to time out, time has not advanced"]
                         }
-                        SocketEvent::Connect(_) => {}
+                        SocketEvent::Connect(_, _)
+                        | SocketEvent::Acked(..)
+                        | SocketEvent::Lost(..)
+                        | SocketEvent::Expired(..)
+                        | SocketEvent::Progress(..)
+                        | SocketEvent::CongestionChanged(..)
+                        | SocketEvent::QualityChanged(..) => {}
                     }
                 }
             }
@@ -0,0 +1,88 @@
+use crate::infrastructure::ReassemblyStats;
+
+use std::time::{Duration, Instant};
+
+/// A snapshot of the network health of a connection, as returned by `Socket::connection_stats`.
+///
+/// Byte counters include the headers of laminar, but not the overhead of encryption, UDP and IP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionStats {
+    /// The smoothed round trip time, if any packet was acknowledged yet.
+    pub smoothed_rtt: Option<Duration>,
+    /// The variance of the round trip time.
+    pub jitter: Duration,
+    /// The lowest round trip time which was measured, if any packet was acknowledged yet.
+    pub min_rtt: Option<Duration>,
+    /// The percentage of the packets which were considered lost, out of the packets which were
+    /// either acknowledged or considered lost.
+    pub packet_loss: f32,
+    /// Packets sent to the remote endpoint, including handshakes, heartbeats and resends.
+    pub packets_sent: u64,
+    /// Bytes sent to the remote endpoint.
+    pub bytes_sent: u64,
+    /// Packets received from the remote endpoint.
+    pub packets_received: u64,
+    /// Bytes received from the remote endpoint.
+    pub bytes_received: u64,
+    /// Packets which were acknowledged by the remote endpoint.
+    pub packets_acked: u64,
+    /// Payload bytes of the reliable packets which were acknowledged by the remote endpoint.
+    pub bytes_acked: u64,
+    /// Reliable packets which were resent because they were not acknowledged in time.
+    pub packets_resent: u64,
+    /// Payload bytes of the resent packets.
+    pub bytes_resent: u64,
+    /// Packets which were considered lost, whether they were resent or not.
+    pub packets_lost: u64,
+    /// Packets which were sent but are neither acknowledged nor considered lost yet.
+    pub packets_in_flight: usize,
+    /// Payload bytes of the reliable packets in flight.
+    pub bytes_in_flight: usize,
+    /// The time a packet was last received from the remote endpoint.
+    pub last_heard: Instant,
+    /// The time a packet was last sent to the remote endpoint.
+    pub last_sent: Instant,
+    /// The counters of the partially received packets which were discarded.
+    pub reassembly: ReassemblyStats,
+}
+
+/// Counts the traffic of a connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrafficCounters {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_acked: u64,
+    pub bytes_acked: u64,
+    pub packets_resent: u64,
+    pub bytes_resent: u64,
+    pub packets_lost: u64,
+}
+
+impl TrafficCounters {
+    /// Returns the percentage of the packets which were considered lost, out of the packets which
+    /// were either acknowledged or considered lost.
+    pub fn packet_loss(&self) -> f32 {
+        let resolved = self.packets_acked + self.packets_lost;
+        if resolved == 0 {
+            return 0.;
+        }
+        (self.packets_lost as f64 / resolved as f64 * 100.) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrafficCounters;
+
+    #[test]
+    fn packet_loss_is_a_percentage_of_the_resolved_packets() {
+        let mut counters = TrafficCounters::default();
+        assert_eq!(counters.packet_loss(), 0.);
+
+        counters.packets_acked = 3;
+        counters.packets_lost = 1;
+        assert_eq!(counters.packet_loss(), 25.);
+    }
+}
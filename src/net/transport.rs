@@ -0,0 +1,72 @@
+//! This module provides the datagram transports a [Socket](crate::Socket) can run on.
+//!
+//! By default a socket runs on a UDP socket, but any transport which can send and receive datagrams
+//! can be used, e.g. a WebRTC data channel, a relay, or the in-process [ChannelTransport] which is
+//! useful for testing.
+
+mod channel;
+
+pub use self::channel::{ChannelNetwork, ChannelTransport};
+
+use super::poller::TransportReadiness;
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+};
+
+/// A transport which sends and receives datagrams, on which the reliability, ordering and connection
+/// management of a [Socket](crate::Socket) are built.
+///
+/// Like UDP, a transport may drop, duplicate or reorder datagrams.
+pub trait DatagramTransport {
+    /// Sends a datagram to the given address, returning the number of bytes sent.
+    fn send_to(&mut self, payload: &[u8], address: SocketAddr) -> io::Result<usize>;
+
+    /// Receives a single datagram into the buffer, returning the number of bytes read and the
+    /// address it came from. When the transport is non-blocking and no datagram is available, an
+    /// error of the kind `WouldBlock` is returned.
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Returns the local address of the transport.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Switches the transport between blocking and non-blocking receives.
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+
+    /// Registers how the polling loop learns that a datagram can be received, either with a file
+    /// descriptor or with a [TransportWaker](crate::TransportWaker) which the transport wakes. Returns
+    /// false if the transport does not support this, in which case the polling loop checks it at
+    /// least once per millisecond.
+    fn register(&self, _readiness: &mut TransportReadiness<'_>) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// On unix, the polling loop wakes up as soon as the socket is readable. On other targets the
+/// readiness of the socket is not polled, so the polling loop checks it at least once per
+/// millisecond.
+impl DatagramTransport for UdpSocket {
+    fn send_to(&mut self, payload: &[u8], address: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, payload, address)
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buffer)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+
+    #[cfg(unix)]
+    fn register(&self, readiness: &mut TransportReadiness<'_>) -> io::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+
+        readiness.register_fd(self.as_raw_fd())?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,192 @@
+use super::DatagramTransport;
+use crate::net::poller::TransportReadiness;
+use crossbeam_channel::{unbounded, Receiver, RecvError, Sender, TryRecvError};
+use mio::{Ready, Registration, SetReadiness};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// An in-process network of [ChannelTransport]s, which deliver datagrams to each other over
+/// channels. Datagrams sent to an address which no transport is bound to are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelNetwork {
+    endpoints: Arc<Mutex<HashMap<SocketAddr, Endpoint>>>,
+}
+
+// The receiving side of a bound transport, as seen by the other transports.
+#[derive(Debug)]
+struct Endpoint {
+    sender: Sender<(Box<[u8]>, SocketAddr)>,
+    set_readiness: SetReadiness,
+}
+
+impl ChannelNetwork {
+    /// Constructs a new, empty `ChannelNetwork`.
+    pub fn new() -> ChannelNetwork {
+        ChannelNetwork::default()
+    }
+
+    /// Binds a transport to the given address. Returns an error of the kind `AddrInUse` if another
+    /// transport is bound to it already.
+    pub fn bind(&self, address: SocketAddr) -> io::Result<ChannelTransport> {
+        let mut endpoints = self.endpoints();
+        if endpoints.contains_key(&address) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+
+        let (sender, receiver) = unbounded();
+        let (registration, set_readiness) = Registration::new2();
+        endpoints.insert(
+            address,
+            Endpoint {
+                sender,
+                set_readiness: set_readiness.clone(),
+            },
+        );
+
+        Ok(ChannelTransport {
+            address,
+            network: self.clone(),
+            receiver,
+            registration,
+            set_readiness,
+            nonblocking: false,
+        })
+    }
+
+    fn endpoints(&self) -> MutexGuard<'_, HashMap<SocketAddr, Endpoint>> {
+        self.endpoints.lock().expect("Network lock poisoned")
+    }
+}
+
+/// A [DatagramTransport] which is bound to an address of a [ChannelNetwork].
+#[derive(Debug)]
+pub struct ChannelTransport {
+    address: SocketAddr,
+    network: ChannelNetwork,
+    receiver: Receiver<(Box<[u8]>, SocketAddr)>,
+    // Readable while datagrams are waiting to be received.
+    registration: Registration,
+    set_readiness: SetReadiness,
+    nonblocking: bool,
+}
+
+impl ChannelTransport {
+    fn receive(&self) -> io::Result<(Box<[u8]>, SocketAddr)> {
+        if !self.nonblocking {
+            return self
+                .receiver
+                .recv()
+                .map_err(|RecvError| io::ErrorKind::BrokenPipe.into());
+        }
+
+        match self.receiver.try_recv() {
+            Ok(datagram) => Ok(datagram),
+            Err(TryRecvError::Empty) => {
+                // Only reset the readiness once there is nothing left to receive, a datagram which
+                // is sent in the meantime makes us readable again.
+                self.set_readiness.set_readiness(Ready::empty())?;
+                match self.receiver.try_recv() {
+                    Ok(datagram) => {
+                        self.set_readiness.set_readiness(Ready::readable())?;
+                        Ok(datagram)
+                    }
+                    Err(_) => Err(io::ErrorKind::WouldBlock.into()),
+                }
+            }
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+impl DatagramTransport for ChannelTransport {
+    fn send_to(&mut self, payload: &[u8], address: SocketAddr) -> io::Result<usize> {
+        if let Some(endpoint) = self.network.endpoints().get(&address) {
+            // The remote transport is being dropped if this fails, which is the same as not being
+            // bound at all.
+            if endpoint.sender.send((payload.into(), self.address)).is_ok() {
+                endpoint.set_readiness.set_readiness(Ready::readable())?;
+            }
+        }
+
+        Ok(payload.len())
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (datagram, address) = self.receive()?;
+
+        // Like UDP, the part of the datagram which does not fit into the buffer is discarded.
+        let len = datagram.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&datagram[..len]);
+        Ok((len, address))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.address)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn register(&self, readiness: &mut TransportReadiness<'_>) -> io::Result<bool> {
+        readiness.register_evented(&self.registration)?;
+        Ok(true)
+    }
+}
+
+impl Drop for ChannelTransport {
+    fn drop(&mut self) {
+        // The network is unusable anyway if its lock is poisoned.
+        if let Ok(mut endpoints) = self.network.endpoints.lock() {
+            endpoints.remove(&self.address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChannelNetwork;
+    use crate::net::DatagramTransport;
+    use std::{io, net::SocketAddr};
+
+    #[test]
+    fn datagrams_are_delivered_to_bound_address() {
+        let network = ChannelNetwork::new();
+        let first_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let second_addr: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+
+        let mut first = network.bind(first_addr).unwrap();
+        let mut second = network.bind(second_addr).unwrap();
+        second.set_nonblocking(true).unwrap();
+
+        first.send_to(&[1, 2, 3], second_addr).unwrap();
+
+        let mut buffer = [0; 16];
+        assert_eq!(second.recv_from(&mut buffer).unwrap(), (3, first_addr));
+        assert_eq!(&buffer[..3], &[1, 2, 3]);
+        assert_eq!(
+            second.recv_from(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn address_can_only_be_bound_once() {
+        let network = ChannelNetwork::new();
+        let address: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+
+        let transport = network.bind(address).unwrap();
+        assert_eq!(
+            network.bind(address).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        drop(transport);
+        assert!(network.bind(address).is_ok());
+    }
+}
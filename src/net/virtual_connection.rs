@@ -1,26 +1,42 @@
 use crate::{
     config::Config,
-    error::{ErrorKind, PacketErrorKind, Result},
+    error::{EncryptionErrorKind, ErrorKind, PacketErrorKind, Result},
     infrastructure::{
         arranging::{Arranging, ArrangingSystem, OrderingSystem, SequencingSystem},
-        AcknowledgmentHandler, CongestionHandler, Fragmentation, SentPacket,
+        AcknowledgmentHandler, CongestionHandler, ConnectionKeys, EncryptionHandler, Fragmentation,
+        LargeMessageReceiver, LargeMessageSender, Pacer, ReassemblyStats, ReceivedChunk,
+        SentPacket,
     },
-    net::constants::{
-        ACKED_PACKET_HEADER, DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM,
-        STANDARD_HEADER_SIZE,
+    net::{
+        connect_token::{ClientData, ConnectToken},
+        constants::{
+            DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM, FRAGMENT_HEADER_SIZE,
+            STANDARD_HEADER_SIZE,
+        },
+        events::{send_event, MessageProgress},
+        handshake::{challenge_response_packet, connection_request_packet, ChallengeCookie},
+        quality::{NetworkQuality, QualityMonitor},
+        stats::{ConnectionStats, TrafficCounters},
     },
     packet::{
+        header::{AckWindow, AckedPacketHeader, ArrangingHeader, ChunkHeader},
         DeliveryGuarantee, OrderingGuarantee, Outgoing, OutgoingPacket, OutgoingPacketBuilder,
-        Packet, PacketReader, PacketType, SequenceNumber,
+        Packet, PacketId, PacketReader, PacketType, SequenceNumber,
     },
+    sequence_buffer::SequenceBuffer,
     SocketEvent,
 };
 
 use crossbeam_channel::{self, Sender};
+use log::warn;
+use std::borrow::Cow;
 use std::fmt;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
+// The number of reliable unordered packets whose identifier is remembered to drop their duplicates.
+const DELIVERED_PACKETS_SIZE: u16 = 4096;
+
 /// Contains the information about a certain 'virtual connection' over udp.
 /// This connections also keeps track of network quality, processing packets, buffering data related to connection etc.
 pub struct VirtualConnection {
@@ -31,27 +47,82 @@ pub struct VirtualConnection {
     /// The address of the remote endpoint
     pub remote_address: SocketAddr,
 
+    state: ConnectionState,
+    // Random nonce sent along with our connection requests.
+    request_nonce: u64,
+    // Packets which are waiting for the handshake to complete before they can be sent.
+    queued_packets: Vec<(PacketId, Packet)>,
+    // The cookie the remote endpoint challenged us with.
+    challenge_cookie: Option<ChallengeCookie>,
+    // Last time we sent a handshake packet to this client.
+    last_handshake: Option<Instant>,
+    // Encrypts and decrypts packets, if the connection has keys.
+    encryption_handler: Option<EncryptionHandler>,
+    // The connect token whose private part we send along with our handshake packets.
+    connect_token: Option<ConnectToken>,
+    // The data of the connect token the remote endpoint connected with.
+    client_data: Option<ClientData>,
+
     ordering_system: OrderingSystem<Box<[u8]>>,
     sequencing_system: SequencingSystem<Box<[u8]>>,
+    // The identifier of the next reliable unordered packet, which is kept when it is resent.
+    delivery_identifier: SequenceNumber,
+    // The identifiers of the reliable unordered packets which were handed to the application.
+    delivered_packets: SequenceBuffer<()>,
     acknowledge_handler: AcknowledgmentHandler,
+    // Whether packets were received which were not acknowledged by a packet we sent yet.
+    ack_required: bool,
     congestion_handler: CongestionHandler,
+    // The mode of the congestion controller which was last reported to the application.
+    congestion_quality: NetworkQuality,
+    // Holds back the packets which exceed the send rate.
+    pacer: Pacer,
+    quality_monitor: QualityMonitor,
+    traffic: TrafficCounters,
 
     config: Config,
     fragmentation: Fragmentation,
+    large_message_sender: LargeMessageSender,
+    large_message_receiver: LargeMessageReceiver,
 }
 
 impl VirtualConnection {
-    /// Creates and returns a new Connection that wraps the provided socket address
+    /// Creates and returns a new Connection that wraps the provided socket address.
+    ///
+    /// The connection starts in the [ConnectionState::Connecting] state.
     pub fn new(addr: SocketAddr, config: &Config, time: Instant) -> VirtualConnection {
         VirtualConnection {
             last_heard: time,
             last_sent: time,
             remote_address: addr,
+            state: ConnectionState::Connecting,
+            request_nonce: rand::random(),
+            queued_packets: Vec::new(),
+            challenge_cookie: None,
+            last_handshake: None,
+            encryption_handler: None,
+            connect_token: None,
+            client_data: None,
             ordering_system: OrderingSystem::new(),
             sequencing_system: SequencingSystem::new(),
+            delivery_identifier: 0,
+            delivered_packets: SequenceBuffer::with_capacity(DELIVERED_PACKETS_SIZE),
             acknowledge_handler: AcknowledgmentHandler::new(),
+            ack_required: false,
             congestion_handler: CongestionHandler::new(config),
+            congestion_quality: NetworkQuality::Good,
+            pacer: Pacer::new(config, time),
+            quality_monitor: QualityMonitor::new(config),
+            traffic: TrafficCounters::default(),
             fragmentation: Fragmentation::new(config),
+            large_message_sender: LargeMessageSender::new(
+                config.fragment_size,
+                config.large_message_window,
+            ),
+            large_message_receiver: LargeMessageReceiver::new(
+                config.max_large_message_size,
+                config.large_message_reassembly_timeout,
+            ),
             config: config.to_owned(),
         }
     }
@@ -70,11 +141,303 @@ impl VirtualConnection {
         time.duration_since(self.last_sent)
     }
 
+    /// Returns the state of the connection handshake.
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Returns true if the connection handshake has completed.
+    pub fn is_connected(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    /// Marks the handshake as completed and returns the packets which were waiting for it.
+    pub fn establish(&mut self) -> Vec<(PacketId, Packet)> {
+        self.state = ConnectionState::Connected;
+        self.challenge_cookie = None;
+        self.last_handshake = None;
+        std::mem::take(&mut self.queued_packets)
+    }
+
+    /// Queues a packet to be sent once the connection handshake has completed.
+    pub fn queue_packet_until_connected(&mut self, id: PacketId, packet: Packet) {
+        self.queued_packets.push((id, packet));
+    }
+
+    /// Returns the number of reliable payload bytes which have not been acknowledged yet, including
+    /// the reliable packets which are waiting for the handshake to complete. Large messages are not
+    /// counted, their chunks are limited by `large_message_window` instead.
+    pub fn in_flight_bytes(&self) -> usize {
+        let queued_bytes: usize = self
+            .queued_packets
+            .iter()
+            .filter(|(_, packet)| {
+                packet.delivery_guarantee() == DeliveryGuarantee::Reliable
+                    && !packet.is_large_message()
+            })
+            .map(|(_, packet)| packet.payload().len())
+            .sum();
+        self.acknowledge_handler.in_flight_bytes() + queued_bytes
+    }
+
+    /// Discards the partially received packets whose fragments did not all arrive in time, and the
+    /// partially received large message if its chunks stopped arriving.
+    pub fn discard_expired_fragments(&mut self, time: Instant) {
+        self.fragmentation.discard_expired(time);
+        self.large_message_receiver.discard_expired(time);
+    }
+
+    /// Returns the counters of the partially received packets which were discarded.
+    pub fn reassembly_stats(&self) -> ReassemblyStats {
+        self.fragmentation.stats()
+    }
+
+    /// Counts a packet of the given size which is sent to the remote endpoint.
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.traffic.packets_sent += 1;
+        self.traffic.bytes_sent += bytes as u64;
+    }
+
+    /// Counts a packet of the given size which is received from the remote endpoint.
+    pub fn record_received(&mut self, bytes: usize) {
+        self.traffic.packets_received += 1;
+        self.traffic.bytes_received += bytes as u64;
+    }
+
+    /// Returns a snapshot of the round trip time, loss and traffic of the connection.
+    pub fn stats(&self) -> ConnectionStats {
+        let rtt_measurer = self.congestion_handler.rtt_measurer();
+        ConnectionStats {
+            smoothed_rtt: rtt_measurer.smoothed_rtt(),
+            jitter: rtt_measurer.rtt_variance(),
+            min_rtt: rtt_measurer.min_rtt(),
+            packet_loss: self.traffic.packet_loss(),
+            packets_sent: self.traffic.packets_sent,
+            bytes_sent: self.traffic.bytes_sent,
+            packets_received: self.traffic.packets_received,
+            bytes_received: self.traffic.bytes_received,
+            packets_acked: self.traffic.packets_acked,
+            bytes_acked: self.traffic.bytes_acked,
+            packets_resent: self.traffic.packets_resent,
+            bytes_resent: self.traffic.bytes_resent,
+            packets_lost: self.traffic.packets_lost,
+            packets_in_flight: self.acknowledge_handler.packets_in_flight(),
+            bytes_in_flight: self.acknowledge_handler.in_flight_bytes(),
+            last_heard: self.last_heard,
+            last_sent: self.last_sent,
+            reassembly: self.fragmentation.stats(),
+        }
+    }
+
+    /// Returns the acknowledgment window of the connection.
+    pub fn ack_window(&self) -> AckWindow {
+        self.acknowledge_handler.window()
+    }
+
+    /// Sets the acknowledgment window that was negotiated during the handshake.
+    pub fn set_ack_window(&mut self, window: AckWindow) {
+        self.acknowledge_handler.set_window(window);
+    }
+
+    /// Returns the random nonce which is sent along with our connection requests.
+    pub fn request_nonce(&self) -> u64 {
+        self.request_nonce
+    }
+
+    /// Returns true if the remote endpoint challenged us already.
+    pub fn has_challenge_cookie(&self) -> bool {
+        self.challenge_cookie.is_some()
+    }
+
+    /// Stores the cookie the remote endpoint challenged us with.
+    pub fn set_challenge_cookie(&mut self, cookie: ChallengeCookie) {
+        self.challenge_cookie = Some(cookie);
+    }
+
+    /// Returns the handshake packet that should be sent to the remote endpoint, if the connection is
+    /// still being established and `interval` has passed since the last handshake packet.
+    pub fn create_handshake_packet(
+        &mut self,
+        interval: Duration,
+        time: Instant,
+    ) -> Option<Box<[u8]>> {
+        if self.is_connected() {
+            return None;
+        }
+
+        if let Some(last_handshake) = self.last_handshake {
+            if last_handshake + interval > time {
+                return None;
+            }
+        }
+
+        self.last_handshake = Some(time);
+
+        let connect_token = self
+            .connect_token
+            .as_ref()
+            .map(ConnectToken::private_data)
+            .unwrap_or_default();
+
+        Some(match self.challenge_cookie {
+            Some(cookie) => challenge_response_packet(
+                &cookie,
+                AckWindow::from_packets(self.config.ack_window_size),
+                connect_token,
+            ),
+            None => connection_request_packet(self.request_nonce, connect_token),
+        })
+    }
+
+    /// Sends the given connect token along with our handshake packets.
+    pub fn set_connect_token(&mut self, token: &ConnectToken) {
+        self.connect_token = Some(token.clone());
+    }
+
+    /// Returns the keys of the connection made with our connect token and the given handshake
+    /// cookie, if we connect with a token.
+    pub fn connect_token_keys(&self, cookie: &ChallengeCookie) -> Option<ConnectionKeys> {
+        self.connect_token
+            .as_ref()
+            .map(|token| token.client_keys(cookie))
+    }
+
+    /// Returns the data of the connect token the remote endpoint connected with, if any.
+    pub fn client_data(&self) -> Option<&ClientData> {
+        self.client_data.as_ref()
+    }
+
+    /// Stores the data of the connect token the remote endpoint connected with.
+    pub fn set_client_data(&mut self, client_data: ClientData) {
+        self.client_data = Some(client_data);
+    }
+
+    /// Returns the first moment after `time` at which a timer of this connection expires: the idle
+    /// timeout, the next handshake packet, the next heartbeat or the next resend.
+    pub fn next_timer(&self, time: Instant) -> Instant {
+        let mut next_timer = self.last_heard + self.config.idle_connection_timeout;
+
+        if !self.is_connected() {
+            let next_handshake = self.last_handshake.map_or(time, |last_handshake| {
+                last_handshake + self.config.handshake_interval
+            });
+            next_timer = next_timer.min(next_handshake);
+        } else if let Some(heartbeat_interval) = self.config.heartbeat_interval {
+            next_timer = next_timer.min(self.last_sent + heartbeat_interval);
+        }
+
+        if let Some(oldest_sent_time) = self.acknowledge_handler.oldest_sent_time() {
+            let next_resend = oldest_sent_time + self.congestion_handler.retransmission_timeout();
+            next_timer = next_timer.min(next_resend);
+        }
+
+        if let Some(next_paced_send) = self.pacer.next_send_time() {
+            next_timer = next_timer.min(next_paced_send);
+        }
+
+        if let Some(next_expiry) = self.fragmentation.next_expiry() {
+            next_timer = next_timer.min(next_expiry);
+        }
+
+        if let Some(next_expiry) = self.large_message_receiver.next_expiry() {
+            next_timer = next_timer.min(next_expiry);
+        }
+
+        if let Some(next_chunk_resend) = self
+            .large_message_sender
+            .next_resend_time(self.congestion_handler.retransmission_timeout())
+        {
+            next_timer = next_timer.min(next_chunk_resend);
+        }
+
+        next_timer
+    }
+
+    /// Holds the packet, which is acknowledged with the given sequence number if it has one, back
+    /// behind the packets which exceed the send rate, and returns the packets which may be sent now.
+    pub fn pace_packet(
+        &mut self,
+        packet: Box<[u8]>,
+        sequence: Option<SequenceNumber>,
+        time: Instant,
+    ) -> Vec<Box<[u8]>> {
+        self.pacer.push(packet, sequence, time);
+        self.take_paced_packets(time)
+    }
+
+    /// Returns the held back packets which the send rate of the congestion controller and
+    /// `max_send_rate` allow to be sent by now.
+    ///
+    /// The round trip time and the retransmission timeout of a packet are measured from the moment
+    /// it is released.
+    pub fn take_paced_packets(&mut self, time: Instant) -> Vec<Box<[u8]>> {
+        let packets = self
+            .pacer
+            .take_due(time, self.congestion_handler.send_rate());
+
+        packets
+            .into_iter()
+            .map(|(packet, sequence)| {
+                if let Some(sequence) = sequence {
+                    self.congestion_handler.process_outgoing(sequence, time);
+                    self.acknowledge_handler.record_sent(sequence, time);
+                }
+                packet
+            })
+            .collect()
+    }
+
+    /// Returns true if this connection holds back `max_paced_packets` packets already.
+    pub fn is_pacer_full(&self) -> bool {
+        self.pacer.is_full()
+    }
+
+    /// Sets the keys with which the packets of this connection are encrypted from now on.
+    pub fn set_keys(&mut self, keys: &ConnectionKeys) {
+        self.encryption_handler = Some(EncryptionHandler::new(keys));
+    }
+
+    /// Returns true if the connection has keys to encrypt its packets with.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption_handler.is_some()
+    }
+
+    /// Encrypts the packet if the connection is established and has keys, otherwise the packet is
+    /// returned as is.
+    pub fn encrypt<'a>(&mut self, packet: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        match self.encryption_handler {
+            Some(ref mut encryption_handler) if self.state == ConnectionState::Connected => {
+                Ok(Cow::Owned(encryption_handler.encrypt(packet)?.into_vec()))
+            }
+            _ => Ok(Cow::Borrowed(packet)),
+        }
+    }
+
+    /// Decrypts a packet that was encrypted by the remote endpoint.
+    pub fn decrypt(&mut self, packet: &[u8]) -> Result<Box<[u8]>> {
+        match self.encryption_handler {
+            Some(ref mut encryption_handler) => encryption_handler.decrypt(packet),
+            None => Err(EncryptionErrorKind::MissingKeys.into()),
+        }
+    }
+
+    /// Returns true if a heartbeat should be sent: to acknowledge the packets which were received
+    /// since we last sent a packet, or to keep the connection alive when nothing was sent for
+    /// `heartbeat_interval`.
+    pub fn requires_heartbeat(&self, heartbeat_interval: Option<Duration>, time: Instant) -> bool {
+        self.is_connected()
+            && (self.ack_required
+                || heartbeat_interval
+                    .is_some_and(|heartbeat_interval| self.last_sent(time) >= heartbeat_interval))
+    }
+
     /// This will create a heartbeat packet that is expected to be sent over the network
+    ///
+    /// Heartbeats carry the acknowledgments of the received packets, but are not acknowledged
+    /// themselves.
     pub fn create_and_process_heartbeat(&mut self, time: Instant) -> OutgoingPacket<'static> {
         self.last_sent = time;
-        self.congestion_handler
-            .process_outgoing(self.acknowledge_handler.local_sequence_num(), time);
+        self.ack_required = false;
 
         OutgoingPacketBuilder::new(&[])
             .with_default_header(
@@ -82,150 +445,195 @@ impl VirtualConnection {
                 DeliveryGuarantee::Unreliable,
                 OrderingGuarantee::None,
             )
+            .with_acknowledgment_header(
+                self.acknowledge_handler.local_sequence_num(),
+                self.acknowledge_handler.remote_sequence_num(),
+                self.acknowledge_handler.ack_bitfield(),
+                self.acknowledge_handler.window(),
+            )
             .build()
     }
 
     /// This will pre-process the given buffer to be sent over the network.
+    ///
+    /// The packet is tracked under the given `id` until it is acknowledged or considered lost.
+    /// Payloads larger than the fragment size are split into fragments, whatever their guarantees.
     pub fn process_outgoing<'a>(
         &mut self,
+        id: PacketId,
         payload: &'a [u8],
         delivery_guarantee: DeliveryGuarantee,
         ordering_guarantee: OrderingGuarantee,
         last_item_identifier: Option<SequenceNumber>,
         time: Instant,
     ) -> Result<Outgoing<'a>> {
-        match delivery_guarantee {
-            DeliveryGuarantee::Unreliable => {
-                if payload.len() <= self.config.receive_buffer_max_size {
-                    let mut builder = OutgoingPacketBuilder::new(payload).with_default_header(
-                        PacketType::Packet,
-                        delivery_guarantee,
-                        ordering_guarantee,
-                    );
+        if payload.len() > self.config.max_packet_size {
+            return Err(ErrorKind::PacketError(
+                PacketErrorKind::ExceededMaxPacketSize,
+            ));
+        }
 
-                    if let OrderingGuarantee::Sequenced(stream_id) = ordering_guarantee {
-                        let item_identifier = self
-                            .sequencing_system
-                            .get_or_create_stream(stream_id.unwrap_or(DEFAULT_SEQUENCING_STREAM))
-                            .new_item_identifier();
+        // Resent packets keep the identifier they were first sent with on their stream.
+        let item_identifier = match ordering_guarantee {
+            OrderingGuarantee::Ordered(stream_id)
+                if delivery_guarantee == DeliveryGuarantee::Reliable =>
+            {
+                Some(last_item_identifier.unwrap_or_else(|| {
+                    self.ordering_system
+                        .get_or_create_stream(stream_id.unwrap_or(DEFAULT_ORDERING_STREAM))
+                        .new_item_identifier() as u16
+                }))
+            }
+            OrderingGuarantee::Sequenced(stream_id) => {
+                Some(last_item_identifier.unwrap_or_else(|| {
+                    self.sequencing_system
+                        .get_or_create_stream(stream_id.unwrap_or(DEFAULT_SEQUENCING_STREAM))
+                        .new_item_identifier() as u16
+                }))
+            }
+            OrderingGuarantee::None if delivery_guarantee == DeliveryGuarantee::Reliable => {
+                Some(last_item_identifier.unwrap_or_else(|| {
+                    let delivery_identifier = self.delivery_identifier;
+                    self.delivery_identifier = delivery_identifier.wrapping_add(1);
+                    delivery_identifier
+                }))
+            }
+            _ => None,
+        };
+
+        let sequence = self.acknowledge_handler.local_sequence_num();
+        let remote_sequence = self.acknowledge_handler.remote_sequence_num();
+        let ack_bitfield = self.acknowledge_handler.ack_bitfield();
+        let window = self.acknowledge_handler.window();
+
+        // spit the packet if the payload length is greater than the allowed fragment size.
+        let outgoing = if payload.len() <= usize::from(self.config.fragment_size) {
+            let builder = OutgoingPacketBuilder::new(payload)
+                .with_default_header(PacketType::Packet, delivery_guarantee, ordering_guarantee)
+                .with_acknowledgment_header(sequence, remote_sequence, ack_bitfield, window);
+
+            Outgoing::Packet(
+                Self::with_arranging_header(builder, ordering_guarantee, item_identifier).build(),
+            )
+        } else {
+            let fragments = Fragmentation::spit_into_fragments(payload, &self.config)?;
+            let fragment_count = fragments.len() as u8;
+
+            Outgoing::Fragments(
+                fragments
+                    .into_iter()
+                    .enumerate()
+                    .map(|(fragment_id, fragment)| {
+                        let mut builder = OutgoingPacketBuilder::new(fragment)
+                            .with_default_header(
+                                PacketType::Fragment,
+                                delivery_guarantee,
+                                ordering_guarantee,
+                            )
+                            .with_fragment_header(sequence, fragment_id as u8, fragment_count);
+
+                        // The first fragment carries the headers which apply to the whole packet.
+                        if fragment_id == 0 {
+                            builder = Self::with_arranging_header(
+                                builder.with_acknowledgment_header(
+                                    sequence,
+                                    remote_sequence,
+                                    ack_bitfield,
+                                    window,
+                                ),
+                                ordering_guarantee,
+                                item_identifier,
+                            );
+                        }
 
-                        builder = builder.with_sequencing_header(item_identifier as u16, stream_id);
-                    };
+                        builder.build()
+                    })
+                    .collect(),
+            )
+        };
 
-                    Ok(Outgoing::Packet(builder.build()))
-                } else {
-                    Err(ErrorKind::PacketError(
-                        PacketErrorKind::ExceededMaxPacketSize,
-                    ))
-                }
-            }
-            DeliveryGuarantee::Reliable => {
-                let payload_length = payload.len() as u16;
-
-                let mut item_identifier_value = None;
-                let outgoing = {
-                    // spit the packet if the payload length is greater than the allowed fragment size.
-                    if payload_length <= self.config.fragment_size {
-                        let mut builder = OutgoingPacketBuilder::new(payload).with_default_header(
-                            PacketType::Packet,
-                            delivery_guarantee,
-                            ordering_guarantee,
-                        );
-
-                        builder = builder.with_acknowledgment_header(
-                            self.acknowledge_handler.local_sequence_num(),
-                            self.acknowledge_handler.remote_sequence_num(),
-                            self.acknowledge_handler.ack_bitfield(),
-                        );
-
-                        if let OrderingGuarantee::Ordered(stream_id) = ordering_guarantee {
-                            let item_identifier = if let Some(item_identifier) =
-                                last_item_identifier
-                            {
-                                item_identifier
-                            } else {
-                                self.ordering_system
-                                    .get_or_create_stream(
-                                        stream_id.unwrap_or(DEFAULT_ORDERING_STREAM),
-                                    )
-                                    .new_item_identifier() as u16
-                            };
-
-                            item_identifier_value = Some(item_identifier);
-
-                            builder = builder.with_ordering_header(item_identifier, stream_id);
-                        };
-
-                        if let OrderingGuarantee::Sequenced(stream_id) = ordering_guarantee {
-                            let item_identifier = if let Some(item_identifier) =
-                                last_item_identifier
-                            {
-                                item_identifier
-                            } else {
-                                self.sequencing_system
-                                    .get_or_create_stream(
-                                        stream_id.unwrap_or(DEFAULT_SEQUENCING_STREAM),
-                                    )
-                                    .new_item_identifier() as u16
-                            };
-
-                            item_identifier_value = Some(item_identifier);
-
-                            builder = builder.with_sequencing_header(item_identifier, stream_id);
-                        };
-
-                        Outgoing::Packet(builder.build())
-                    } else {
-                        Outgoing::Fragments(
-                            Fragmentation::spit_into_fragments(payload, &self.config)?
-                                .into_iter()
-                                .enumerate()
-                                .map(|(fragment_id, fragment)| {
-                                    let fragments_needed = Fragmentation::fragments_needed(
-                                        payload_length,
-                                        self.config.fragment_size,
-                                    )
-                                        as u8;
-
-                                    let mut builder = OutgoingPacketBuilder::new(fragment)
-                                        .with_default_header(
-                                            PacketType::Fragment,
-                                            delivery_guarantee,
-                                            ordering_guarantee,
-                                        );
-
-                                    builder = builder.with_fragment_header(
-                                        self.acknowledge_handler.local_sequence_num(),
-                                        fragment_id as u8,
-                                        fragments_needed,
-                                    );
-
-                                    if fragment_id == 0 {
-                                        builder = builder.with_acknowledgment_header(
-                                            self.acknowledge_handler.local_sequence_num(),
-                                            self.acknowledge_handler.remote_sequence_num(),
-                                            self.acknowledge_handler.ack_bitfield(),
-                                        );
-                                    }
-
-                                    builder.build()
-                                })
-                                .collect(),
-                        )
-                    }
-                };
+        self.last_sent = time;
+        self.ack_required = false;
+        // Unreliable packets are only tracked to learn whether they arrived, they are never resent
+        // so their payload is not kept.
+        let tracked_payload = match delivery_guarantee {
+            DeliveryGuarantee::Reliable => payload,
+            DeliveryGuarantee::Unreliable => &[],
+        };
+        self.acknowledge_handler.process_outgoing(
+            id,
+            tracked_payload,
+            delivery_guarantee,
+            ordering_guarantee,
+            item_identifier,
+        );
 
-                self.last_sent = time;
-                self.congestion_handler
-                    .process_outgoing(self.acknowledge_handler.local_sequence_num(), time);
-                self.acknowledge_handler.process_outgoing(
-                    payload,
-                    ordering_guarantee,
-                    item_identifier_value,
-                );
+        Ok(outgoing)
+    }
 
-                Ok(outgoing)
+    /// Queues a large message to be streamed in chunks, after the large messages queued before it.
+    ///
+    /// The message is tracked under the given `id` until all of its chunks are acknowledged.
+    pub fn queue_large_message(&mut self, id: PacketId, payload: &[u8]) -> Result<()> {
+        if payload.len() > self.config.max_large_message_size {
+            return Err(ErrorKind::PacketError(
+                PacketErrorKind::ExceededMaxPacketSize,
+            ));
+        }
+
+        self.large_message_sender.push(id, payload);
+        Ok(())
+    }
+
+    /// Returns the large message packets which are due: the acknowledgment of the chunks which
+    /// were received since the last poll, and the chunks which were not sent yet or not
+    /// acknowledged within the retransmission timeout.
+    pub fn gather_large_message_packets(&mut self, time: Instant) -> Vec<Box<[u8]>> {
+        let mut packets = Vec::new();
+
+        if let Some(ack_header) = self.large_message_receiver.take_ack() {
+            packets.push(
+                OutgoingPacketBuilder::new(&[])
+                    .with_default_header(
+                        PacketType::ChunkAck,
+                        DeliveryGuarantee::Unreliable,
+                        OrderingGuarantee::None,
+                    )
+                    .with_chunk_ack_header(ack_header)
+                    .build()
+                    .contents(),
+            );
+        }
+
+        packets.extend(
+            self.large_message_sender
+                .due_chunks(time, self.congestion_handler.retransmission_timeout()),
+        );
+
+        if !packets.is_empty() {
+            self.last_sent = time;
+        }
+        packets
+    }
+
+    // Adds the arranging header for the given ordering guarantee, if the packet has an identifier
+    // on its stream.
+    fn with_arranging_header(
+        builder: OutgoingPacketBuilder,
+        ordering_guarantee: OrderingGuarantee,
+        item_identifier: Option<SequenceNumber>,
+    ) -> OutgoingPacketBuilder {
+        match (ordering_guarantee, item_identifier) {
+            (OrderingGuarantee::Ordered(stream_id), Some(item_identifier)) => {
+                builder.with_ordering_header(item_identifier, stream_id)
             }
+            (OrderingGuarantee::Sequenced(stream_id), Some(item_identifier)) => {
+                builder.with_sequencing_header(item_identifier, stream_id)
+            }
+            (OrderingGuarantee::None, Some(item_identifier)) => {
+                builder.with_delivery_header(item_identifier)
+            }
+            _ => builder,
         }
     }
 
@@ -247,159 +655,258 @@ impl VirtualConnection {
         }
 
         if header.is_heartbeat() {
-            // Heartbeat packets are unreliable, unordered and empty packets.
-            // We already updated our `self.last_heard` time, nothing else to be done.
-            return Ok(());
+            // Heartbeat packets are unreliable, unordered and empty packets, which only carry the
+            // acknowledgments of the packets we sent.
+            let acked_header =
+                packet_reader.read_acknowledge_header(self.acknowledge_handler.window())?;
+            return self.process_acknowledgments(&acked_header, true, sender, time);
         }
 
-        match header.delivery_guarantee() {
-            DeliveryGuarantee::Unreliable => {
-                if let OrderingGuarantee::Sequenced(_id) = header.ordering_guarantee() {
-                    let arranging_header =
-                        packet_reader.read_arranging_header(u16::from(STANDARD_HEADER_SIZE))?;
-
-                    let payload = packet_reader.read_payload();
+        match header.packet_type() {
+            PacketType::Chunk => {
+                let chunk_header = packet_reader.read_chunk_header()?;
+                let payload = packet_reader.read_payload();
+                return self.process_chunk(chunk_header, &payload, sender, time);
+            }
+            PacketType::ChunkAck => {
+                let chunk_ack_header = packet_reader.read_chunk_ack_header()?;
+                if let Some(id) = self.large_message_sender.process_ack(&chunk_ack_header) {
+                    if self.config.acknowledgment_events {
+                        send_event(sender, SocketEvent::Acked(self.remote_address, id))?;
+                    }
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
 
-                    let stream = self
-                        .sequencing_system
-                        .get_or_create_stream(arranging_header.stream_id());
+        let delivery_guarantee = header.delivery_guarantee();
+        let ordering_guarantee = header.ordering_guarantee();
+        // Only reliable packets can be ordered, unreliable packets can only be sequenced. Reliable
+        // unordered packets carry an identifier as well, to drop their duplicates.
+        let is_arranged = match ordering_guarantee {
+            OrderingGuarantee::Sequenced(_) => true,
+            OrderingGuarantee::Ordered(_) | OrderingGuarantee::None => {
+                delivery_guarantee == DeliveryGuarantee::Reliable
+            }
+        };
+        let window = self.acknowledge_handler.window();
+
+        if header.is_fragment() {
+            if let Ok((fragment_header, acked_header)) = packet_reader.read_fragment(window) {
+                let arranging_header = if fragment_header.id() == 0 && is_arranged {
+                    Some(packet_reader.read_arranging_header(
+                        u16::from(STANDARD_HEADER_SIZE + FRAGMENT_HEADER_SIZE)
+                            + u16::from(AckedPacketHeader::size_with_window(window)),
+                    )?)
+                } else {
+                    None
+                };
+                let payload = packet_reader.read_payload();
+
+                if let Some(reassembled) = self.fragmentation.handle_fragment(
+                    fragment_header,
+                    acked_header,
+                    arranging_header,
+                    &payload,
+                    time,
+                )? {
+                    self.deliver_payload(
+                        reassembled.assemble().into_boxed_slice(),
+                        delivery_guarantee,
+                        ordering_guarantee,
+                        reassembled.arranging_header,
+                        sender,
+                    )?;
 
-                    if let Some(packet) = stream.arrange(arranging_header.arranging_id(), payload) {
-                        Self::queue_packet(
-                            sender,
-                            packet,
-                            self.remote_address,
-                            header.delivery_guarantee(),
-                            OrderingGuarantee::Sequenced(Some(arranging_header.stream_id())),
-                        )?;
+                    if let Some(acked_header) = reassembled.acked_header {
+                        self.process_acknowledgments(&acked_header, false, sender, time)?;
                     }
-
-                    return Ok(());
                 }
+            }
+        } else {
+            let acked_header = packet_reader.read_acknowledge_header(window)?;
+            let arranging_header = if is_arranged {
+                Some(packet_reader.read_arranging_header(u16::from(
+                    STANDARD_HEADER_SIZE + AckedPacketHeader::size_with_window(window),
+                ))?)
+            } else {
+                None
+            };
+
+            self.deliver_payload(
+                packet_reader.read_payload(),
+                delivery_guarantee,
+                ordering_guarantee,
+                arranging_header,
+                sender,
+            )?;
+            self.process_acknowledgments(&acked_header, false, sender, time)?;
+        }
+
+        Ok(())
+    }
 
-                Self::queue_packet(
-                    sender,
-                    packet_reader.read_payload(),
+    // Places a chunk of a large message, and hands the message to the application once it is
+    // complete. The progress is reported while the message arrives.
+    fn process_chunk(
+        &mut self,
+        chunk_header: ChunkHeader,
+        payload: &[u8],
+        sender: &Sender<SocketEvent>,
+        time: Instant,
+    ) -> Result<()> {
+        match self
+            .large_message_receiver
+            .process_chunk(chunk_header, payload, time)?
+        {
+            Some(ReceivedChunk::Progress {
+                message_id,
+                received_bytes,
+                total_bytes,
+            }) => send_event(
+                sender,
+                SocketEvent::Progress(
                     self.remote_address,
-                    header.delivery_guarantee(),
-                    header.ordering_guarantee(),
-                )?;
+                    MessageProgress {
+                        message_id,
+                        received_bytes,
+                        total_bytes,
+                    },
+                ),
+            ),
+            Some(ReceivedChunk::Complete(message)) => Self::queue_packet(
+                sender,
+                message,
+                self.remote_address,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // Hands a complete payload to the application. Packets with an arranging header are first
+    // arranged on their stream, which may hold them back or release earlier packets along with them.
+    fn deliver_payload(
+        &mut self,
+        payload: Box<[u8]>,
+        delivery_guarantee: DeliveryGuarantee,
+        ordering_guarantee: OrderingGuarantee,
+        arranging_header: Option<ArrangingHeader>,
+        sender: &Sender<SocketEvent>,
+    ) -> Result<()> {
+        match (ordering_guarantee, arranging_header) {
+            (OrderingGuarantee::Sequenced(_), Some(arranging_header)) => {
+                let stream = self
+                    .sequencing_system
+                    .get_or_create_stream(arranging_header.stream_id());
+
+                if let Some(packet) = stream.arrange(arranging_header.arranging_id(), payload) {
+                    Self::queue_packet(
+                        sender,
+                        packet,
+                        self.remote_address,
+                        delivery_guarantee,
+                        OrderingGuarantee::Sequenced(Some(arranging_header.stream_id())),
+                    )?;
+                }
             }
-            DeliveryGuarantee::Reliable => {
-                if header.is_fragment() {
-                    if let Ok((fragment_header, acked_header)) = packet_reader.read_fragment() {
-                        let payload = packet_reader.read_payload();
-
-                        match self
-                            .fragmentation
-                            .handle_fragment(fragment_header, &payload)
-                        {
-                            Ok(Some(payload)) => {
-                                Self::queue_packet(
-                                    sender,
-                                    payload.into_boxed_slice(),
-                                    self.remote_address,
-                                    header.delivery_guarantee(),
-                                    OrderingGuarantee::None,
-                                )?;
-                            }
-                            Ok(None) => return Ok(()),
-                            Err(e) => return Err(e),
-                        };
-
-                        if let Some(acked_header) = acked_header {
-                            self.congestion_handler
-                                .process_incoming(acked_header.sequence());
-                            self.acknowledge_handler.process_incoming(
-                                acked_header.sequence(),
-                                acked_header.ack_seq(),
-                                acked_header.ack_field(),
-                            );
-                        }
-                    }
-                } else {
-                    let acked_header = packet_reader.read_acknowledge_header()?;
-
-                    if let OrderingGuarantee::Sequenced(_) = header.ordering_guarantee() {
-                        let arranging_header = packet_reader.read_arranging_header(u16::from(
-                            STANDARD_HEADER_SIZE + ACKED_PACKET_HEADER,
-                        ))?;
-
-                        let payload = packet_reader.read_payload();
-
-                        let stream = self
-                            .sequencing_system
-                            .get_or_create_stream(arranging_header.stream_id());
-
-                        if let Some(packet) =
-                            stream.arrange(arranging_header.arranging_id(), payload)
-                        {
-                            Self::queue_packet(
-                                sender,
-                                packet,
-                                self.remote_address,
-                                header.delivery_guarantee(),
-                                OrderingGuarantee::Sequenced(Some(arranging_header.stream_id())),
-                            )?;
-                        }
-                    } else if let OrderingGuarantee::Ordered(_id) = header.ordering_guarantee() {
-                        let arranging_header = packet_reader.read_arranging_header(u16::from(
-                            STANDARD_HEADER_SIZE + ACKED_PACKET_HEADER,
-                        ))?;
-
-                        let payload = packet_reader.read_payload();
-
-                        let stream = self
-                            .ordering_system
-                            .get_or_create_stream(arranging_header.stream_id());
-
-                        if let Some(packet) =
-                            stream.arrange(arranging_header.arranging_id(), payload)
-                        {
-                            Self::queue_packet(
-                                sender,
-                                packet,
-                                self.remote_address,
-                                header.delivery_guarantee(),
-                                OrderingGuarantee::Ordered(Some(arranging_header.stream_id())),
-                            )?;
-
-                            while let Some(packet) = stream.iter_mut().next() {
-                                Self::queue_packet(
-                                    sender,
-                                    packet,
-                                    self.remote_address,
-                                    header.delivery_guarantee(),
-                                    OrderingGuarantee::Ordered(Some(arranging_header.stream_id())),
-                                )?;
-                            }
-                        }
-                    } else {
-                        let payload = packet_reader.read_payload();
+            (OrderingGuarantee::Ordered(_), Some(arranging_header)) => {
+                let stream = self
+                    .ordering_system
+                    .get_or_create_stream(arranging_header.stream_id());
+
+                if let Some(packet) = stream.arrange(arranging_header.arranging_id(), payload) {
+                    Self::queue_packet(
+                        sender,
+                        packet,
+                        self.remote_address,
+                        delivery_guarantee,
+                        OrderingGuarantee::Ordered(Some(arranging_header.stream_id())),
+                    )?;
 
+                    while let Some(packet) = stream.iter_mut().next() {
                         Self::queue_packet(
                             sender,
-                            payload,
+                            packet,
                             self.remote_address,
-                            header.delivery_guarantee(),
-                            header.ordering_guarantee(),
+                            delivery_guarantee,
+                            OrderingGuarantee::Ordered(Some(arranging_header.stream_id())),
                         )?;
                     }
-
-                    self.congestion_handler
-                        .process_incoming(acked_header.sequence());
-                    self.acknowledge_handler.process_incoming(
-                        acked_header.sequence(),
-                        acked_header.ack_seq(),
-                        acked_header.ack_field(),
-                    );
                 }
             }
+            (OrderingGuarantee::None, Some(arranging_header)) => {
+                // A packet is resent when its acknowledgment was lost, so it may arrive twice.
+                // Identifiers which are too old to be remembered are considered duplicates as well.
+                let arranging_id = arranging_header.arranging_id();
+                let is_duplicate = self.delivered_packets.exists(arranging_id)
+                    || self.delivered_packets.insert(arranging_id, ()).is_none();
+
+                if !is_duplicate {
+                    Self::queue_packet(
+                        sender,
+                        payload,
+                        self.remote_address,
+                        delivery_guarantee,
+                        ordering_guarantee,
+                    )?;
+                }
+            }
+            _ => Self::queue_packet(
+                sender,
+                payload,
+                self.remote_address,
+                delivery_guarantee,
+                ordering_guarantee,
+            )?,
         }
 
         Ok(())
     }
 
+    // Processes the acknowledgment information of a received packet, and notifies the application of
+    // the packets which were acknowledged by it. The packet itself is acknowledged by the next
+    // packet we send, unless it is a heartbeat.
+    fn process_acknowledgments(
+        &mut self,
+        acked_header: &AckedPacketHeader,
+        is_heartbeat: bool,
+        sender: &Sender<SocketEvent>,
+        time: Instant,
+    ) -> Result<()> {
+        self.congestion_handler
+            .process_acknowledgment(acked_header.ack_seq(), time);
+        self.report_congestion_quality(sender)?;
+        let in_flight_bytes = self.acknowledge_handler.in_flight_bytes();
+        let acked = if is_heartbeat {
+            self.acknowledge_handler
+                .process_acks(acked_header.ack_seq(), acked_header.ack_field())
+        } else {
+            self.ack_required = true;
+            self.acknowledge_handler.process_incoming(
+                acked_header.sequence(),
+                acked_header.ack_seq(),
+                acked_header.ack_field(),
+            )
+        };
+        self.traffic.packets_acked += acked.len() as u64;
+        self.traffic.bytes_acked +=
+            (in_flight_bytes - self.acknowledge_handler.in_flight_bytes()) as u64;
+        for _ in 0..acked.len() {
+            self.quality_monitor.process_ack();
+        }
+        self.report_quality(sender)?;
+
+        if self.config.acknowledgment_events {
+            for id in acked {
+                send_event(sender, SocketEvent::Acked(self.remote_address, id))?;
+            }
+        }
+        Ok(())
+    }
+
     fn queue_packet(
         tx: &Sender<SocketEvent>,
         payload: Box<[u8]>,
@@ -407,21 +914,175 @@ impl VirtualConnection {
         delivery: DeliveryGuarantee,
         ordering: OrderingGuarantee,
     ) -> Result<()> {
-        tx.send(SocketEvent::Packet(Packet::new(
-            remote_addr,
-            payload,
-            delivery,
-            ordering,
-        )))?;
-        Ok(())
+        send_event(
+            tx,
+            SocketEvent::Packet(Packet::new(remote_addr, payload, delivery, ordering)),
+        )
     }
 
     /// This will gather dropped packets from the acknowledgment handler.
     ///
     /// Note that after requesting dropped packets the dropped packets will be removed from this client.
-    pub fn gather_dropped_packets(&mut self) -> Vec<SentPacket> {
-        self.acknowledge_handler.dropped_packets()
+    /// Only the packets that should be resent are returned, the others are reported as lost.
+    pub fn gather_dropped_packets(
+        &mut self,
+        time: Instant,
+        sender: &Sender<SocketEvent>,
+    ) -> Result<Vec<SentPacket>> {
+        let dropped = self.acknowledge_handler.dropped_packets();
+        self.process_losses(dropped.len(), time, sender)?;
+        self.discard_lost_packets(dropped, time, sender)
+    }
+
+    /// This will gather the packets which have not been acknowledged within the retransmission timeout.
+    ///
+    /// Note that after requesting expired packets the expired packets will be removed from this client.
+    /// Only the packets that should be resent are returned, the others are reported as lost.
+    pub fn gather_expired_packets(
+        &mut self,
+        time: Instant,
+        sender: &Sender<SocketEvent>,
+    ) -> Result<Vec<SentPacket>> {
+        let expired = self
+            .acknowledge_handler
+            .expired_packets(time, self.congestion_handler.retransmission_timeout());
+        self.process_losses(expired.len(), time, sender)?;
+        self.discard_lost_packets(expired, time, sender)
     }
+
+    // Counts the given number of lost packets and hands them to the congestion controller and the
+    // quality monitor.
+    fn process_losses(
+        &mut self,
+        count: usize,
+        time: Instant,
+        sender: &Sender<SocketEvent>,
+    ) -> Result<()> {
+        self.traffic.packets_lost += count as u64;
+        for _ in 0..count {
+            self.congestion_handler.process_loss(time);
+            self.quality_monitor.process_loss();
+        }
+        self.report_congestion_quality(sender)?;
+        self.report_quality(sender)
+    }
+
+    // Notifies the application when the round trip time or packet loss of the connection crossed
+    // their thresholds.
+    fn report_quality(&mut self, sender: &Sender<SocketEvent>) -> Result<()> {
+        let smoothed_rtt = self.congestion_handler.rtt_measurer().smoothed_rtt();
+        if let Some(quality) = self.quality_monitor.update(smoothed_rtt) {
+            send_event(
+                sender,
+                SocketEvent::QualityChanged(self.remote_address, quality),
+            )?;
+        }
+        Ok(())
+    }
+
+    // Notifies the application when the congestion controller changed its mode.
+    fn report_congestion_quality(&mut self, sender: &Sender<SocketEvent>) -> Result<()> {
+        let quality = self.congestion_handler.quality();
+        if quality != self.congestion_quality {
+            self.congestion_quality = quality;
+            send_event(
+                sender,
+                SocketEvent::CongestionChanged(self.remote_address, quality),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Stops resending the packet which was processed last once `deadline` has passed.
+    pub fn expire_last_packet_at(&mut self, deadline: Instant) {
+        self.acknowledge_handler.record_deadline(deadline);
+    }
+
+    /// This will pre-process packets that need to be resent, they will be sent under a new sequence number.
+    ///
+    /// Only the packets which were processed successfully are counted as resent.
+    pub fn process_resends<'a>(
+        &mut self,
+        packets: &'a [SentPacket],
+        time: Instant,
+    ) -> Result<Vec<Outgoing<'a>>> {
+        let mut resends = Vec::with_capacity(packets.len());
+
+        for waiting_packet in packets {
+            let outgoing = self.process_outgoing(
+                waiting_packet.id,
+                &waiting_packet.payload,
+                // Because a delivery guarantee is only sent with reliable packets
+                DeliveryGuarantee::Reliable,
+                // This is stored with the dropped packet because they could be mixed
+                waiting_packet.ordering_guarantee,
+                waiting_packet.item_identifier,
+                time,
+            )?;
+            self.acknowledge_handler
+                .record_resend(waiting_packet.resend_count + 1);
+            self.traffic.packets_resent += 1;
+            self.traffic.bytes_resent += waiting_packet.payload.len() as u64;
+            if let Some(deadline) = waiting_packet.deadline {
+                self.acknowledge_handler.record_deadline(deadline);
+            }
+            resends.push(outgoing);
+        }
+
+        Ok(resends)
+    }
+
+    // Removes the unreliable packets, which are never resent, and the packets which have been resent
+    // the maximal allowed number of times. The removed packets are reported as lost. Packets whose
+    // deadline has passed are removed as well and reported as expired.
+    fn discard_lost_packets(
+        &self,
+        packets: Vec<SentPacket>,
+        time: Instant,
+        sender: &Sender<SocketEvent>,
+    ) -> Result<Vec<SentPacket>> {
+        let mut resends = Vec::with_capacity(packets.len());
+
+        for packet in packets {
+            let is_expired = matches!(packet.deadline, Some(deadline) if deadline <= time);
+
+            if packet.delivery_guarantee == DeliveryGuarantee::Reliable && is_expired {
+                send_event(sender, SocketEvent::Expired(self.remote_address, packet.id))?;
+                continue;
+            }
+
+            let is_exhausted = match self.config.max_packet_resends {
+                Some(max_resends) => packet.resend_count >= max_resends,
+                None => false,
+            };
+
+            if packet.delivery_guarantee == DeliveryGuarantee::Reliable && !is_exhausted {
+                resends.push(packet);
+                continue;
+            }
+
+            if is_exhausted {
+                warn!(
+                    "Giving up on packet to {} after {} resends.",
+                    self.remote_address, packet.resend_count
+                );
+            }
+            if self.config.acknowledgment_events {
+                send_event(sender, SocketEvent::Lost(self.remote_address, packet.id))?;
+            }
+        }
+
+        Ok(resends)
+    }
+}
+
+/// The state of the handshake of a [VirtualConnection].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// We are waiting for the connection handshake to complete.
+    Connecting,
+    /// The connection handshake has completed.
+    Connected,
 }
 
 impl fmt::Debug for VirtualConnection {
@@ -439,15 +1100,18 @@ impl fmt::Debug for VirtualConnection {
 mod tests {
     use super::VirtualConnection;
     use crate::config::Config;
+    use crate::infrastructure::SentPacket;
     use crate::net::constants;
     use crate::packet::header::{AckedPacketHeader, ArrangingHeader, HeaderWriter, StandardHeader};
-    use crate::packet::{DeliveryGuarantee, OrderingGuarantee, Outgoing, Packet, PacketType};
+    use crate::packet::{
+        DeliveryGuarantee, OrderingGuarantee, Outgoing, Packet, PacketId, PacketType,
+    };
     use crate::protocol_version::ProtocolVersion;
     use crate::SocketEvent;
     use byteorder::{BigEndian, WriteBytesExt};
     use crossbeam_channel::{unbounded, TryRecvError};
     use std::io::Write;
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
     const PAYLOAD: [u8; 4] = [1, 2, 3, 4];
 
@@ -461,9 +1125,10 @@ mod tests {
         let standard_header = [protocol_version, vec![1, 1, 2]].concat();
 
         let acked_header = vec![1, 0, 0, 2, 0, 0, 0, 3];
-        let first_fragment = vec![0, 1, 1, 3];
-        let second_fragment = vec![0, 1, 2, 3];
-        let third_fragment = vec![0, 1, 3, 3];
+        // The first fragment carries the acknowledgment and the ordering header.
+        let first_fragment = vec![0, 1, 0, 3, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let second_fragment = vec![0, 1, 1, 3];
+        let third_fragment = vec![0, 1, 2, 3];
 
         let (tx, rx) = unbounded::<SocketEvent>();
 
@@ -533,6 +1198,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn fragmented_packets_are_ordered_on_their_stream() {
+        let mut connection = create_virtual_connection();
+        let mut other = create_virtual_connection();
+        let large_payload = vec![1; 4000];
+        let time = Instant::now();
+
+        let mut datagrams = Vec::new();
+        for payload in [large_payload.as_slice(), &[2; 10]].iter() {
+            match other
+                .process_outgoing(
+                    PacketId(0),
+                    payload,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::Ordered(Some(1)),
+                    None,
+                    time,
+                )
+                .unwrap()
+            {
+                Outgoing::Packet(packet) => datagrams.push(packet.contents()),
+                Outgoing::Fragments(fragments) => {
+                    datagrams.extend(fragments.iter().map(|fragment| fragment.contents()))
+                }
+            }
+        }
+
+        // The small packet arrives first, it waits for the large packet which was sent before it.
+        let (tx, rx) = unbounded::<SocketEvent>();
+        for datagram in datagrams.iter().rev() {
+            connection.process_incoming(datagram, &tx, time).unwrap();
+        }
+
+        let payloads: Vec<_> = rx
+            .try_iter()
+            .map(|event| match event {
+                SocketEvent::Packet(packet) => packet.payload().to_vec(),
+                _ => panic!("Expected a packet"),
+            })
+            .collect();
+        assert_eq!(payloads, vec![large_payload, vec![2; 10]]);
+    }
+
+    #[test]
+    fn fragments_arriving_out_of_order_are_reassembled_and_acknowledged() {
+        let mut connection = create_virtual_connection();
+        let mut other = create_virtual_connection();
+        let payload: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+        let time = Instant::now();
+
+        let fragments = match other
+            .process_outgoing(
+                PacketId(0),
+                &payload,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+                time,
+            )
+            .unwrap()
+        {
+            Outgoing::Packet(_) => panic!("Expected fragments got packet"),
+            Outgoing::Fragments(fragments) => fragments,
+        };
+
+        let (tx, rx) = unbounded::<SocketEvent>();
+        for fragment in fragments.iter().rev() {
+            assert!(rx.try_recv().is_err());
+            connection
+                .process_incoming(&fragment.contents(), &tx, time)
+                .unwrap();
+        }
+
+        match rx.try_recv().unwrap() {
+            SocketEvent::Packet(packet) => assert_eq!(packet.payload(), payload.as_slice()),
+            _ => panic!("Expected the reassembled packet"),
+        }
+        // The acknowledgment header of the first fragment is processed once the packet is complete.
+        assert_eq!(connection.acknowledge_handler.remote_sequence_num(), 0);
+    }
+
     #[test]
     fn expect_fragmentation() {
         let mut connection = create_virtual_connection();
@@ -541,6 +1287,7 @@ mod tests {
 
         let outgoing = connection
             .process_outgoing(
+                PacketId(0),
                 &buffer,
                 DeliveryGuarantee::Reliable,
                 OrderingGuarantee::Ordered(None),
@@ -565,6 +1312,7 @@ mod tests {
 
         connection
             .process_outgoing(
+                PacketId(0),
                 &buffer,
                 DeliveryGuarantee::Unreliable,
                 OrderingGuarantee::None,
@@ -575,6 +1323,7 @@ mod tests {
 
         connection
             .process_outgoing(
+                PacketId(0),
                 &buffer,
                 DeliveryGuarantee::Unreliable,
                 OrderingGuarantee::Sequenced(None),
@@ -585,6 +1334,7 @@ mod tests {
 
         connection
             .process_outgoing(
+                PacketId(0),
                 &buffer,
                 DeliveryGuarantee::Reliable,
                 OrderingGuarantee::Ordered(None),
@@ -595,6 +1345,7 @@ mod tests {
 
         connection
             .process_outgoing(
+                PacketId(0),
                 &buffer,
                 DeliveryGuarantee::Reliable,
                 OrderingGuarantee::Sequenced(None),
@@ -710,6 +1461,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn failed_resend_is_not_counted() {
+        let mut connection = create_virtual_connection();
+        let packets = [SentPacket {
+            id: PacketId(0),
+            payload: vec![0; Config::default().max_packet_size + 1].into_boxed_slice(),
+            delivery_guarantee: DeliveryGuarantee::Reliable,
+            ordering_guarantee: OrderingGuarantee::None,
+            item_identifier: None,
+            sent_time: None,
+            resend_count: 0,
+            deadline: None,
+        }];
+
+        assert!(connection
+            .process_resends(&packets, Instant::now())
+            .is_err());
+        assert_eq!(connection.stats().packets_resent, 0);
+    }
+
+    #[test]
+    fn resent_reliable_unordered_packet_is_delivered_once() {
+        let mut connection = create_virtual_connection();
+        let (tx, rx) = unbounded::<SocketEvent>();
+
+        // a resend carries a new sequence number, but the same delivery identifier.
+        for sequence in 1..3 {
+            let mut packet = Vec::new();
+            StandardHeader::new(
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                PacketType::Packet,
+            )
+            .parse(&mut packet)
+            .unwrap();
+            AckedPacketHeader::new(sequence, 0, 0)
+                .parse(&mut packet)
+                .unwrap();
+            ArrangingHeader::new(7, constants::DEFAULT_DELIVERY_STREAM)
+                .parse(&mut packet)
+                .unwrap();
+            packet.write_all(&PAYLOAD).unwrap();
+
+            connection
+                .process_incoming(packet.as_slice(), &tx, Instant::now())
+                .unwrap();
+        }
+
+        assert_eq!(
+            rx.try_recv(),
+            Ok(SocketEvent::Packet(Packet::reliable_unordered(
+                get_fake_addr(),
+                PAYLOAD.to_vec(),
+            )))
+        );
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
     #[test]
     fn assure_correct_processing_of_incoming() {
         let mut connection = create_virtual_connection();
@@ -759,17 +1568,21 @@ mod tests {
         assert_right_header_size(
             DeliveryGuarantee::Unreliable,
             OrderingGuarantee::None,
-            (constants::STANDARD_HEADER_SIZE) as usize,
+            (constants::STANDARD_HEADER_SIZE + constants::ACKED_PACKET_HEADER) as usize,
         );
         assert_right_header_size(
             DeliveryGuarantee::Unreliable,
             OrderingGuarantee::Sequenced(None),
-            (constants::STANDARD_HEADER_SIZE + constants::ARRANGING_PACKET_HEADER) as usize,
+            (constants::STANDARD_HEADER_SIZE
+                + constants::ACKED_PACKET_HEADER
+                + constants::ARRANGING_PACKET_HEADER) as usize,
         );
         assert_right_header_size(
             DeliveryGuarantee::Reliable,
             OrderingGuarantee::None,
-            (constants::STANDARD_HEADER_SIZE + constants::ACKED_PACKET_HEADER) as usize,
+            (constants::STANDARD_HEADER_SIZE
+                + constants::ACKED_PACKET_HEADER
+                + constants::ARRANGING_PACKET_HEADER) as usize,
         );
         assert_right_header_size(
             DeliveryGuarantee::Reliable,
@@ -781,6 +1594,49 @@ mod tests {
     }
 
     /// ======= helper functions =========
+    #[test]
+    fn next_timer_is_earliest_timer() {
+        let time = Instant::now();
+        let config = Config {
+            heartbeat_interval: Some(Duration::from_secs(1)),
+            ..Config::default()
+        };
+        let mut connection = VirtualConnection::new(get_fake_addr(), &config, time);
+
+        // The first handshake packet is due right away.
+        assert_eq!(connection.next_timer(time), time);
+
+        connection.create_handshake_packet(config.handshake_interval, time);
+        assert_eq!(
+            connection.next_timer(time),
+            time + config.handshake_interval
+        );
+
+        connection.establish();
+        assert_eq!(connection.next_timer(time), time + Duration::from_secs(1));
+
+        let outgoing = connection
+            .process_outgoing(
+                PacketId(0),
+                PAYLOAD.as_ref(),
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                None,
+                time,
+            )
+            .unwrap();
+        // The retransmission timeout only runs once the packet leaves the pacer.
+        assert_eq!(connection.next_timer(time), time + Duration::from_secs(1));
+
+        if let Outgoing::Packet(packet) = outgoing {
+            connection.pace_packet(packet.contents(), packet.sequence(), time);
+        }
+        assert_eq!(
+            connection.next_timer(time),
+            time + connection.congestion_handler.retransmission_timeout()
+        );
+    }
+
     fn create_virtual_connection() -> VirtualConnection {
         VirtualConnection::new(get_fake_addr(), &Config::default(), Instant::now())
     }
@@ -804,10 +1660,8 @@ mod tests {
         header.parse(&mut packet).unwrap();
 
         if let OrderingGuarantee::Sequenced(val) = ordering {
-            if delivery == DeliveryGuarantee::Reliable {
-                let ack_header = AckedPacketHeader::new(1, 2, 3);
-                ack_header.parse(&mut packet).unwrap();
-            }
+            let ack_header = AckedPacketHeader::new(1, 2, 3);
+            ack_header.parse(&mut packet).unwrap();
 
             let order_header = ArrangingHeader::new(order_id, val.unwrap());
             order_header.parse(&mut packet).unwrap();
@@ -857,9 +1711,14 @@ mod tests {
         let header = StandardHeader::new(delivery, OrderingGuarantee::None, PacketType::Packet);
         header.parse(&mut packet).unwrap();
 
+        let ack_header = AckedPacketHeader::new(1, 2, 3);
+        ack_header.parse(&mut packet).unwrap();
+
+        // reliable packets are identified to drop their duplicates.
         if delivery == DeliveryGuarantee::Reliable {
-            let ack_header = AckedPacketHeader::new(1, 2, 3);
-            ack_header.parse(&mut packet).unwrap();
+            ArrangingHeader::new(1, constants::DEFAULT_DELIVERY_STREAM)
+                .parse(&mut packet)
+                .unwrap();
         }
 
         packet.write_all(&PAYLOAD).unwrap();
@@ -886,7 +1745,14 @@ mod tests {
         let buffer = vec![1; 500];
 
         let outgoing = connection
-            .process_outgoing(&buffer, delivery, ordering, None, Instant::now())
+            .process_outgoing(
+                PacketId(0),
+                &buffer,
+                delivery,
+                ordering,
+                None,
+                Instant::now(),
+            )
             .unwrap();
 
         match outgoing {
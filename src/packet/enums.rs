@@ -90,6 +90,24 @@ pub enum PacketType {
     Fragment = 1,
     /// Heartbeat packet
     Heartbeat = 2,
+    /// Request to establish a connection
+    ConnectionRequest = 3,
+    /// Challenge the remote endpoint has to answer before a connection is established
+    ConnectionChallenge = 4,
+    /// Answer to a connection challenge
+    ChallengeResponse = 5,
+    /// The connection request was accepted
+    ConnectionAccepted = 6,
+    /// The connection request was denied
+    ConnectionDenied = 7,
+    /// The remote endpoint closed the connection
+    Disconnect = 8,
+    /// Packet of another type, encrypted with the keys of the connection
+    Encrypted = 9,
+    /// Chunk of a large message
+    Chunk = 10,
+    /// Acknowledgment of the received chunks of a large message
+    ChunkAck = 11,
 }
 
 impl EnumConverter for PacketType {
@@ -107,6 +125,15 @@ impl TryFrom<u8> for PacketType {
             0 => Ok(PacketType::Packet),
             1 => Ok(PacketType::Fragment),
             2 => Ok(PacketType::Heartbeat),
+            3 => Ok(PacketType::ConnectionRequest),
+            4 => Ok(PacketType::ConnectionChallenge),
+            5 => Ok(PacketType::ChallengeResponse),
+            6 => Ok(PacketType::ConnectionAccepted),
+            7 => Ok(PacketType::ConnectionDenied),
+            8 => Ok(PacketType::Disconnect),
+            9 => Ok(PacketType::Encrypted),
+            10 => Ok(PacketType::Chunk),
+            11 => Ok(PacketType::ChunkAck),
             _ => Err(ErrorKind::DecodingError(DecodingErrorKind::PacketType)),
         }
     }
@@ -171,5 +198,22 @@ mod tests {
             PacketType::Heartbeat,
             PacketType::try_from(heartbeat.to_u8()).unwrap()
         );
+
+        for packet_type in &[
+            PacketType::ConnectionRequest,
+            PacketType::ConnectionChallenge,
+            PacketType::ChallengeResponse,
+            PacketType::ConnectionAccepted,
+            PacketType::ConnectionDenied,
+            PacketType::Disconnect,
+            PacketType::Encrypted,
+            PacketType::Chunk,
+            PacketType::ChunkAck,
+        ] {
+            assert_eq!(
+                *packet_type,
+                PacketType::try_from(packet_type.to_u8()).unwrap()
+            );
+        }
     }
 }
@@ -1,15 +1,19 @@
 //! This module provides parses and readers for the headers that could be appended to any packet.
-//! We use headers to control reliability, fragmentation, and ordering.
+//! We use headers to control reliability, fragmentation, large messages, and ordering.
 
 mod acked_packet_header;
 mod arranging_header;
+mod chunk_ack_header;
+mod chunk_header;
 mod fragment_header;
 mod header_reader;
 mod header_writer;
 mod standard_header;
 
-pub use self::acked_packet_header::AckedPacketHeader;
+pub use self::acked_packet_header::{AckWindow, AckedPacketHeader};
 pub use self::arranging_header::ArrangingHeader;
+pub use self::chunk_ack_header::{ChunkAckHeader, CHUNK_ACK_FIELD_SIZE};
+pub use self::chunk_header::ChunkHeader;
 pub use self::fragment_header::FragmentHeader;
 pub use self::header_reader::HeaderReader;
 pub use self::header_writer::HeaderWriter;
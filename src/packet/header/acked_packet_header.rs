@@ -4,6 +4,57 @@ use crate::net::constants::ACKED_PACKET_HEADER;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::Cursor;
 
+/// The number of packets before the last acknowledged one that an acknowledgment header covers.
+///
+/// The window is negotiated per connection during the handshake and determines the size of the
+/// bitfield in the `AckedPacketHeader`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AckWindow {
+    /// Acknowledges the last 32 packets with a 4 byte bitfield.
+    #[default]
+    Bits32,
+    /// Acknowledges the last 64 packets with an 8 byte bitfield.
+    Bits64,
+    /// Acknowledges the last 128 packets with a 16 byte bitfield.
+    Bits128,
+}
+
+impl AckWindow {
+    /// Returns the smallest window which covers at least `packets` packets, or the largest window
+    /// if none does.
+    pub fn from_packets(packets: u16) -> AckWindow {
+        match packets {
+            0..=32 => AckWindow::Bits32,
+            33..=64 => AckWindow::Bits64,
+            _ => AckWindow::Bits128,
+        }
+    }
+
+    /// Returns the window whose bitfield is `field_size` bytes large, if there is one.
+    pub fn from_field_size(field_size: u8) -> Option<AckWindow> {
+        match field_size {
+            4 => Some(AckWindow::Bits32),
+            8 => Some(AckWindow::Bits64),
+            16 => Some(AckWindow::Bits128),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of packets covered by this window.
+    pub fn packets(self) -> u16 {
+        u16::from(self.field_size()) * 8
+    }
+
+    /// Returns the size in bytes of the bitfield of this window.
+    pub fn field_size(self) -> u8 {
+        match self {
+            AckWindow::Bits32 => 4,
+            AckWindow::Bits64 => 8,
+            AckWindow::Bits128 => 16,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 /// This header providing reliability information.
 pub struct AckedPacketHeader {
@@ -11,30 +62,64 @@ pub struct AckedPacketHeader {
     pub seq: u16,
     // this is the last acknowledged sequence number.
     ack_seq: u16,
-    // this is an bitfield of all last 32 acknowledged packages
-    ack_field: u32,
+    // this is an bitfield of the acknowledged packages within the window
+    ack_field: u128,
+    // this is the window that determines how many bits of the bitfield are written
+    window: AckWindow,
 }
 
 impl AckedPacketHeader {
     /// When we compose packet headers, the local sequence becomes the sequence number of the packet, and the remote sequence becomes the ack.
     /// The ack bitfield is calculated by looking into a queue of up to 33 packets, containing sequence numbers in the range [remote sequence - 32, remote sequence].
     /// We set bit n (in [1,32]) in ack bits to 1 if the sequence number remote sequence - n is in the received queue.
+    #[allow(dead_code)]
     pub fn new(seq_num: u16, last_seq: u16, bit_field: u32) -> AckedPacketHeader {
+        AckedPacketHeader::with_window(seq_num, last_seq, u128::from(bit_field), AckWindow::Bits32)
+    }
+
+    /// Constructs a header whose bitfield covers the given `window`; bits beyond the window are not
+    /// written.
+    pub fn with_window(
+        seq_num: u16,
+        last_seq: u16,
+        bit_field: u128,
+        window: AckWindow,
+    ) -> AckedPacketHeader {
         AckedPacketHeader {
             seq: seq_num,
             ack_seq: last_seq,
             ack_field: bit_field,
+            window,
         }
     }
 
+    /// Reads a header whose bitfield covers the given `window`.
+    pub fn read_with_window(
+        rdr: &mut Cursor<&[u8]>,
+        window: AckWindow,
+    ) -> Result<AckedPacketHeader> {
+        let seq = rdr.read_u16::<BigEndian>()?;
+        let ack_seq = rdr.read_u16::<BigEndian>()?;
+        let ack_field = rdr.read_uint128::<BigEndian>(window.field_size() as usize)?;
+
+        Ok(AckedPacketHeader::with_window(
+            seq, ack_seq, ack_field, window,
+        ))
+    }
+
+    /// Returns the size of a header whose bitfield covers the given `window`.
+    pub fn size_with_window(window: AckWindow) -> u8 {
+        4 + window.field_size()
+    }
+
     /// Get the sequence number from this packet.
     #[allow(dead_code)]
     pub fn sequence(&self) -> u16 {
         self.seq
     }
 
-    /// Get bit field of all last 32 acknowledged packages
-    pub fn ack_field(&self) -> u32 {
+    /// Get bit field of the acknowledged packages within the window
+    pub fn ack_field(&self) -> u128 {
         self.ack_field
     }
 
@@ -50,7 +135,9 @@ impl HeaderWriter for AckedPacketHeader {
     fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output {
         buffer.write_u16::<BigEndian>(self.seq)?;
         buffer.write_u16::<BigEndian>(self.ack_seq)?;
-        buffer.write_u32::<BigEndian>(self.ack_field)?;
+        let field_size = self.window.field_size() as usize;
+        let mask = !0u128 >> (128 - field_size * 8);
+        buffer.write_uint128::<BigEndian>(self.ack_field & mask, field_size)?;
         Ok(())
     }
 }
@@ -59,15 +146,7 @@ impl HeaderReader for AckedPacketHeader {
     type Header = Result<AckedPacketHeader>;
 
     fn read(rdr: &mut Cursor<&[u8]>) -> Self::Header {
-        let seq = rdr.read_u16::<BigEndian>()?;
-        let ack_seq = rdr.read_u16::<BigEndian>()?;
-        let ack_field = rdr.read_u32::<BigEndian>()?;
-
-        Ok(AckedPacketHeader {
-            seq,
-            ack_seq,
-            ack_field,
-        })
+        AckedPacketHeader::read_with_window(rdr, AckWindow::Bits32)
     }
 
     fn size() -> u8 {
@@ -78,7 +157,7 @@ impl HeaderReader for AckedPacketHeader {
 #[cfg(test)]
 mod tests {
     use crate::net::constants::ACKED_PACKET_HEADER;
-    use crate::packet::header::{AckedPacketHeader, HeaderReader, HeaderWriter};
+    use crate::packet::header::{AckWindow, AckedPacketHeader, HeaderReader, HeaderWriter};
     use std::io::Cursor;
 
     #[test]
@@ -110,4 +189,46 @@ mod tests {
     fn size() {
         assert_eq!(AckedPacketHeader::size(), ACKED_PACKET_HEADER);
     }
+
+    #[test]
+    fn serialize_and_deserialize_larger_windows() {
+        for &window in &[AckWindow::Bits64, AckWindow::Bits128] {
+            let bit_field = 1 | 1 << (window.packets() - 1);
+            let header = AckedPacketHeader::with_window(1, 2, bit_field, window);
+
+            let mut buffer = Vec::new();
+            header.parse(&mut buffer).unwrap();
+            assert_eq!(
+                buffer.len() as u8,
+                AckedPacketHeader::size_with_window(window)
+            );
+
+            let mut cursor = Cursor::new(buffer.as_slice());
+            let header = AckedPacketHeader::read_with_window(&mut cursor, window).unwrap();
+            assert_eq!(header.sequence(), 1);
+            assert_eq!(header.ack_seq(), 2);
+            assert_eq!(header.ack_field(), bit_field);
+        }
+    }
+
+    #[test]
+    fn bits_beyond_the_window_are_not_written() {
+        let header = AckedPacketHeader::with_window(1, 2, !0, AckWindow::Bits64);
+
+        let mut buffer = Vec::new();
+        header.parse(&mut buffer).unwrap();
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let header = AckedPacketHeader::read_with_window(&mut cursor, AckWindow::Bits64).unwrap();
+        assert_eq!(header.ack_field(), u128::from(u64::MAX));
+    }
+
+    #[test]
+    fn window_rounds_up_to_supported_size() {
+        assert_eq!(AckWindow::from_packets(1), AckWindow::Bits32);
+        assert_eq!(AckWindow::from_packets(32), AckWindow::Bits32);
+        assert_eq!(AckWindow::from_packets(33), AckWindow::Bits64);
+        assert_eq!(AckWindow::from_packets(100), AckWindow::Bits128);
+        assert_eq!(AckWindow::from_packets(1000), AckWindow::Bits128);
+    }
 }
@@ -0,0 +1,116 @@
+use super::{HeaderReader, HeaderWriter};
+use crate::error::Result;
+use crate::net::constants::CHUNK_ACK_HEADER_SIZE;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+
+/// The number of chunks after the first missing chunk that a chunk acknowledgment covers.
+pub const CHUNK_ACK_FIELD_SIZE: u32 = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// This header acknowledges the chunks of a large message which were received.
+pub struct ChunkAckHeader {
+    message_id: u16,
+    received_up_to: u32,
+    ack_field: u64,
+}
+
+impl ChunkAckHeader {
+    /// Create a new acknowledgment for the given message.
+    ///
+    /// - `received_up_to` = the number of chunks at the start of the message which were all received.
+    /// - `ack_field` = bit `i` is set if the chunk at `received_up_to + 1 + i` was received.
+    pub fn new(message_id: u16, received_up_to: u32, ack_field: u64) -> Self {
+        ChunkAckHeader {
+            message_id,
+            received_up_to,
+            ack_field,
+        }
+    }
+
+    /// Get the id of the acknowledged message.
+    pub fn message_id(&self) -> u16 {
+        self.message_id
+    }
+
+    /// Get the number of chunks at the start of the message which were all received.
+    pub fn received_up_to(&self) -> u32 {
+        self.received_up_to
+    }
+
+    /// Returns true if the chunk with the given index is acknowledged.
+    pub fn is_acked(&self, index: u32) -> bool {
+        if index < self.received_up_to {
+            return true;
+        }
+        let offset = index - self.received_up_to;
+        (1..=CHUNK_ACK_FIELD_SIZE).contains(&offset) && self.ack_field & (1 << (offset - 1)) != 0
+    }
+}
+
+impl HeaderWriter for ChunkAckHeader {
+    type Output = Result<()>;
+
+    fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output {
+        buffer.write_u16::<BigEndian>(self.message_id)?;
+        buffer.write_u32::<BigEndian>(self.received_up_to)?;
+        buffer.write_u64::<BigEndian>(self.ack_field)?;
+
+        Ok(())
+    }
+}
+
+impl HeaderReader for ChunkAckHeader {
+    type Header = Result<ChunkAckHeader>;
+
+    fn read(rdr: &mut Cursor<&[u8]>) -> Self::Header {
+        let message_id = rdr.read_u16::<BigEndian>()?;
+        let received_up_to = rdr.read_u32::<BigEndian>()?;
+        let ack_field = rdr.read_u64::<BigEndian>()?;
+
+        Ok(ChunkAckHeader {
+            message_id,
+            received_up_to,
+            ack_field,
+        })
+    }
+
+    /// Get the size of this header.
+    fn size() -> u8 {
+        CHUNK_ACK_HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::constants::CHUNK_ACK_HEADER_SIZE;
+    use crate::packet::header::{ChunkAckHeader, HeaderReader, HeaderWriter};
+    use std::io::Cursor;
+
+    #[test]
+    fn serialize_and_deserialize() {
+        let mut buffer = Vec::new();
+        let header = ChunkAckHeader::new(1, 100_000, 0b101);
+        assert![header.parse(&mut buffer).is_ok()];
+        assert_eq!(buffer.len(), CHUNK_ACK_HEADER_SIZE as usize);
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        assert_eq!(ChunkAckHeader::read(&mut cursor).unwrap(), header);
+    }
+
+    #[test]
+    fn acknowledges_chunks_before_and_after_the_first_missing_chunk() {
+        let header = ChunkAckHeader::new(0, 10, 0b101);
+        assert!(header.is_acked(9));
+        assert!(!header.is_acked(10));
+        assert!(header.is_acked(11));
+        assert!(!header.is_acked(12));
+        assert!(header.is_acked(13));
+        assert!(!header.is_acked(100));
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(ChunkAckHeader::size(), CHUNK_ACK_HEADER_SIZE);
+    }
+}
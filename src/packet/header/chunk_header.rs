@@ -0,0 +1,117 @@
+use super::{HeaderReader, HeaderWriter};
+use crate::error::Result;
+use crate::net::constants::CHUNK_HEADER_SIZE;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// This header represents a chunk of a large message.
+pub struct ChunkHeader {
+    message_id: u16,
+    index: u32,
+    message_size: u32,
+    chunk_size: u16,
+}
+
+impl ChunkHeader {
+    /// Create a new header for the chunk with the given index of a large message.
+    pub fn new(message_id: u16, index: u32, message_size: u32, chunk_size: u16) -> Self {
+        ChunkHeader {
+            message_id,
+            index,
+            message_size,
+            chunk_size,
+        }
+    }
+
+    /// Get the id of the message this chunk is part of.
+    pub fn message_id(&self) -> u16 {
+        self.message_id
+    }
+
+    /// Get the index of this chunk in the message.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Get the size of the whole message in bytes.
+    pub fn message_size(&self) -> u32 {
+        self.message_size
+    }
+
+    /// Get the size of every chunk of the message, except for the last one which may be smaller.
+    pub fn chunk_size(&self) -> u16 {
+        self.chunk_size
+    }
+
+    /// Get the number of chunks the message is split into. An empty message is sent as one empty chunk.
+    pub fn chunk_count(&self) -> u32 {
+        let chunk_size = u32::from(self.chunk_size.max(1));
+        self.message_size.div_ceil(chunk_size).max(1)
+    }
+}
+
+impl HeaderWriter for ChunkHeader {
+    type Output = Result<()>;
+
+    fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output {
+        buffer.write_u16::<BigEndian>(self.message_id)?;
+        buffer.write_u32::<BigEndian>(self.index)?;
+        buffer.write_u32::<BigEndian>(self.message_size)?;
+        buffer.write_u16::<BigEndian>(self.chunk_size)?;
+
+        Ok(())
+    }
+}
+
+impl HeaderReader for ChunkHeader {
+    type Header = Result<ChunkHeader>;
+
+    fn read(rdr: &mut Cursor<&[u8]>) -> Self::Header {
+        let message_id = rdr.read_u16::<BigEndian>()?;
+        let index = rdr.read_u32::<BigEndian>()?;
+        let message_size = rdr.read_u32::<BigEndian>()?;
+        let chunk_size = rdr.read_u16::<BigEndian>()?;
+
+        Ok(ChunkHeader {
+            message_id,
+            index,
+            message_size,
+            chunk_size,
+        })
+    }
+
+    /// Get the size of this header.
+    fn size() -> u8 {
+        CHUNK_HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::constants::CHUNK_HEADER_SIZE;
+    use crate::packet::header::{ChunkHeader, HeaderReader, HeaderWriter};
+    use std::io::Cursor;
+
+    #[test]
+    fn serialize_and_deserialize() {
+        let mut buffer = Vec::new();
+        let header = ChunkHeader::new(1, 70_000, 5_000_000, 1024);
+        assert![header.parse(&mut buffer).is_ok()];
+        assert_eq!(buffer.len(), CHUNK_HEADER_SIZE as usize);
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        assert_eq!(ChunkHeader::read(&mut cursor).unwrap(), header);
+    }
+
+    #[test]
+    fn chunk_count_rounds_up() {
+        assert_eq!(ChunkHeader::new(0, 0, 4096, 1024).chunk_count(), 4);
+        assert_eq!(ChunkHeader::new(0, 0, 4097, 1024).chunk_count(), 5);
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(ChunkHeader::size(), CHUNK_HEADER_SIZE);
+    }
+}
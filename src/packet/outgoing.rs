@@ -1,10 +1,11 @@
 use crate::{
-    net::constants::{DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM},
+    net::constants::{DEFAULT_DELIVERY_STREAM, DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM},
     packet::{
         header::{
-            AckedPacketHeader, ArrangingHeader, FragmentHeader, HeaderWriter, StandardHeader,
+            AckWindow, AckedPacketHeader, ArrangingHeader, ChunkAckHeader, ChunkHeader,
+            FragmentHeader, HeaderWriter, StandardHeader,
         },
-        DeliveryGuarantee, OrderingGuarantee, PacketType,
+        DeliveryGuarantee, OrderingGuarantee, PacketType, SequenceNumber,
     },
 };
 
@@ -12,6 +13,7 @@ use crate::{
 pub struct OutgoingPacketBuilder<'p> {
     header: Vec<u8>,
     payload: &'p [u8],
+    sequence: Option<SequenceNumber>,
 }
 
 impl<'p> OutgoingPacketBuilder<'p> {
@@ -20,6 +22,7 @@ impl<'p> OutgoingPacketBuilder<'p> {
         OutgoingPacketBuilder {
             header: Vec::new(),
             payload,
+            sequence: None,
         }
     }
 
@@ -31,6 +34,25 @@ impl<'p> OutgoingPacketBuilder<'p> {
             .parse(&mut self.header)
             .expect("Could not write fragment header to buffer");
 
+        self.sequence = Some(packet_seq);
+        self
+    }
+
+    /// This will add the `ChunkHeader` of a chunk of a large message to the header.
+    pub fn with_chunk_header(mut self, header: ChunkHeader) -> Self {
+        header
+            .parse(&mut self.header)
+            .expect("Could not write chunk header to buffer");
+
+        self
+    }
+
+    /// This will add the `ChunkAckHeader`, which acknowledges the received chunks of a large message, to the header.
+    pub fn with_chunk_ack_header(mut self, header: ChunkAckHeader) -> Self {
+        header
+            .parse(&mut self.header)
+            .expect("Could not write chunk acknowledgment header to buffer");
+
         self
     }
 
@@ -50,17 +72,21 @@ impl<'p> OutgoingPacketBuilder<'p> {
     }
 
     /// This will add the [`AckedPacketHeader`](./headers/acked_packet_header) to the header.
+    ///
+    /// - `window` = the acknowledgment window negotiated for the connection, which determines how many bits of `bit_field` are written.
     pub fn with_acknowledgment_header(
         mut self,
         seq_num: u16,
         last_seq: u16,
-        bit_field: u32,
+        bit_field: u128,
+        window: AckWindow,
     ) -> Self {
-        let header = AckedPacketHeader::new(seq_num, last_seq, bit_field);
+        let header = AckedPacketHeader::with_window(seq_num, last_seq, bit_field, window);
         header
             .parse(&mut self.header)
             .expect("Could not write acknowledgment header to buffer");
 
+        self.sequence = Some(seq_num);
         self
     }
 
@@ -94,11 +120,25 @@ impl<'p> OutgoingPacketBuilder<'p> {
         self
     }
 
+    /// This will add the [`ArrangingHeader`](./headers/arranging_header) of a reliable unordered packet.
+    ///
+    /// - `arranging_id` = identifier for this packet which is kept when it is resent, so the receiver can drop its duplicates.
+    pub fn with_delivery_header(mut self, arranging_id: u16) -> Self {
+        let header = ArrangingHeader::new(arranging_id, DEFAULT_DELIVERY_STREAM);
+
+        header
+            .parse(&mut self.header)
+            .expect("Could not write arranging header to buffer");
+
+        self
+    }
+
     /// This will construct a `OutgoingPacket` from the contents constructed with this builder.
     pub fn build(self) -> OutgoingPacket<'p> {
         OutgoingPacket {
             header: self.header,
             payload: self.payload,
+            sequence: self.sequence,
         }
     }
 }
@@ -107,9 +147,15 @@ impl<'p> OutgoingPacketBuilder<'p> {
 pub struct OutgoingPacket<'p> {
     header: Vec<u8>,
     payload: &'p [u8],
+    sequence: Option<SequenceNumber>,
 }
 
 impl<'p> OutgoingPacket<'p> {
+    /// Returns the sequence number with which this packet is acknowledged, if it carries one.
+    pub fn sequence(&self) -> Option<SequenceNumber> {
+        self.sequence
+    }
+
     /// This will return the contents of this packet; the content includes the header and payload bytes.
     ///
     /// # Remark
@@ -132,6 +178,7 @@ pub enum Outgoing<'a> {
 
 #[cfg(test)]
 mod tests {
+    use crate::packet::header::AckWindow;
     use crate::packet::PacketType;
     use crate::packet::{DeliveryGuarantee, OrderingGuarantee, OutgoingPacketBuilder};
 
@@ -170,7 +217,7 @@ mod tests {
         let payload = test_payload();
 
         let outgoing = OutgoingPacketBuilder::new(&payload)
-            .with_acknowledgment_header(1, 2, 3)
+            .with_acknowledgment_header(1, 2, 3, AckWindow::Bits32)
             .build();
 
         let expected: Vec<u8> = [vec![0, 1, 0, 2, 0, 0, 0, 3], test_payload()]
@@ -1,6 +1,7 @@
 use crate::net::constants::STANDARD_HEADER_SIZE;
 use crate::packet::header::{
-    AckedPacketHeader, ArrangingHeader, FragmentHeader, HeaderReader, StandardHeader,
+    AckWindow, AckedPacketHeader, ArrangingHeader, ChunkAckHeader, ChunkHeader, FragmentHeader,
+    HeaderReader, StandardHeader,
 };
 use crate::{ErrorKind, Result};
 
@@ -57,12 +58,13 @@ impl<'s> PacketReader<'s> {
     ///
     /// # Remark
     /// - Will change the position to the location of `AckedPacketHeader`
-    pub fn read_acknowledge_header(&mut self) -> Result<AckedPacketHeader> {
+    /// - The size of the header depends on the acknowledgment `window` of the connection.
+    pub fn read_acknowledge_header(&mut self, window: AckWindow) -> Result<AckedPacketHeader> {
         // acknowledge header comes after standard header.
         self.cursor.set_position(u64::from(STANDARD_HEADER_SIZE));
 
-        if self.can_read(AckedPacketHeader::size()) {
-            AckedPacketHeader::read(&mut self.cursor)
+        if self.can_read(AckedPacketHeader::size_with_window(window)) {
+            AckedPacketHeader::read_with_window(&mut self.cursor, window)
         } else {
             Err(ErrorKind::CouldNotReadHeader(String::from(
                 "acknowledgment",
@@ -77,12 +79,18 @@ impl<'s> PacketReader<'s> {
     /// e.g. when reading `StandardHeader` the position of the underlying `Cursor` will be at the end where it left of,
     /// when calling this function afterward it will read the `FragmentHeader` from there on.
     /// - Note that only the first fragment of a sequence contains acknowledgment information that's why `AckedPacketHeader` is optional.
-    pub fn read_fragment(&mut self) -> Result<(FragmentHeader, Option<AckedPacketHeader>)> {
+    pub fn read_fragment(
+        &mut self,
+        window: AckWindow,
+    ) -> Result<(FragmentHeader, Option<AckedPacketHeader>)> {
         if self.can_read(FragmentHeader::size()) {
             let fragment_header = FragmentHeader::read(&mut self.cursor)?;
 
             let acked_header = if fragment_header.id() == 0 {
-                Some(AckedPacketHeader::read(&mut self.cursor)?)
+                Some(AckedPacketHeader::read_with_window(
+                    &mut self.cursor,
+                    window,
+                )?)
             } else {
                 None
             };
@@ -93,6 +101,36 @@ impl<'s> PacketReader<'s> {
         }
     }
 
+    /// Read the `ChunkHeader` of a chunk of a large message from the underlying buffer.
+    ///
+    /// # Remark
+    /// - Will change the position to the location of `ChunkHeader`, which comes after the standard header.
+    pub fn read_chunk_header(&mut self) -> Result<ChunkHeader> {
+        self.cursor.set_position(u64::from(STANDARD_HEADER_SIZE));
+
+        if self.can_read(ChunkHeader::size()) {
+            ChunkHeader::read(&mut self.cursor)
+        } else {
+            Err(ErrorKind::CouldNotReadHeader(String::from("chunk")))
+        }
+    }
+
+    /// Read the `ChunkAckHeader` of a chunk acknowledgment from the underlying buffer.
+    ///
+    /// # Remark
+    /// - Will change the position to the location of `ChunkAckHeader`, which comes after the standard header.
+    pub fn read_chunk_ack_header(&mut self) -> Result<ChunkAckHeader> {
+        self.cursor.set_position(u64::from(STANDARD_HEADER_SIZE));
+
+        if self.can_read(ChunkAckHeader::size()) {
+            ChunkAckHeader::read(&mut self.cursor)
+        } else {
+            Err(ErrorKind::CouldNotReadHeader(String::from(
+                "chunk acknowledgment",
+            )))
+        }
+    }
+
     /// Read the payload` from the underlying buffer.
     ///
     /// # Remark
@@ -113,7 +151,7 @@ impl<'s> PacketReader<'s> {
 
 #[cfg(test)]
 mod tests {
-    use crate::packet::header::{AckedPacketHeader, HeaderReader, StandardHeader};
+    use crate::packet::header::{AckWindow, AckedPacketHeader, HeaderReader, StandardHeader};
     use crate::packet::{DeliveryGuarantee, OrderingGuarantee, PacketReader, PacketType};
 
     #[test]
@@ -154,7 +192,9 @@ mod tests {
 
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
-        let acked_header = reader.read_acknowledge_header().unwrap();
+        let acked_header = reader
+            .read_acknowledge_header(AckWindow::default())
+            .unwrap();
 
         assert_eq!(acked_header.sequence(), 1);
         assert_eq!(acked_header.ack_seq(), 2);
@@ -174,7 +214,7 @@ mod tests {
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
         let standard_header = reader.read_standard_header().unwrap();
-        let (fragment_header, acked_header) = reader.read_fragment().unwrap();
+        let (fragment_header, acked_header) = reader.read_fragment(AckWindow::default()).unwrap();
 
         assert_eq!(standard_header.protocol_version(), 1);
         assert_eq!(standard_header.packet_type(), PacketType::Packet);
@@ -223,7 +263,9 @@ mod tests {
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
         let standard_header = reader.read_standard_header().unwrap();
-        let acked_header = reader.read_acknowledge_header().unwrap();
+        let acked_header = reader
+            .read_acknowledge_header(AckWindow::default())
+            .unwrap();
         let arranging_header = reader
             .read_arranging_header((StandardHeader::size() + AckedPacketHeader::size()) as u16)
             .unwrap();
@@ -255,7 +297,9 @@ mod tests {
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
         let standard_header = reader.read_standard_header().unwrap();
-        let acked_header = reader.read_acknowledge_header().unwrap();
+        let acked_header = reader
+            .read_acknowledge_header(AckWindow::default())
+            .unwrap();
 
         assert_eq!(standard_header.protocol_version(), 1);
         assert_eq!(standard_header.packet_type(), PacketType::Packet);
@@ -1,5 +1,21 @@
 use crate::packet::{DeliveryGuarantee, OrderingGuarantee};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Identifies a packet handed to the socket for sending.
+///
+/// The id is returned when the packet is sent and is reported again in the `SocketEvent::Acked` or
+/// `SocketEvent::Lost` event for that packet. Ids are unique per socket.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct PacketId(pub(crate) u64);
+
+impl PacketId {
+    /// Takes the next id from the given counter.
+    pub(crate) fn next(counter: &AtomicU64) -> PacketId {
+        PacketId(counter.fetch_add(1, Ordering::Relaxed))
+    }
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 /// This is a user friendly packet containing the payload, endpoint, and reliability guarantees.
@@ -7,8 +23,8 @@ use std::net::SocketAddr;
 ///
 /// | Reliability Type             | Packet Drop     | Packet Duplication | Packet Order     | Packet Fragmentation |Packet Delivery|
 /// | :-------------:              | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------:
-/// |   **Unreliable Unordered**   |       Any       |      Yes           |     No           |      No              |   No
-/// |   **Unreliable Sequenced**   |    Any + old    |      No            |     Sequenced    |      No              |   No
+/// |   **Unreliable Unordered**   |       Any       |      Yes           |     No           |      Yes             |   No
+/// |   **Unreliable Sequenced**   |    Any + old    |      No            |     Sequenced    |      Yes             |   No
 /// |   **Reliable Unordered**     |       No        |      No            |     No           |      Yes             |   Yes
 /// |   **Reliable Ordered**       |       No        |      No            |     Ordered      |      Yes             |   Yes
 /// |   **Reliable Sequenced**     |    Only old     |      No            |     Sequenced    |      Yes             |   Only newest
@@ -23,6 +39,10 @@ pub struct Packet {
     delivery: DeliveryGuarantee,
     /// defines on how the packet will be ordered.
     ordering: OrderingGuarantee,
+    /// defines how long the packet will be resent before it expires.
+    time_to_live: Option<Duration>,
+    /// defines whether the packet is streamed in acknowledged chunks.
+    large_message: bool,
 }
 
 impl Packet {
@@ -38,6 +58,8 @@ impl Packet {
             payload,
             delivery,
             ordering,
+            time_to_live: None,
+            large_message: false,
         }
     }
 
@@ -49,7 +71,7 @@ impl Packet {
     ///
     /// | Packet Drop     | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
     /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
-    /// |       Any       |        Yes         |      No          |      No              |       No        |
+    /// |       Any       |        Yes         |      No          |      Yes             |       No        |
     ///
     /// Basically just bare UDP. The packet may or may not be delivered.
     pub fn unreliable(addr: SocketAddr, payload: Vec<u8>) -> Packet {
@@ -58,6 +80,8 @@ impl Packet {
             payload: payload.into_boxed_slice(),
             delivery: DeliveryGuarantee::Unreliable,
             ordering: OrderingGuarantee::None,
+            time_to_live: None,
+            large_message: false,
         }
     }
 
@@ -69,7 +93,7 @@ impl Packet {
     ///
     /// | Packet Drop     | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
     /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
-    /// |    Any + old    |        No          |      Sequenced   |      No              |       No        |
+    /// |    Any + old    |        No          |      Sequenced   |      Yes             |       No        |
     ///
     /// Basically just bare UDP, free to be dropped, but has some sequencing to it so that only the newest packets are kept.
     pub fn unreliable_sequenced(
@@ -82,6 +106,8 @@ impl Packet {
             payload: payload.into_boxed_slice(),
             delivery: DeliveryGuarantee::Unreliable,
             ordering: OrderingGuarantee::Sequenced(stream_id),
+            time_to_live: None,
+            large_message: false,
         }
     }
 
@@ -101,6 +127,8 @@ impl Packet {
             payload: payload.into_boxed_slice(),
             delivery: DeliveryGuarantee::Reliable,
             ordering: OrderingGuarantee::None,
+            time_to_live: None,
+            large_message: false,
         }
     }
 
@@ -124,6 +152,8 @@ impl Packet {
             payload: payload.into_boxed_slice(),
             delivery: DeliveryGuarantee::Reliable,
             ordering: OrderingGuarantee::Ordered(stream_id),
+            time_to_live: None,
+            large_message: false,
         }
     }
 
@@ -148,9 +178,51 @@ impl Packet {
             payload: payload.into_boxed_slice(),
             delivery: DeliveryGuarantee::Reliable,
             ordering: OrderingGuarantee::Sequenced(stream_id),
+            time_to_live: None,
+            large_message: false,
+        }
+    }
+
+    /// Create a new large message by passing the receiver and data.
+    ///
+    /// Large message; The payload is streamed in chunks of `Config::fragment_size` bytes, so it is not limited to `Config::max_fragments` fragments.
+    /// Only the chunks which were not acknowledged are resent, and the receiver gets a `SocketEvent::Progress` while the message arrives.
+    ///
+    /// *Details*
+    ///
+    /// |   Packet Drop   | Packet Duplication | Packet Order     | Packet Fragmentation | Packet Delivery |
+    /// | :-------------: | :-------------:    | :-------------:  | :-------------:      | :-------------: |
+    /// |       No        |      No            |      Ordered     |      Yes             |       Yes       |
+    ///
+    /// # Remark
+    /// - Large messages to the same endpoint are sent one after another, in the order they were sent.
+    /// - The size of the message is limited by `Config::max_large_message_size`.
+    pub fn large_message(addr: SocketAddr, payload: Vec<u8>) -> Packet {
+        Packet {
+            addr,
+            payload: payload.into_boxed_slice(),
+            delivery: DeliveryGuarantee::Reliable,
+            ordering: OrderingGuarantee::None,
+            time_to_live: None,
+            large_message: true,
         }
     }
 
+    /// Sets the time after which laminar stops resending this packet if it still was not
+    /// acknowledged, and emits a `SocketEvent::Expired` for it instead.
+    ///
+    /// # Remark
+    /// - The time is counted from the moment the socket sends the packet for the first time.
+    /// - Only reliable unordered and sequenced packets expire.
+    /// - Unreliable packets are never resent.
+    /// - The time to live of ordered packets is ignored, so `time_to_live` returns `None` for them: an expired ordered packet would hold back all later packets of its stream.
+    pub fn with_ttl(mut self, time_to_live: Duration) -> Packet {
+        if !matches!(self.ordering, OrderingGuarantee::Ordered(_)) {
+            self.time_to_live = Some(time_to_live);
+        }
+        self
+    }
+
     /// Returns the payload of this packet.
     pub fn payload(&self) -> &[u8] {
         &self.payload
@@ -174,12 +246,23 @@ impl Packet {
     pub fn order_guarantee(&self) -> OrderingGuarantee {
         self.ordering
     }
+
+    /// Returns the time after which this packet is no longer resent, if any.
+    pub fn time_to_live(&self) -> Option<Duration> {
+        self.time_to_live
+    }
+
+    /// Returns whether this packet is streamed as a large message.
+    pub fn is_large_message(&self) -> bool {
+        self.large_message
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::packet::{DeliveryGuarantee, OrderingGuarantee, Packet};
     use std::net::SocketAddr;
+    use std::time::Duration;
 
     #[test]
     fn assure_creation_unreliable_packet() {
@@ -240,6 +323,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn assure_creation_with_ttl() {
+        let packet = Packet::reliable_unordered(test_addr(), test_payload());
+        assert_eq!(packet.time_to_live(), None);
+
+        let packet = packet.with_ttl(Duration::from_millis(200));
+        assert_eq!(packet.time_to_live(), Some(Duration::from_millis(200)));
+
+        let packet = Packet::reliable_ordered(test_addr(), test_payload(), None)
+            .with_ttl(Duration::from_millis(200));
+        assert_eq!(packet.time_to_live(), None);
+    }
+
+    #[test]
+    fn assure_creation_large_message() {
+        let packet = Packet::large_message(test_addr(), test_payload());
+
+        assert_eq!(packet.payload(), test_payload().as_slice());
+        assert_eq!(packet.delivery_guarantee(), DeliveryGuarantee::Reliable);
+        assert!(packet.is_large_message());
+        assert!(!Packet::reliable_unordered(test_addr(), test_payload()).is_large_message());
+    }
+
     fn test_payload() -> Vec<u8> {
         return "test".as_bytes().to_vec();
     }
@@ -39,6 +39,16 @@ impl<T: Clone + Default> SequenceBuffer<T> {
         None
     }
 
+    /// Returns an iterator over the stored entries along with their sequence numbers.
+    pub fn iter(&self) -> impl Iterator<Item = (SequenceNumber, &T)> {
+        self.entry_sequences
+            .iter()
+            .zip(self.entries.iter())
+            .filter_map(|(sequence_num, entry)| {
+                sequence_num.map(|sequence_num| (sequence_num, entry))
+            })
+    }
+
     /// Insert the entry data into the sequence buffer. If the requested sequence number is "too
     /// old", the entry will not be inserted and no reference will be returned.
     pub fn insert(&mut self, sequence_num: SequenceNumber, entry: T) -> Option<&mut T> {
@@ -226,6 +236,19 @@ mod tests {
         assert_eq!(count_entries(&buffer), 1);
     }
 
+    #[test]
+    fn iter_returns_stored_entries() {
+        let mut buffer = SequenceBuffer::with_capacity(4);
+        for i in 0..6 {
+            buffer.insert(i, DataStub);
+        }
+        buffer.remove(3);
+
+        let mut sequence_nums: Vec<SequenceNumber> = buffer.iter().map(|(s, _)| s).collect();
+        sequence_nums.sort();
+        assert_eq!(sequence_nums, vec![2, 4, 5]);
+    }
+
     fn count_entries(buffer: &SequenceBuffer<DataStub>) -> usize {
         let nums: Vec<&SequenceNumber> = buffer.entry_sequences.iter().flatten().collect();
         nums.len()
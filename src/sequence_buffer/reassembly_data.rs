@@ -1,36 +1,47 @@
-use crate::net::constants::MAX_FRAGMENTS_DEFAULT;
-use crate::packet::SequenceNumber;
+use crate::packet::header::{AckedPacketHeader, ArrangingHeader};
+use std::time::Instant;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 /// This contains the information required to reassemble fragments.
 pub struct ReassemblyData {
-    pub sequence: SequenceNumber,
     pub num_fragments_received: u8,
     pub num_fragments_total: u8,
-    pub buffer: Vec<u8>,
-    pub fragments_received: [bool; MAX_FRAGMENTS_DEFAULT as usize],
+    /// The payload of each fragment, in the slot of its fragment id.
+    pub fragments: Vec<Option<Box<[u8]>>>,
+    /// The acknowledgment header, which is sent along with the first fragment.
+    pub acked_header: Option<AckedPacketHeader>,
+    /// The arranging header of ordered and sequenced packets, which is sent along with the first fragment.
+    pub arranging_header: Option<ArrangingHeader>,
+    /// The number of payload bytes of the fragments which were received.
+    pub received_bytes: usize,
+    /// The time the first fragment was received.
+    pub started: Option<Instant>,
 }
 
 impl ReassemblyData {
-    pub fn new(sequence: SequenceNumber, num_fragments_total: u8, prealloc: usize) -> Self {
+    pub fn new(num_fragments_total: u8, started: Instant) -> Self {
         Self {
-            sequence,
             num_fragments_received: 0,
             num_fragments_total,
-            buffer: Vec::with_capacity(prealloc),
-            fragments_received: [false; MAX_FRAGMENTS_DEFAULT as usize],
+            fragments: vec![None; usize::from(num_fragments_total)],
+            acked_header: None,
+            arranging_header: None,
+            received_bytes: 0,
+            started: Some(started),
         }
     }
-}
 
-impl Default for ReassemblyData {
-    fn default() -> Self {
-        Self {
-            sequence: 0,
-            num_fragments_received: 0,
-            num_fragments_total: 0,
-            buffer: Vec::with_capacity(1024),
-            fragments_received: [false; MAX_FRAGMENTS_DEFAULT as usize],
-        }
+    /// Returns true once a fragment was received for every slot.
+    pub fn is_complete(&self) -> bool {
+        self.num_fragments_received == self.num_fragments_total
+    }
+
+    /// Concatenates the payloads of the fragments in the order of their ids.
+    pub fn assemble(&self) -> Vec<u8> {
+        self.fragments
+            .iter()
+            .flatten()
+            .flat_map(|fragment| fragment.iter().copied())
+            .collect()
     }
 }
@@ -0,0 +1,159 @@
+use laminar::{Config, NetworkConditions, NetworkSimulator, Packet, SocketEvent};
+use std::{net::SocketAddr, time::Duration};
+
+fn server_addr() -> SocketAddr {
+    "10.0.0.1:1000".parse().unwrap()
+}
+
+fn client_addr() -> SocketAddr {
+    "10.0.0.2:1000".parse().unwrap()
+}
+
+fn simulator(conditions: NetworkConditions) -> NetworkSimulator {
+    let mut simulator = NetworkSimulator::new(1234);
+    simulator.set_conditions(conditions);
+    simulator
+        .add_socket(server_addr(), Config::default())
+        .unwrap();
+    simulator
+        .add_socket(client_addr(), Config::default())
+        .unwrap();
+    simulator
+}
+
+fn received_payloads(simulator: &mut NetworkSimulator) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    while let Some(event) = simulator.socket(server_addr()).recv() {
+        if let SocketEvent::Packet(packet) = event {
+            payloads.push(packet.payload().to_vec());
+        }
+    }
+    payloads
+}
+
+#[test]
+fn reliable_ordered_packets_survive_loss_and_reordering() {
+    let mut simulator = simulator(NetworkConditions {
+        packet_loss: 0.2,
+        latency: Duration::from_millis(50),
+        jitter: Duration::from_millis(40),
+    });
+
+    for number in 0..100u8 {
+        simulator
+            .socket(client_addr())
+            .send(Packet::reliable_ordered(server_addr(), vec![number], None))
+            .unwrap();
+        simulator.advance(Duration::from_millis(10));
+    }
+    simulator.advance(Duration::from_secs(3));
+
+    let expected: Vec<_> = (0..100u8).map(|number| vec![number]).collect();
+    assert_eq!(received_payloads(&mut simulator), expected);
+}
+
+#[test]
+fn reliable_packets_arrive_despite_loss() {
+    let mut simulator = simulator(NetworkConditions {
+        packet_loss: 0.4,
+        latency: Duration::from_millis(30),
+        ..NetworkConditions::default()
+    });
+
+    for number in 0..50u8 {
+        simulator
+            .socket(client_addr())
+            .send(Packet::reliable_unordered(server_addr(), vec![number]))
+            .unwrap();
+        simulator.advance(Duration::from_millis(10));
+    }
+    simulator.advance(Duration::from_secs(3));
+
+    // Unordered packets may arrive in any order, but resent packets are delivered only once.
+    let mut payloads = received_payloads(&mut simulator);
+    payloads.sort();
+    let expected: Vec<_> = (0..50u8).map(|number| vec![number]).collect();
+    assert_eq!(payloads, expected);
+}
+
+#[test]
+fn reliable_packet_of_a_receive_only_peer_is_delivered_once() {
+    let mut simulator = simulator(NetworkConditions {
+        latency: Duration::from_millis(10),
+        ..NetworkConditions::default()
+    });
+
+    simulator
+        .socket(client_addr())
+        .send(Packet::reliable_unordered(server_addr(), vec![1]))
+        .unwrap();
+    simulator.advance(Duration::from_secs(4));
+
+    assert_eq!(received_payloads(&mut simulator), vec![vec![1]]);
+    let stats = simulator
+        .socket(client_addr())
+        .connection_stats(server_addr())
+        .unwrap();
+    assert_eq!(stats.packets_resent, 0);
+    assert_eq!(stats.packets_in_flight, 0);
+}
+
+#[test]
+fn one_way_traffic_on_a_perfect_link_loses_nothing() {
+    let mut simulator = simulator(NetworkConditions {
+        latency: Duration::from_millis(20),
+        ..NetworkConditions::default()
+    });
+
+    for number in 0..250u8 {
+        let packet = if number % 2 == 0 {
+            Packet::unreliable(server_addr(), vec![number])
+        } else {
+            Packet::reliable_unordered(server_addr(), vec![number])
+        };
+        simulator.socket(client_addr()).send(packet).unwrap();
+        simulator.advance(Duration::from_millis(20));
+    }
+    simulator.advance(Duration::from_secs(2));
+
+    assert_eq!(received_payloads(&mut simulator).len(), 250);
+    let stats = simulator
+        .socket(client_addr())
+        .connection_stats(server_addr())
+        .unwrap();
+    assert_eq!(stats.packet_loss, 0.);
+    assert_eq!(stats.packets_lost, 0);
+    assert_eq!(stats.packets_resent, 0);
+    assert_eq!(stats.packets_in_flight, 0);
+    while let Some(event) = simulator.socket(client_addr()).recv() {
+        assert!(
+            !matches!(event, SocketEvent::QualityChanged(..)),
+            "Unexpected event: {:?}",
+            event
+        );
+    }
+}
+
+#[test]
+fn silent_client_times_out() {
+    let mut simulator = simulator(NetworkConditions::default());
+
+    simulator
+        .socket(client_addr())
+        .send(Packet::unreliable(server_addr(), vec![1]))
+        .unwrap();
+    simulator.advance(Duration::from_millis(100));
+    assert_eq!(
+        simulator.socket(server_addr()).recv(),
+        Some(SocketEvent::Connect(client_addr(), None))
+    );
+    while simulator.socket(server_addr()).recv().is_some() {}
+
+    simulator.remove_socket(client_addr());
+    simulator.advance(Config::default().idle_connection_timeout);
+
+    assert_eq!(
+        simulator.socket(server_addr()).recv(),
+        Some(SocketEvent::Timeout(client_addr()))
+    );
+}